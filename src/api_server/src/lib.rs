@@ -20,7 +20,7 @@ use mmds::data_store;
 use mmds::data_store::Mmds;
 use seccomp::{BpfProgram, SeccompFilter};
 use utils::eventfd::EventFd;
-use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
+use vmm::rpc_interface::{ErrorCode, VmmAction, VmmActionError, VmmData};
 use vmm::vmm_config::instance_info::InstanceInfo;
 #[cfg(target_arch = "x86_64")]
 use vmm::vmm_config::snapshot::SnapshotType;
@@ -291,6 +291,13 @@ impl ApiServer {
     fn json_fault_message<T: AsRef<str> + serde::Serialize>(msg: T) -> String {
         json!({ "fault_message": msg }).to_string()
     }
+
+    fn json_fault_message_with_code<T: AsRef<str> + serde::Serialize>(
+        msg: T,
+        code: ErrorCode,
+    ) -> String {
+        json!({ "fault_message": msg, "error_code": code }).to_string()
+    }
 }
 
 #[cfg(test)]
@@ -392,6 +399,10 @@ mod tests {
                     snapshot_path: PathBuf::new(),
                     mem_file_path: PathBuf::new(),
                     version: None,
+                    sparse_mem_file: false,
+                    atomic_write: false,
+                    direct_io_mem_file: false,
+                    bandwidth_limit: None,
                 })),
                 start_time_us,
             );
@@ -406,6 +417,10 @@ mod tests {
                     snapshot_path: PathBuf::new(),
                     mem_file_path: PathBuf::new(),
                     version: None,
+                    sparse_mem_file: false,
+                    atomic_write: false,
+                    direct_io_mem_file: false,
+                    bandwidth_limit: None,
                 })),
                 start_time_us,
             );
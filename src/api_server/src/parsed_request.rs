@@ -18,7 +18,7 @@ use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
 use crate::request::net::{parse_patch_net, parse_put_net};
 use crate::request::snapshot::parse_patch_vm_state;
 #[cfg(target_arch = "x86_64")]
-use crate::request::snapshot::parse_put_snapshot;
+use crate::request::snapshot::{parse_get_snapshot, parse_put_snapshot};
 use crate::request::vsock::parse_put_vsock;
 use crate::ApiServer;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
@@ -61,6 +61,8 @@ impl ParsedRequest {
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.get(1)),
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            #[cfg(target_arch = "x86_64")]
+            (Method::Get, "snapshot", None) => parse_get_snapshot(path_tokens.get(1)),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
@@ -119,6 +121,12 @@ impl ParsedRequest {
                     response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
                     response
                 }
+                VmmData::SnapshotStatus(status) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(status).unwrap()));
+                    response
+                }
             },
             Err(vmm_action_error) => {
                 error!(
@@ -126,8 +134,9 @@ impl ParsedRequest {
                     vmm_action_error
                 );
                 let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
-                response.set_body(Body::new(ApiServer::json_fault_message(
+                response.set_body(Body::new(ApiServer::json_fault_message_with_code(
                     vmm_action_error.to_string(),
+                    vmm_action_error.error_code(),
                 )));
                 response
             }
@@ -556,7 +565,8 @@ pub(crate) mod tests {
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
         let mut buf = Cursor::new(vec![0]);
-        let json = ApiServer::json_fault_message(error.to_string());
+        let json = ApiServer::json_fault_message_with_code(error.to_string(), error.error_code());
+        assert!(json.contains("\"error_code\":\"start_microvm\""));
         let response = ParsedRequest::convert_to_response(&Err(error));
         response.write_all(&mut buf).unwrap();
 
@@ -884,6 +894,21 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_try_from_get_snapshot_status() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /snapshot/status HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req)
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::GetSnapshotStatus)));
+    }
+
     #[test]
     fn test_try_from_patch_vm() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -49,6 +49,7 @@ pub fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
         && vm_config.mem_size_mib.is_none()
         && vm_config.cpu_template.is_none()
         && vm_config.ht_enabled.is_none()
+        && vm_config.mem_backend.is_none()
     {
         return method_to_error(Method::Patch);
     }
@@ -73,8 +74,11 @@ fn check_unsupported_fields(_vm_config: &VmConfig) -> Result<(), Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::memory_backend::{MemoryBackendConfig, MemoryBackendType};
 
     #[test]
     fn test_parse_get_machine_config_request() {
@@ -120,6 +124,7 @@ mod tests {
             ht_enabled: Some(true),
             cpu_template: None,
             track_dirty_pages: true,
+            mem_backend: None,
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -145,6 +150,7 @@ mod tests {
                 ht_enabled: Some(true),
                 cpu_template: Some(CpuFeaturesTemplate::T2),
                 track_dirty_pages: true,
+                mem_backend: None,
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -157,6 +163,48 @@ mod tests {
         {
             assert!(parse_put_machine_config(&Body::new(body)).is_err());
         }
+
+        // 5. Test that a memory backend can be specified.
+        let body = r#"{
+                "vcpu_count": 8,
+                "mem_size_mib": 1024,
+                "ht_enabled": false,
+                "mem_backend": {
+                    "backend_type": "Hugetlbfs",
+                    "path": "/mnt/hugepages"
+                }
+              }"#;
+        let expected_config = VmConfig {
+            vcpu_count: Some(8),
+            mem_size_mib: Some(1024),
+            ht_enabled: Some(false),
+            cpu_template: None,
+            track_dirty_pages: false,
+            mem_backend: Some(MemoryBackendConfig {
+                backend_type: MemoryBackendType::Hugetlbfs,
+                path: Some(PathBuf::from("/mnt/hugepages")),
+                numa_node: None,
+                mlock: None,
+                populate: false,
+            }),
+        };
+
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::SetVmConfiguration(config) => assert_eq!(config, expected_config),
+            _ => panic!("Test failed."),
+        }
+
+        // 6. Test that unknown memory backend fields are rejected.
+        let body = r#"{
+                "vcpu_count": 8,
+                "mem_size_mib": 1024,
+                "ht_enabled": false,
+                "mem_backend": {
+                    "backend_type": "Memfd",
+                    "hugepages": true
+                }
+              }"#;
+        assert!(parse_put_machine_config(&Body::new(body)).is_err());
     }
 
     #[test]
@@ -10,6 +10,21 @@ use crate::request::{Method, StatusCode};
 use vmm::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams};
 use vmm::vmm_config::snapshot::{Vm, VmState};
 
+#[cfg(target_arch = "x86_64")]
+pub fn parse_get_snapshot(path_second_token: Option<&&str>) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"status") => Ok(ParsedRequest::new_sync(VmmAction::GetSnapshotStatus)),
+        Some(&unknown_path) => Err(Error::InvalidPathMethod(
+            format!("/snapshot/{}", unknown_path),
+            Method::Get,
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Missing snapshot resource.".to_string(),
+        )),
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 pub fn parse_put_snapshot(
     body: &Body,
@@ -70,6 +85,10 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: Some(String::from("0.23.0")),
+            sparse_mem_file: false,
+            atomic_write: false,
+            direct_io_mem_file: false,
+            bandwidth_limit: None,
         };
 
         match vmm_action_from_request(
@@ -89,6 +108,37 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: None,
+            sparse_mem_file: false,
+            atomic_write: false,
+            direct_io_mem_file: false,
+            bandwidth_limit: None,
+        };
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "sparse_mem_file": true,
+                "atomic_write": true,
+                "direct_io_mem_file": true,
+                "bandwidth_limit": 1048576
+              }"#;
+
+        expected_cfg = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            version: None,
+            sparse_mem_file: true,
+            atomic_write: true,
+            direct_io_mem_file: true,
+            bandwidth_limit: Some(1_048_576),
         };
 
         match vmm_action_from_request(
@@ -143,6 +193,18 @@ mod tests {
         assert!(parse_put_snapshot(&Body::new(body), None).is_err());
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_parse_get_snapshot() {
+        match vmm_action_from_request(parse_get_snapshot(Some(&"status")).unwrap()) {
+            VmmAction::GetSnapshotStatus => (),
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_get_snapshot(Some(&"invalid")).is_err());
+        assert!(parse_get_snapshot(None).is_err());
+    }
+
     #[test]
     fn test_parse_patch_vm_state() {
         let mut body = r#"{
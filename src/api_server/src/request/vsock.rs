@@ -25,6 +25,14 @@ mod tests {
               }"#;
         assert!(parse_put_vsock(&Body::new(body)).is_ok());
 
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "queue_size": 1024
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body)).is_ok());
+
         let body = r#"{
                 "vsock_id": "foo",
                 "guest_cid": 42,
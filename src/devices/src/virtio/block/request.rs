@@ -12,7 +12,7 @@ use std::result;
 
 use logger::{IncMetric, METRICS};
 use virtio_gen::virtio_blk::*;
-use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap};
+use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
 use super::super::DescriptorChain;
 use super::device::DiskProperties;
@@ -159,12 +159,9 @@ impl Request {
                 return Err(Error::UnexpectedReadOnlyDescriptor);
             }
 
-            // Check that the address of the data descriptor is valid in guest memory.
-            let _ = mem
-                .checked_offset(data_desc.addr, data_desc.len as usize)
-                .ok_or(Error::GuestMemory(GuestMemoryError::InvalidGuestAddress(
-                    data_desc.addr,
-                )))?;
+            // Check that the whole data buffer is valid in guest memory.
+            mem.check_range(data_desc.addr, data_desc.len as usize)
+                .map_err(Error::GuestMemory)?;
 
             req.data_addr = data_desc.addr;
             req.data_len = data_desc.len;
@@ -181,11 +178,8 @@ impl Request {
 
         // Check that the address of the status descriptor is valid in guest memory.
         // We will write an u32 status here after executing the request.
-        let _ = mem
-            .checked_offset(status_desc.addr, mem::size_of::<u32>())
-            .ok_or(Error::GuestMemory(GuestMemoryError::InvalidGuestAddress(
-                status_desc.addr,
-            )))?;
+        mem.check_range(status_desc.addr, mem::size_of::<u32>())
+            .map_err(Error::GuestMemory)?;
 
         req.status_addr = status_desc.addr;
 
@@ -254,7 +248,7 @@ mod tests {
 
     use crate::virtio::queue::tests::*;
     use crate::virtio::test_utils::VirtQueue;
-    use vm_memory::{Address, GuestAddress};
+    use vm_memory::{Address, GuestAddress, GuestMemory};
 
     #[test]
     fn test_read_request_header() {
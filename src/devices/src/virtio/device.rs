@@ -109,6 +109,26 @@ pub trait VirtioDevice: AsAny + Send {
     fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
         None
     }
+
+    /// Called before the device state is saved to a snapshot, e.g. to quiesce in-flight I/O.
+    fn pre_save(&mut self) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Called once the snapshot was created, whether that succeeded or not.
+    fn post_save(&mut self) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Called on a device restored from a snapshot, before it is attached to the MMIO bus.
+    fn pre_restore(&mut self) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Called once all the devices of the microVM were restored from a snapshot.
+    fn post_restore(&mut self) -> std::result::Result<(), String> {
+        Ok(())
+    }
 }
 
 impl std::fmt::Debug for dyn VirtioDevice {
@@ -44,6 +44,13 @@ pub struct QueueState {
     next_used: Wrapping<u16>,
 }
 
+impl QueueState {
+    /// Returns the maximal size in elements the device offered for the queue.
+    pub fn max_size(&self) -> u16 {
+        self.max_size
+    }
+}
+
 impl Persist<'_> for Queue {
     type State = QueueState;
     type ConstructorArgs = ();
@@ -75,6 +82,9 @@ impl Persist<'_> for Queue {
             used_ring: GuestAddress::new(state.used_ring),
             next_avail: state.next_avail,
             next_used: state.next_used,
+            // Set up by the device, according to its acked features.
+            uses_notif_suppression: false,
+            num_added: Wrapping(0),
         })
     }
 }
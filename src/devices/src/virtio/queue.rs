@@ -186,6 +186,12 @@ pub struct Queue {
 
     pub(crate) next_avail: Wrapping<u16>,
     pub(crate) next_used: Wrapping<u16>,
+
+    /// Whether the driver and the device suppress notifications through the `used_event` and
+    /// `avail_event` fields, as negotiated with `VIRTIO_RING_F_EVENT_IDX`.
+    pub(crate) uses_notif_suppression: bool,
+    /// The number of descriptor chains added to the used ring since the last `prepare_kick()`.
+    pub(crate) num_added: Wrapping<u16>,
 }
 
 impl Queue {
@@ -200,6 +206,8 @@ impl Queue {
             used_ring: GuestAddress(0),
             next_avail: Wrapping(0),
             next_used: Wrapping(0),
+            uses_notif_suppression: false,
+            num_added: Wrapping(0),
         }
     }
 
@@ -283,8 +291,11 @@ impl Queue {
     }
 
     /// Pop the first available descriptor chain from the avail ring.
+    ///
+    /// When notifications are suppressed and the avail ring is found empty, the driver is asked
+    /// to notify the device as soon as it makes a new descriptor chain available.
     pub fn pop<'a, 'b>(&'a mut self, mem: &'b GuestMemoryMmap) -> Option<DescriptorChain<'b>> {
-        if self.len(mem) == 0 {
+        if self.len(mem) == 0 && !(self.uses_notif_suppression && self.enable_notification(mem)) {
             return None;
         }
 
@@ -364,6 +375,7 @@ impl Queue {
             .map_err(QueueError::UsedRing)?;
 
         self.next_used += Wrapping(1);
+        self.num_added += Wrapping(1);
 
         // This fence ensures all descriptor writes are visible before the index update is.
         fence(Ordering::Release);
@@ -373,6 +385,49 @@ impl Queue {
             .map_err(QueueError::UsedRing)
     }
 
+    /// Checks whether the driver needs to be notified about the descriptor chains added to the
+    /// used ring since the previous call.
+    ///
+    /// Unless notifications are suppressed, the driver always needs to be notified. Otherwise, it
+    /// only does if one of these descriptor chains is the one it set `used_event` to, which lets
+    /// a whole batch of used descriptor chains be signaled with a single interrupt.
+    pub fn prepare_kick(&mut self, mem: &GuestMemoryMmap) -> bool {
+        let num_added = std::mem::replace(&mut self.num_added, Wrapping(0));
+        if !self.uses_notif_suppression {
+            return true;
+        }
+
+        // This fence ensures the used ring index update is visible before `used_event` is read.
+        fence(Ordering::SeqCst);
+
+        // `used_event` follows the `ring` of `struct virtq_avail`.
+        // `self.is_valid()` already performed all the bound checks on the avail ring.
+        let used_event_addr = self
+            .avail_ring
+            .unchecked_add(4 + 2 * u64::from(self.actual_size()));
+        let used_event = Wrapping(mem.read_obj::<u16>(used_event_addr).unwrap());
+
+        // The spec's `vring_need_event()`: `used_event` must be one of the `num_added` used ring
+        // indexes that preceded `self.next_used`.
+        self.next_used - used_event - Wrapping(1) < num_added
+    }
+
+    /// Asks the driver to notify the device once it makes the next descriptor chain available,
+    /// and returns whether the avail ring received descriptor chains in the meantime.
+    fn enable_notification(&mut self, mem: &GuestMemoryMmap) -> bool {
+        // `avail_event` follows the `ring` of `struct virtq_used`.
+        // `self.is_valid()` already performed all the bound checks on the used ring.
+        let avail_event_addr = self
+            .used_ring
+            .unchecked_add(4 + 8 * u64::from(self.actual_size()));
+        mem.write_obj(self.next_avail.0, avail_event_addr).unwrap();
+
+        // This fence ensures the driver sees `avail_event` before the avail ring is checked again,
+        // so that descriptor chains made available in between don't go unnoticed.
+        fence(Ordering::SeqCst);
+        !self.is_empty(mem)
+    }
+
     /// Fetch the available ring index (`virtq_avail->idx`) from guest memory.
     /// This is written by the driver, to indicate the next slot that will be filled in the avail
     /// ring.
@@ -609,6 +664,50 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_notif_suppression() {
+        let m = &GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), m, 16);
+        let mut q = vq.create_queue();
+
+        for j in 0..4 {
+            vq.dtable[j].set(0x1000 * (j + 1) as u64, 0x1000, 0, 0);
+            vq.avail.ring[j].set(j as u16);
+        }
+        vq.avail.idx.set(2);
+
+        // Without notification suppression, the driver is always notified.
+        q.add_used(m, 0, 0x1000).unwrap();
+        assert!(q.prepare_kick(m));
+        assert_eq!(q.num_added, Wrapping(0));
+
+        q.uses_notif_suppression = true;
+
+        // Finding the avail ring empty asks the driver for a notification on the next chain.
+        assert!(q.pop(m).is_some());
+        assert!(q.pop(m).is_some());
+        assert!(q.pop(m).is_none());
+        assert_eq!(vq.used.event.get(), 2);
+
+        // Chains made available by then are still popped.
+        vq.avail.idx.set(3);
+        assert!(q.pop(m).is_some());
+        vq.avail.idx.set(4);
+        assert!(q.pop(m).is_some());
+        assert!(q.pop(m).is_none());
+        assert_eq!(vq.used.event.get(), 4);
+
+        // The driver asked to be notified once the used ring index goes past 2.
+        vq.avail.event.set(2);
+        q.add_used(m, 1, 0x1000).unwrap();
+        assert!(!q.prepare_kick(m));
+        q.add_used(m, 2, 0x1000).unwrap();
+        q.add_used(m, 3, 0x1000).unwrap();
+        assert!(q.prepare_kick(m));
+        // The whole batch was signaled already.
+        assert!(!q.prepare_kick(m));
+    }
+
     #[test]
     fn test_queue_error_display() {
         let err = UsedRing(GuestMemoryError::InvalidGuestAddress(GuestAddress(0)));
@@ -78,6 +78,15 @@
 //          2. The receiver can be proactive, and send VSOCK_OP_CREDIT_UPDATE packet, whenever
 //             it thinks its peer's information is out of date.
 //          Our implementation uses the proactive approach.
+//
+// 4. SOCK_SEQPACKET connections
+//    If VIRTIO_VSOCK_F_SEQPACKET was negotiated, a connection can have the
+//    VSOCK_TYPE_SEQPACKET type. Data is still moved via VSOCK_OP_RW packets and subject to the
+//    same flow control, but message boundaries are preserved: a message may span several
+//    packets, and the last one carries the VSOCK_FLAGS_SEQ_EOM flag. Since the host-side
+//    socket is a SOCK_SEQPACKET socket too, messages are read and written whole, and only
+//    fragmented / reassembled at the vsock packet level.
+use std::collections::VecDeque;
 use std::io::{ErrorKind, Read, Write};
 use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
@@ -128,6 +137,16 @@ pub struct VsockConnection<S: Read + Write + AsRawFd> {
     /// Instant when this connection should be scheduled for immediate termination, due to some
     /// timeout condition having been fulfilled.
     expiry: Option<Instant>,
+    /// Whether this is a SOCK_SEQPACKET connection, preserving message boundaries.
+    seqpacket: bool,
+    /// The host-side message currently being fragmented into RX packets (seqpacket only).
+    rx_msg: Vec<u8>,
+    /// How much of `self.rx_msg` has already been sent to the peer.
+    rx_msg_offset: usize,
+    /// The guest-side message currently being reassembled from TX packets (seqpacket only).
+    tx_msg: Vec<u8>,
+    /// Complete guest-side messages waiting to be written to the host stream (seqpacket only).
+    tx_msgs: VecDeque<Vec<u8>>,
 }
 
 impl<S> VsockChannel for VsockConnection<S>
@@ -211,8 +230,15 @@ where
             let max_len = std::cmp::min(buf.len(), self.peer_avail_credit());
 
             // Read data from the stream straight to the RX buffer, for maximum throughput.
-            match self.stream.read(&mut buf[..max_len]) {
-                Ok(read_cnt) => {
+            // Seqpacket connections go through an intermediate message buffer instead, since
+            // a message can't be read in pieces from the host socket.
+            let read_result = if self.seqpacket {
+                self.read_msg_fragment(&mut buf[..max_len])
+            } else {
+                self.stream.read(&mut buf[..max_len]).map(|cnt| (cnt, true))
+            };
+            match read_result {
+                Ok((read_cnt, end_of_msg)) => {
                     if read_cnt == 0 {
                         // A 0-length read means the host stream was closed down. In that case,
                         // we'll ask our peer to shut down the connection. We can neither send nor
@@ -229,6 +255,15 @@ where
                         // length of the read data.
                         pkt.set_op(uapi::VSOCK_OP_RW).set_len(read_cnt as u32);
                         METRICS.vsock.rx_bytes_count.add(read_cnt);
+                        if self.seqpacket {
+                            if end_of_msg {
+                                pkt.set_flag(uapi::VSOCK_FLAGS_SEQ_EOM);
+                            } else {
+                                // The rest of the message is still waiting in our buffer,
+                                // and the host stream won't tell us about it.
+                                self.pending_rx.insert(PendingRx::Rw);
+                            }
+                        }
                     }
                     self.rx_cnt += Wrapping(pkt.len());
                     self.last_fwd_cnt_to_peer = self.fwd_cnt;
@@ -285,6 +320,12 @@ where
         self.peer_fwd_cnt = Wrapping(pkt.fwd_cnt());
         METRICS.vsock.tx_packets_count.inc();
 
+        // If we were waiting for credit to send the rest of a host-side message, we might be
+        // able to resume now.
+        if self.rx_msg_offset < self.rx_msg.len() && !self.need_credit_update_from_peer() {
+            self.pending_rx.insert(PendingRx::Rw);
+        }
+
         match self.state {
             // Most frequent case: this is an established connection that needs to forward some
             // data to the host stream. Also works for a connection that has begun shutting
@@ -302,15 +343,35 @@ where
 
                 // Unwrapping here is safe, since we just checked `pkt.buf()` above.
                 let buf_slice = &pkt.buf().unwrap()[..(pkt.len() as usize)];
-                if let Err(err) = self.send_bytes(buf_slice) {
-                    // If we can't write to the host stream, that's an unrecoverable error, so
-                    // we'll terminate this connection.
-                    warn!(
-                        "vsock: error writing to local stream (lp={}, pp={}): {:?}",
-                        self.local_port, self.peer_port, err
-                    );
-                    self.kill();
-                    return Ok(());
+                let send_result = if self.seqpacket {
+                    self.send_msg_fragment(buf_slice, pkt.flags() & uapi::VSOCK_FLAGS_SEQ_EOM != 0)
+                } else {
+                    self.send_bytes(buf_slice)
+                };
+                match send_result {
+                    Ok(()) => (),
+                    // Our TX buffer holds exactly the data the peer sent that wasn't yet
+                    // forwarded, so overflowing it means the peer ignored the credit we
+                    // advertised. Terminate the connection, rather than buffer more data.
+                    Err(Error::TxBufFull) => {
+                        METRICS.vsock.tx_credit_violations.inc();
+                        warn!(
+                            "vsock: peer exceeded its credit (lp={}, pp={})",
+                            self.local_port, self.peer_port
+                        );
+                        self.kill();
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        // If we can't write to the host stream, that's an unrecoverable error, so
+                        // we'll terminate this connection.
+                        warn!(
+                            "vsock: error writing to local stream (lp={}, pp={}): {:?}",
+                            self.local_port, self.peer_port, err
+                        );
+                        self.kill();
+                        return Ok(());
+                    }
                 }
 
                 // We might've just consumed some data. If that's the case, we might need to
@@ -338,7 +399,7 @@ where
                 let send_off = pkt.flags() & uapi::VSOCK_FLAGS_SHUTDOWN_SEND != 0;
                 self.state = ConnState::PeerClosed(recv_off, send_off);
                 if recv_off && send_off {
-                    if self.tx_buf.is_empty() {
+                    if !self.has_pending_tx() {
                         self.pending_rx.insert(PendingRx::Rst);
                     } else {
                         self.expiry = Some(
@@ -355,7 +416,7 @@ where
             {
                 *recv_off = *recv_off || (pkt.flags() & uapi::VSOCK_FLAGS_SHUTDOWN_RCV != 0);
                 *send_off = *send_off || (pkt.flags() & uapi::VSOCK_FLAGS_SHUTDOWN_SEND != 0);
-                if *recv_off && *send_off && self.tx_buf.is_empty() {
+                if *recv_off && *send_off && !self.has_pending_tx() {
                     self.pending_rx.insert(PendingRx::Rst);
                 }
             }
@@ -419,7 +480,7 @@ where
     /// - data can be written to the host stream, and the TX buffer needs to be flushed.
     fn get_polled_evset(&self) -> EventSet {
         let mut evset = EventSet::empty();
-        if !self.tx_buf.is_empty() {
+        if self.has_pending_tx() {
             // There's data waiting in the TX buffer, so we are interested in being notified
             // when writing to the host stream wouldn't block.
             evset.insert(EventSet::OUT);
@@ -445,35 +506,46 @@ where
         if evset.contains(EventSet::OUT) {
             // Data can be written to the host stream. Time to flush out the TX buffer.
             //
-            if self.tx_buf.is_empty() {
+            if !self.has_pending_tx() {
                 METRICS.vsock.conn_event_fails.inc();
                 info!("vsock: connection received unexpected EPOLLOUT event");
                 return;
             }
-            let flushed = self
-                .tx_buf
-                .flush_to(&mut self.stream)
-                .unwrap_or_else(|err| {
+            if self.seqpacket {
+                if let Err(err) = self.flush_tx_msgs() {
                     METRICS.vsock.tx_flush_fails.inc();
                     warn!(
-                        "vsock: error flushing TX buf for (lp={}, pp={}): {:?}",
+                        "vsock: error flushing TX messages for (lp={}, pp={}): {:?}",
                         self.local_port, self.peer_port, err
                     );
-                    match err {
-                        Error::TxBufFlush(inner) if inner.kind() == ErrorKind::WouldBlock => {
-                            // This should never happen (EWOULDBLOCK after EPOLLOUT), but
-                            // it does, so let's absorb it.
-                        }
-                        _ => self.kill(),
-                    };
-                    0
-                });
-            self.fwd_cnt += Wrapping(flushed as u32);
-            METRICS.vsock.tx_bytes_count.add(flushed as usize);
+                    self.kill();
+                }
+            } else {
+                let flushed = self
+                    .tx_buf
+                    .flush_to(&mut self.stream)
+                    .unwrap_or_else(|err| {
+                        METRICS.vsock.tx_flush_fails.inc();
+                        warn!(
+                            "vsock: error flushing TX buf for (lp={}, pp={}): {:?}",
+                            self.local_port, self.peer_port, err
+                        );
+                        match err {
+                            Error::TxBufFlush(inner) if inner.kind() == ErrorKind::WouldBlock => {
+                                // This should never happen (EWOULDBLOCK after EPOLLOUT), but
+                                // it does, so let's absorb it.
+                            }
+                            _ => self.kill(),
+                        };
+                        0
+                    });
+                self.fwd_cnt += Wrapping(flushed as u32);
+                METRICS.vsock.tx_bytes_count.add(flushed as usize);
+            }
 
             // If this connection was shutting down, but is waiting to drain the TX buffer
             // before forceful termination, the wait might be over.
-            if self.state == ConnState::PeerClosed(true, true) && self.tx_buf.is_empty() {
+            if self.state == ConnState::PeerClosed(true, true) && !self.has_pending_tx() {
                 self.pending_rx.insert(PendingRx::Rst);
             } else if self.peer_needs_credit_update() {
                 // If we've freed up some more buffer space, we may need to let the peer know it
@@ -512,6 +584,11 @@ where
             last_fwd_cnt_to_peer: Wrapping(0),
             pending_rx: PendingRxSet::from(PendingRx::Response),
             expiry: None,
+            seqpacket: false,
+            rx_msg: Vec::new(),
+            rx_msg_offset: 0,
+            tx_msg: Vec::new(),
+            tx_msgs: VecDeque::new(),
         }
     }
 
@@ -538,9 +615,22 @@ where
             last_fwd_cnt_to_peer: Wrapping(0),
             pending_rx: PendingRxSet::from(PendingRx::Request),
             expiry: None,
+            seqpacket: false,
+            rx_msg: Vec::new(),
+            rx_msg_offset: 0,
+            tx_msg: Vec::new(),
+            tx_msgs: VecDeque::new(),
         }
     }
 
+    /// Turn this into a SOCK_SEQPACKET connection, preserving message boundaries. The host
+    /// stream is expected to be a SOCK_SEQPACKET socket, so that each read / write moves
+    /// exactly one message.
+    pub fn with_seqpacket(mut self) -> Self {
+        self.seqpacket = true;
+        self
+    }
+
     /// Check if there is an expiry (kill) timer set for this connection, sometime in the
     /// future.
     pub fn will_expire(&self) -> bool {
@@ -571,6 +661,16 @@ where
         self.pending_rx.insert(PendingRx::Rst);
     }
 
+    /// Make a non-blocking attempt at flushing the TX buffer to the host stream, regardless of
+    /// whether an EPOLLOUT event has been received for it.
+    /// Returns `true` if the TX buffer is empty after the flush attempt.
+    pub fn flush_tx_buf(&mut self) -> bool {
+        if self.has_pending_tx() {
+            self.notify(EventSet::OUT);
+        }
+        !self.has_pending_tx()
+    }
+
     /// Return the connections state.
     pub fn state(&self) -> ConnState {
         self.state
@@ -627,6 +727,103 @@ where
         Ok(())
     }
 
+    /// Check if there is data waiting to be flushed to the host stream.
+    fn has_pending_tx(&self) -> bool {
+        !self.tx_buf.is_empty() || !self.tx_msgs.is_empty()
+    }
+
+    /// Fill `buf` with the next fragment of the current host-side message, reading a new
+    /// message from the host stream if the current one has been fully sent.
+    /// Returns the number of bytes copied, and whether the fragment ends the message. A
+    /// 0-length read means the host stream was closed down.
+    ///
+    /// Messages longer than `CONN_SEQPACKET_MAX_MSG_SIZE` fail with `EMSGSIZE`, since
+    /// delivering them truncated would break the message boundaries the guest relies on.
+    fn read_msg_fragment(&mut self, buf: &mut [u8]) -> std::io::Result<(usize, bool)> {
+        if self.rx_msg_offset >= self.rx_msg.len() {
+            // Leave room for one more byte than the largest message we accept: a SOCK_SEQPACKET
+            // read silently drops whatever doesn't fit, so filling up the buffer is the only way
+            // to tell that a message was too large.
+            self.rx_msg.resize(defs::CONN_SEQPACKET_MAX_MSG_SIZE + 1, 0);
+            self.rx_msg_offset = 0;
+            match self.stream.read(&mut self.rx_msg) {
+                Ok(read_cnt) if read_cnt > defs::CONN_SEQPACKET_MAX_MSG_SIZE => {
+                    self.rx_msg.clear();
+                    return Err(std::io::Error::from_raw_os_error(libc::EMSGSIZE));
+                }
+                Ok(read_cnt) => self.rx_msg.truncate(read_cnt),
+                Err(err) => {
+                    self.rx_msg.clear();
+                    return Err(err);
+                }
+            }
+            let read_cnt = self.rx_msg.len();
+            if read_cnt == 0 {
+                return Ok((0, true));
+            }
+        }
+
+        let len = std::cmp::min(buf.len(), self.rx_msg.len() - self.rx_msg_offset);
+        buf[..len].copy_from_slice(&self.rx_msg[self.rx_msg_offset..self.rx_msg_offset + len]);
+        self.rx_msg_offset += len;
+        Ok((len, self.rx_msg_offset == self.rx_msg.len()))
+    }
+
+    /// Absorb a fragment of a guest-side message, and write the message to the host stream
+    /// once it is complete.
+    ///
+    /// Messages that can't be written right away are queued, and flushed in order once the
+    /// host stream becomes writable. Both the partial message and the queue count against
+    /// the TX buffer space we advertised to the peer.
+    fn send_msg_fragment(&mut self, buf: &[u8], end_of_msg: bool) -> Result<()> {
+        let pending = self.tx_msg.len() + self.tx_msgs.iter().map(Vec::len).sum::<usize>();
+        if pending + buf.len() > defs::CONN_TX_BUF_SIZE as usize {
+            return Err(Error::TxBufFull);
+        }
+
+        self.tx_msg.extend_from_slice(buf);
+        if !end_of_msg {
+            return Ok(());
+        }
+
+        let msg = std::mem::replace(&mut self.tx_msg, Vec::new());
+        // Preserve message ordering: if older messages are still queued, this one has to
+        // wait its turn.
+        if !self.tx_msgs.is_empty() || !self.write_msg(&msg)? {
+            self.tx_msgs.push_back(msg);
+        }
+        Ok(())
+    }
+
+    /// Write the queued guest-side messages to the host stream, until it would block.
+    fn flush_tx_msgs(&mut self) -> Result<()> {
+        while let Some(msg) = self.tx_msgs.pop_front() {
+            if !self.write_msg(&msg)? {
+                self.tx_msgs.push_front(msg);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a whole message to the host stream.
+    /// Returns `false` if the write would block, in which case nothing was written. A
+    /// SOCK_SEQPACKET socket never performs partial writes.
+    fn write_msg(&mut self, msg: &[u8]) -> Result<bool> {
+        match self.stream.write(msg) {
+            Ok(_) => {
+                self.fwd_cnt += Wrapping(msg.len() as u32);
+                METRICS.vsock.tx_bytes_count.add(msg.len());
+                Ok(true)
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => {
+                METRICS.vsock.tx_write_fails.inc();
+                Err(Error::StreamWrite(err))
+            }
+        }
+    }
+
     /// Check if the credit information the peer has last received from us is outdated.
     fn peer_needs_credit_update(&self) -> bool {
         let peer_seen_free_buf =
@@ -659,7 +856,11 @@ where
             .set_dst_cid(self.peer_cid)
             .set_src_port(self.local_port)
             .set_dst_port(self.peer_port)
-            .set_type(uapi::VSOCK_TYPE_STREAM)
+            .set_type(if self.seqpacket {
+                uapi::VSOCK_TYPE_SEQPACKET
+            } else {
+                uapi::VSOCK_TYPE_STREAM
+            })
             .set_buf_alloc(defs::CONN_TX_BUF_SIZE)
             .set_fwd_cnt(self.fwd_cnt.0)
     }
@@ -1226,12 +1427,110 @@ mod tests {
         }
 
         // Then try to send more data.
+        let credit_violations = METRICS.vsock.tx_credit_violations.count();
         ctx.send();
 
         // The connection should've committed suicide.
         assert_eq!(ctx.conn.state, ConnState::Killed);
+        assert!(METRICS.vsock.tx_credit_violations.count() > credit_violations);
+        assert!(ctx.conn.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+    }
+
+    #[test]
+    fn test_seqpacket_rx() {
+        let mut ctx = CsmTestContext::new_established();
+        ctx.conn.seqpacket = true;
+
+        // A host-side message larger than the RX buffer should be split across packets, with
+        // only the last one marking the end of the message.
+        let buf_len = ctx.pkt.buf().unwrap().len();
+        let data: Vec<u8> = (0..buf_len + 16).map(|i| i as u8).collect();
+        ctx.set_stream(TestStream::new_with_read_buf(&data));
+        ctx.notify_epollin();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.type_(), uapi::VSOCK_TYPE_SEQPACKET);
+        assert_eq!(ctx.pkt.len() as usize, buf_len);
+        assert_eq!(ctx.pkt.flags() & uapi::VSOCK_FLAGS_SEQ_EOM, 0);
+        assert_eq!(ctx.pkt.buf().unwrap()[..buf_len], data[..buf_len]);
+
+        // The rest of the message should be pending, even though the stream is now empty.
+        assert!(ctx.conn.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.len(), 16);
+        assert_ne!(ctx.pkt.flags() & uapi::VSOCK_FLAGS_SEQ_EOM, 0);
+        assert_eq!(ctx.pkt.buf().unwrap()[..16], data[buf_len..]);
+        assert!(!ctx.conn.has_pending_rx());
+
+        // Running out of credit mid-message should stall the message until the peer sends a
+        // credit update.
+        ctx.set_stream(TestStream::new_with_read_buf(&data[..32]));
+        ctx.set_peer_credit(8);
+        ctx.notify_epollin();
+        ctx.recv();
+        assert_eq!(ctx.pkt.len(), 8);
+        assert_eq!(ctx.pkt.flags() & uapi::VSOCK_FLAGS_SEQ_EOM, 0);
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_CREDIT_REQUEST);
+        assert!(!ctx.conn.has_pending_rx());
+        let rx_cnt = ctx.conn.rx_cnt.0;
+        ctx.init_pkt(uapi::VSOCK_OP_CREDIT_UPDATE, 0)
+            .set_fwd_cnt(rx_cnt);
+        ctx.send();
         assert!(ctx.conn.has_pending_rx());
         ctx.recv();
+        assert_eq!(ctx.pkt.len(), 24);
+        assert_ne!(ctx.pkt.flags() & uapi::VSOCK_FLAGS_SEQ_EOM, 0);
+        assert_eq!(ctx.pkt.buf().unwrap()[..24], data[8..32]);
+
+        // A message too large to be read whole resets the connection, instead of reaching the
+        // guest truncated.
+        let mut ctx = CsmTestContext::new_established();
+        ctx.conn.seqpacket = true;
+        let data = vec![0u8; defs::CONN_SEQPACKET_MAX_MSG_SIZE + 1];
+        ctx.set_stream(TestStream::new_with_read_buf(&data));
+        ctx.notify_epollin();
+        ctx.recv();
         assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
     }
+
+    #[test]
+    fn test_seqpacket_tx() {
+        let mut ctx = CsmTestContext::new_established();
+        ctx.conn.seqpacket = true;
+
+        // Fragments should only be written to the host stream once the message is complete.
+        ctx.init_data_pkt(&[1, 2, 3, 4]);
+        ctx.send();
+        assert!(ctx.conn.stream.write_buf.is_empty());
+        ctx.init_data_pkt(&[5, 6]);
+        ctx.pkt.set_flag(uapi::VSOCK_FLAGS_SEQ_EOM);
+        ctx.send();
+        assert_eq!(ctx.conn.stream.write_buf, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(ctx.conn.fwd_cnt, Wrapping(6));
+
+        // Complete messages that can't be written right away should be queued, and flushed
+        // in order when the host stream becomes writable.
+        let mut stream = TestStream::new();
+        stream.write_state = StreamState::WouldBlock;
+        ctx.set_stream(stream);
+        ctx.init_data_pkt(&[7, 8]);
+        ctx.pkt.set_flag(uapi::VSOCK_FLAGS_SEQ_EOM);
+        ctx.send();
+        ctx.init_data_pkt(&[9]);
+        ctx.pkt.set_flag(uapi::VSOCK_FLAGS_SEQ_EOM);
+        ctx.send();
+        assert_eq!(ctx.conn.tx_msgs.len(), 2);
+        assert!(ctx.conn.get_polled_evset().contains(EventSet::OUT));
+
+        ctx.set_stream(TestStream::new());
+        ctx.notify_epollout();
+        assert!(ctx.conn.tx_msgs.is_empty());
+        assert!(!ctx.conn.get_polled_evset().contains(EventSet::OUT));
+        assert_eq!(ctx.conn.stream.write_buf, vec![7, 8, 9]);
+        assert_eq!(ctx.conn.fwd_cnt, Wrapping(9));
+    }
 }
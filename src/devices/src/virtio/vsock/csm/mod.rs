@@ -21,6 +21,10 @@ pub mod defs {
 
     /// Connection graceful shutdown timeout, in millis.
     pub const CONN_SHUTDOWN_TIMEOUT_MS: u64 = 2000;
+
+    /// Largest message that can be read from a SOCK_SEQPACKET host socket. Longer messages
+    /// are truncated by the host kernel.
+    pub const CONN_SEQPACKET_MAX_MSG_SIZE: usize = 64 * 1024;
 }
 
 #[derive(Debug)]
@@ -13,7 +13,13 @@ use std::result;
 /// https://docs.oasis-open.org/virtio/virtio/v1.1/virtio-v1.1.html
 ///
 /// The vsock device has two input parameters: a CID to identify the device, and a `VsockBackend`
-/// to use for offloading vsock traffic.
+/// to use for offloading vsock traffic. The size of its virtio queues can optionally be set as
+/// well, trading guest memory for throughput.
+///
+/// Besides the spec-defined guest CID, the device config space exposes the buffer sizing:
+/// - offset 0: the guest CID (le64);
+/// - offset 8: the RX queue size selected by the driver, i.e. the number of RX buffers (le32);
+/// - offset 12: the max vsock packet buffer size the device handles, in bytes (le32).
 ///
 /// Upon its activation, the vsock device registers handlers for the following events/FDs:
 /// - an RX queue FD;
@@ -26,7 +32,7 @@ use std::sync::Arc;
 use logger::{debug, error, warn, IncMetric, METRICS};
 use utils::byte_order;
 use utils::eventfd::EventFd;
-use vm_memory::GuestMemoryMmap;
+use vm_memory::{Bytes, GuestMemoryMmap};
 
 use super::super::super::Error as DeviceError;
 use super::super::{
@@ -45,8 +51,21 @@ pub(crate) const EVQ_INDEX: usize = 2;
 /// - VIRTIO_F_VERSION_1: the device conforms to at least version 1.0 of the VirtIO spec.
 /// - VIRTIO_F_IN_ORDER: the device returns used buffers in the same order that the driver makes
 ///   them available.
-pub(crate) const AVAIL_FEATURES: u64 =
-    1 << uapi::VIRTIO_F_VERSION_1 as u64 | 1 << uapi::VIRTIO_F_IN_ORDER as u64;
+/// - VIRTIO_VSOCK_F_SEQPACKET: the device supports SOCK_SEQPACKET connections.
+/// - VIRTIO_RING_F_EVENT_IDX: the device only interrupts the driver when the driver asked to be
+///   notified about the used buffers.
+pub(crate) const AVAIL_FEATURES: u64 = 1 << uapi::VIRTIO_F_VERSION_1 as u64
+    | 1 << uapi::VIRTIO_F_IN_ORDER as u64
+    | 1 << uapi::VIRTIO_VSOCK_F_SEQPACKET as u64
+    | 1 << uapi::VIRTIO_RING_F_EVENT_IDX as u64;
+
+/// Checks that `queue_size` is a valid virtio queue size.
+pub fn validate_queue_size(queue_size: u16) -> super::Result<()> {
+    if queue_size == 0 || queue_size > defs::MAX_QUEUE_SIZE || !queue_size.is_power_of_two() {
+        return Err(VsockError::InvalidQueueSize(queue_size));
+    }
+    Ok(())
+}
 
 pub struct Vsock<B> {
     cid: u64,
@@ -101,9 +120,15 @@ where
 
     /// Create a new virtio-vsock device with the given VM CID and vsock backend.
     pub fn new(cid: u64, backend: B) -> super::Result<Vsock<B>> {
-        let queues: Vec<VirtQueue> = defs::QUEUE_SIZES
-            .iter()
-            .map(|&max_size| VirtQueue::new(max_size))
+        Self::with_queue_size(cid, backend, defs::QUEUE_SIZE)
+    }
+
+    /// Create a new virtio-vsock device with the given VM CID and vsock backend, whose queues
+    /// hold up to `queue_size` descriptor chain heads.
+    pub fn with_queue_size(cid: u64, backend: B, queue_size: u16) -> super::Result<Vsock<B>> {
+        validate_queue_size(queue_size)?;
+        let queues: Vec<VirtQueue> = (0..defs::NUM_QUEUES)
+            .map(|_| VirtQueue::new(queue_size))
             .collect();
         Self::with_queues(cid, backend, queues)
     }
@@ -120,6 +145,11 @@ where
         &self.backend
     }
 
+    /// The size of the device's queues, be it configured or restored from a snapshot.
+    pub fn queue_size(&self) -> u16 {
+        self.queues[0].get_max_size()
+    }
+
     /// Signal the guest driver that we've used some virtio buffers that it had previously made
     /// available.
     pub fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -132,9 +162,24 @@ where
         })
     }
 
+    /// Set up the queues to suppress notifications, if the driver acked `VIRTIO_RING_F_EVENT_IDX`.
+    pub(crate) fn setup_notif_suppression(&mut self) {
+        let enabled = self.acked_features & (1 << uapi::VIRTIO_RING_F_EVENT_IDX as u64) != 0;
+        for queue in self.queues.iter_mut() {
+            queue.uses_notif_suppression = enabled;
+        }
+    }
+
+    /// Let the backend accept SOCK_SEQPACKET traffic, if the driver acked
+    /// `VIRTIO_VSOCK_F_SEQPACKET`.
+    pub(crate) fn setup_seqpacket(&mut self) {
+        let enabled = self.acked_features & (1 << uapi::VIRTIO_VSOCK_F_SEQPACKET as u64) != 0;
+        self.backend.set_seqpacket(enabled);
+    }
+
     /// Walk the driver-provided RX queue buffers and attempt to fill them up with any data that we
-    /// have pending. Return `true` if descriptors have been added to the used ring, and `false`
-    /// otherwise.
+    /// have pending. Return `true` if descriptors have been added to the used ring and the driver
+    /// needs to be notified about them, and `false` otherwise.
     pub fn process_rx(&mut self) -> bool {
         debug!("vsock: process_rx()");
         let mem = match self.device_state {
@@ -171,12 +216,13 @@ where
                 });
         }
 
-        have_used
+        // The whole batch of used RX descriptors is signaled at most once.
+        have_used && self.queues[RXQ_INDEX].prepare_kick(mem)
     }
 
     /// Walk the driver-provided TX queue buffers, package them up as vsock packets, and send them
     /// to the backend for processing. Return `true` if descriptors have been added to the used
-    /// ring, and `false` otherwise.
+    /// ring and the driver needs to be notified about them, and `false` otherwise.
     pub fn process_tx(&mut self) -> bool {
         debug!("vsock::process_tx()");
         let mem = match self.device_state {
@@ -215,7 +261,53 @@ where
                 });
         }
 
-        have_used
+        have_used && self.queues[TXQ_INDEX].prepare_kick(mem)
+    }
+
+    /// Quiesce the device ahead of its state being saved.
+    ///
+    /// TX packets that the driver has already made available are handed over to the backend,
+    /// which is then asked to drain, so that no in-flight data is left half-processed. Failing to
+    /// notify the driver of the handed over packets fails the save.
+    pub fn prepare_save(&mut self) -> super::Result<()> {
+        if let DeviceState::Activated(_) = self.device_state {
+            if self.process_tx() {
+                // Fail the save rather than leave the driver unaware of the used TX buffers.
+                if let Err(DeviceError::FailedSignalingUsedQueue(e)) = self.signal_used_queue() {
+                    return Err(VsockError::EventFd(e));
+                }
+            }
+        }
+        self.backend.drain()
+    }
+
+    /// Send a `VIRTIO_VSOCK_EVENT_TRANSPORT_RESET` event to the guest driver, letting it know
+    /// that all the connections it had established have been dropped. This is needed after
+    /// a snapshot restore, since connection state is not persisted.
+    pub fn send_transport_reset_event(&mut self) -> super::Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // An inactive device has no driver to notify.
+            DeviceState::Inactive => return Ok(()),
+        };
+
+        let head = self.queues[EVQ_INDEX].pop(mem).ok_or_else(|| {
+            METRICS.vsock.ev_queue_event_fails.inc();
+            VsockError::EvqEmpty
+        })?;
+
+        mem.write_obj::<u32>(uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET, head.addr)
+            .map_err(VsockError::GuestMemoryMmap)?;
+
+        self.queues[EVQ_INDEX]
+            .add_used(mem, head.index, head.len)
+            .unwrap_or_else(|e| {
+                error!("Failed to add available descriptor {}: {}", head.index, e);
+            });
+
+        self.signal_used_queue().unwrap_or_default();
+
+        Ok(())
     }
 }
 
@@ -268,6 +360,10 @@ where
             4 if data.len() == 4 => {
                 byte_order::write_le_u32(data, ((self.cid() >> 32) & 0xffff_ffff) as u32)
             }
+            8 if data.len() == 4 => {
+                byte_order::write_le_u32(data, u32::from(self.queues[RXQ_INDEX].actual_size()))
+            }
+            12 if data.len() == 4 => byte_order::write_le_u32(data, defs::MAX_PKT_BUF_SIZE as u32),
             _ => {
                 METRICS.vsock.cfg_fails.inc();
                 warn!(
@@ -305,6 +401,8 @@ where
             return Err(ActivateError::BadActivate);
         }
 
+        self.setup_notif_suppression();
+        self.setup_seqpacket();
         self.device_state = DeviceState::Activated(mem);
 
         Ok(())
@@ -316,6 +414,42 @@ where
             DeviceState::Activated(_) => true,
         }
     }
+
+    fn reset(&mut self) -> Option<(EventFd, Vec<EventFd>)> {
+        // The original event fds stay with the device, since they remain registered with KVM and
+        // the event manager for when the driver re-initializes the device.
+        let interrupt_evt = self.interrupt_evt.try_clone().ok()?;
+        let queue_evts = self
+            .queue_events
+            .iter()
+            .map(EventFd::try_clone)
+            .collect::<std::io::Result<Vec<_>>>()
+            .ok()?;
+
+        self.backend.reset();
+        for queue in self.queues.iter_mut() {
+            *queue = VirtQueue::new(queue.get_max_size());
+        }
+        self.acked_features = 0;
+        self.interrupt_status.store(0, Ordering::SeqCst);
+        self.device_state = DeviceState::Inactive;
+
+        Some((interrupt_evt, queue_evts))
+    }
+
+    fn pre_save(&mut self) -> result::Result<(), String> {
+        self.prepare_save().map_err(|err| format!("{:?}", err))
+    }
+
+    fn post_restore(&mut self) -> result::Result<(), String> {
+        // Connections are not persisted through snapshots, so the guest driver needs to be
+        // told to drop the ones it had established. Failing to do so doesn't prevent the
+        // device from working, but leaves the guest with stale connections.
+        if let Err(err) = self.send_transport_reset_event() {
+            warn!("Failed to send vsock transport reset event: {:?}", err);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -323,6 +457,8 @@ mod tests {
     use super::*;
     use crate::virtio::vsock::defs::uapi;
     use crate::virtio::vsock::test_utils::TestContext;
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::GuestAddress;
 
     #[test]
     fn test_virtio_device() {
@@ -372,6 +508,19 @@ mod tests {
         ctx.device.read_config(0, &mut data);
         assert_eq!(byte_order::read_le_u64(&data), ctx.cid);
 
+        // Test reading the buffer sizing.
+        let mut data = [0u8; 4];
+        ctx.device.read_config(8, &mut data);
+        assert_eq!(byte_order::read_le_u32(&data), 0);
+        ctx.device.queues[RXQ_INDEX].size = 128;
+        ctx.device.read_config(8, &mut data);
+        assert_eq!(byte_order::read_le_u32(&data), 128);
+        ctx.device.read_config(12, &mut data);
+        assert_eq!(
+            byte_order::read_le_u32(&data),
+            defs::MAX_PKT_BUF_SIZE as u32
+        );
+
         // Check that out-of-bounds reading doesn't mutate the destination buffer.
         let mut data = [0u8, 1, 2, 3, 4, 5, 6, 7];
         ctx.device.read_config(2, &mut data);
@@ -392,5 +541,157 @@ mod tests {
 
         // Test a correct activation.
         ctx.device.activate(ctx.mem.clone()).unwrap();
+        assert!(ctx.device.backend().seqpacket);
+    }
+
+    #[test]
+    fn test_prepare_save() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+
+        // An inactive device only drains its backend.
+        ctx.device.prepare_save().unwrap();
+        assert_eq!(ctx.device.backend.drain_cnt, 1);
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 0);
+
+        // An active device hands over any available TX packets before draining the backend.
+        ctx.mock_activate(test_ctx.mem.clone());
+        ctx.device.prepare_save().unwrap();
+        assert_eq!(ctx.device.backend.drain_cnt, 2);
+        assert_eq!(ctx.device.backend.tx_ok_cnt, 1);
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
+
+        // Failing to signal the used TX buffers fails the save.
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.mock_activate(test_ctx.mem.clone());
+        ctx.device.interrupt_evt.write(std::u64::MAX - 1).unwrap();
+        match ctx.device.prepare_save() {
+            Err(VsockError::EventFd(_)) => (),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(ctx.device.backend.drain_cnt, 0);
+    }
+
+    #[test]
+    fn test_notif_suppression() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.device.set_acked_features(AVAIL_FEATURES);
+        ctx.mock_activate(test_ctx.mem.clone());
+        assert!(ctx
+            .device
+            .queues
+            .iter()
+            .all(|queue| queue.uses_notif_suppression));
+
+        // The driver didn't move `used_event` past the first used TX buffer.
+        assert!(ctx.device.process_tx());
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
+        // Having emptied the TX queue, the device asked to be notified about the next buffer.
+        assert_eq!(ctx.guest_txvq.used.event.get(), 1);
+
+        // Used buffers the driver didn't ask to hear about don't need an interrupt.
+        ctx.guest_txvq.avail.event.set(2);
+        ctx.guest_txvq.avail.ring[1].set(0);
+        ctx.guest_txvq.avail.idx.set(2);
+        assert!(!ctx.device.process_tx());
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 2);
+        assert_eq!(ctx.device.backend.tx_ok_cnt, 2);
+
+        // The feature is only used when the driver acked it.
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.device
+            .set_acked_features(AVAIL_FEATURES & !(1 << uapi::VIRTIO_RING_F_EVENT_IDX as u64));
+        ctx.mock_activate(test_ctx.mem.clone());
+        assert!(ctx
+            .device
+            .queues
+            .iter()
+            .all(|queue| !queue.uses_notif_suppression));
+    }
+
+    #[test]
+    fn test_reset() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.device.set_acked_features(AVAIL_FEATURES);
+        ctx.mock_activate(test_ctx.mem.clone());
+        ctx.device.backend.set_pending_rx(true);
+        ctx.signal_txq_event();
+        ctx.device.signal_used_queue().unwrap();
+
+        let (interrupt_evt, queue_evts) = ctx.device.reset().unwrap();
+        assert!(!ctx.device.is_activated());
+        assert_eq!(ctx.device.backend.reset_cnt, 1);
+        assert!(!ctx.device.backend.has_pending_rx());
+        assert_eq!(ctx.device.acked_features, 0);
+        assert_eq!(ctx.device.interrupt_status.load(Ordering::SeqCst), 0);
+        for queue in ctx.device.queues.iter() {
+            assert!(!queue.ready);
+            assert_eq!(queue.next_avail.0, 0);
+        }
+        // The returned event fds are duplicates of the ones kept by the device.
+        assert_eq!(queue_evts.len(), defs::NUM_QUEUES);
+        interrupt_evt.write(1).unwrap();
+        assert_eq!(ctx.device.interrupt_evt.read().unwrap(), 2);
+
+        // The driver can bring the device back up.
+        ctx.device.activate(test_ctx.mem.clone()).unwrap();
+        assert!(ctx.device.is_activated());
+    }
+
+    #[test]
+    fn test_send_transport_reset_event() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+
+        // Inactive devices have no driver to notify.
+        ctx.device.send_transport_reset_event().unwrap();
+
+        // No available event queue descriptors.
+        ctx.mock_activate(test_ctx.mem.clone());
+        match ctx.device.send_transport_reset_event() {
+            Err(VsockError::EvqEmpty) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // Make one event queue descriptor available and pre-fill it with garbage.
+        let event_addr = GuestAddress(0x0060_0000);
+        test_ctx
+            .mem
+            .write_obj::<u32>(0xdead_beef, event_addr)
+            .unwrap();
+        ctx.guest_evvq.dtable[0].set(event_addr.0, 4, VIRTQ_DESC_F_WRITE, 0);
+        ctx.guest_evvq.avail.ring[0].set(0);
+        ctx.guest_evvq.avail.idx.set(1);
+
+        ctx.device.send_transport_reset_event().unwrap();
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 1);
+        assert_eq!(
+            test_ctx.mem.read_obj::<u32>(event_addr).unwrap(),
+            uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET
+        );
+    }
+
+    #[test]
+    fn test_queue_size() {
+        use crate::virtio::vsock::test_utils::TestBackend;
+
+        let vsock = Vsock::with_queue_size(3, TestBackend::new(), 1024).unwrap();
+        assert_eq!(vsock.queues.len(), defs::NUM_QUEUES);
+        assert!(vsock.queues.iter().all(|q| q.get_max_size() == 1024));
+        assert!(Vsock::new(3, TestBackend::new())
+            .unwrap()
+            .queues
+            .iter()
+            .all(|q| q.get_max_size() == defs::QUEUE_SIZE));
+
+        for &size in [0, 100, 65535].iter() {
+            match Vsock::with_queue_size(3, TestBackend::new(), size) {
+                Err(VsockError::InvalidQueueSize(s)) => assert_eq!(s, size),
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+        }
     }
 }
@@ -147,6 +147,42 @@ where
             error!("Failed to unregister vsock activate evt: {:?}", e);
         });
     }
+
+    // Queue and backend events can only fire on an inactive device after the driver has reset
+    // it. Stop listening for them and wait for the driver to activate the device again.
+    fn handle_event_after_reset(&self, source: i32, event_manager: &mut EventManager) {
+        debug!("vsock: event after device reset");
+        let self_subscriber = match event_manager.subscriber(source) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process vsock event after reset: {:?}", e);
+                return;
+            }
+        };
+
+        let runtime_fds = [
+            self.queue_events[RXQ_INDEX].as_raw_fd(),
+            self.queue_events[TXQ_INDEX].as_raw_fd(),
+            self.queue_events[EVQ_INDEX].as_raw_fd(),
+            self.backend.as_raw_fd(),
+        ];
+        for fd in runtime_fds.iter() {
+            event_manager.unregister(*fd).unwrap_or_else(|e| {
+                error!("Failed to unregister vsock event: {:?}", e);
+            });
+        }
+
+        let activate_fd = self.activate_evt.as_raw_fd();
+        event_manager
+            .register(
+                activate_fd,
+                EpollEvent::new(EventSet::IN, activate_fd as u64),
+                self_subscriber,
+            )
+            .unwrap_or_else(|e| {
+                error!("Failed to register vsock activate evt: {:?}", e);
+            });
+    }
 }
 
 impl<B> Subscriber for Vsock<B>
@@ -178,11 +214,13 @@ where
             if raise_irq {
                 self.signal_used_queue().unwrap_or_default();
             }
+        } else if source == activate_evt {
+            // Activation requests that were not handled before the driver reset the device.
+            if let Err(e) = self.activate_evt.read() {
+                error!("Failed to consume vsock activate event: {:?}", e);
+            }
         } else {
-            warn!(
-                "Vsock: The device is not yet activated. Spurious event received: {:?}",
-                source
-            );
+            self.handle_event_after_reset(source, event_manager);
         }
     }
 
@@ -343,6 +381,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_backend_stall() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.mock_activate(test_ctx.mem.clone());
+
+        // A stalled backend shouldn't consume any buffers.
+        ctx.device.backend.set_stalled(true);
+        ctx.device.backend.set_pending_rx(true);
+        ctx.signal_txq_event();
+        ctx.signal_rxq_event();
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 0);
+        assert_eq!(ctx.guest_rxvq.used.idx.get(), 0);
+
+        // Once the backend recovers, scripted failures still apply in order.
+        ctx.device.backend.set_stalled(false);
+        ctx.device
+            .backend
+            .script_tx(vec![Some(VsockError::NoData), None]);
+        ctx.device.backend.script_rx(vec![Some(VsockError::NoData)]);
+        ctx.signal_txq_event();
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 0);
+        assert_eq!(ctx.guest_rxvq.used.idx.get(), 0);
+        ctx.signal_txq_event();
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
+        assert_eq!(ctx.guest_rxvq.used.idx.get(), 1);
+        assert_eq!(ctx.device.backend.tx_ok_cnt, 1);
+        assert_eq!(ctx.device.backend.rx_ok_cnt, 1);
+    }
+
     #[test]
     fn test_rxq_event() {
         // Test case:
@@ -625,5 +693,36 @@ mod tests {
             assert_eq!(guest_rxvq.used.idx.get(), 1);
             assert_eq!(guest_txvq.used.idx.get(), 1);
         }
+
+        // The driver resets the device.
+        let queues = vsock.lock().unwrap().queues.clone();
+        assert!(vsock.lock().unwrap().reset().is_some());
+
+        // The first runtime event after the reset makes the device stop listening for them.
+        vsock.lock().unwrap().backend.evfd.write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        vsock.lock().unwrap().queue_events[TXQ_INDEX]
+            .write(1)
+            .unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // The driver sets the queues up again, makes a new TX descriptor available and
+        // re-activates the device.
+        guest_txvq.avail.ring[1].set(0);
+        guest_txvq.avail.idx.set(2);
+        {
+            let mut device = vsock.lock().unwrap();
+            device.queues = queues;
+            device.activate(test_ctx.mem.clone()).unwrap();
+        }
+        // Process the activate event.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        // Handle the pending TX queue and backend events.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 2);
+        assert_eq!(guest_txvq.used.idx.get(), 2);
     }
 }
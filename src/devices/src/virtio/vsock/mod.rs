@@ -18,7 +18,7 @@ use std::os::unix::io::AsRawFd;
 use crate::virtio::persist::Error as VirtioStateError;
 
 pub use self::defs::uapi::VIRTIO_ID_VSOCK as TYPE_VSOCK;
-pub use self::device::Vsock;
+pub use self::device::{validate_queue_size, Vsock};
 pub use self::unix::{Error as VsockUnixBackendError, VsockUnixBackend};
 
 use utils::epoll::EventSet;
@@ -33,11 +33,11 @@ mod defs {
 
     /// Number of virtio queues.
     pub const NUM_QUEUES: usize = 3;
-    /// Max size of virtio queues.
-    pub const QUEUE_SIZE: u16 = 256;
-    /// Virtio queue sizes, in number of descriptor chain heads.
+    /// Default max size of virtio queues, in number of descriptor chain heads.
     /// There are 3 queues for a virtio device (in this order): RX, TX, Event
-    pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
+    pub const QUEUE_SIZE: u16 = 256;
+    /// Largest queue size allowed by the virtio spec.
+    pub const MAX_QUEUE_SIZE: u16 = 32768;
 
     /// Max vsock packet data/buffer size.
     pub const MAX_PKT_BUF_SIZE: usize = 64 * 1024;
@@ -52,6 +52,13 @@ mod defs {
         pub const VIRTIO_F_IN_ORDER: usize = 35;
         /// The device conforms to the virtio spec version 1.0.
         pub const VIRTIO_F_VERSION_1: u32 = 32;
+        /// The driver and the device suppress notifications through the `used_event` and
+        /// `avail_event` fields of the virtqueues.
+        /// Defined in `/include/uapi/linux/virtio_ring.h`.
+        pub const VIRTIO_RING_F_EVENT_IDX: u32 = 29;
+        /// The device supports SOCK_SEQPACKET connections.
+        /// Defined in `/include/uapi/linux/virtio_vsock.h`.
+        pub const VIRTIO_VSOCK_F_SEQPACKET: u32 = 1;
 
         /// Virtio vsock device ID.
         /// Defined in `include/uapi/linux/virtio_ids.h`.
@@ -82,12 +89,24 @@ mod defs {
         pub const VSOCK_FLAGS_SHUTDOWN_RCV: u32 = 1;
         /// Valid with a VSOCK_OP_SHUTDOWN packet: the packet sender will send no more data.
         pub const VSOCK_FLAGS_SHUTDOWN_SEND: u32 = 2;
+        /// Valid with a SOCK_SEQPACKET VSOCK_OP_RW packet: the packet ends a message.
+        pub const VSOCK_FLAGS_SEQ_EOM: u32 = 1;
 
         /// Vsock packet type.
         /// Defined in `/include/uapi/linux/virtio_vsock.h`.
         ///
-        /// Stream / connection-oriented packet (the only currently valid type).
+        /// Stream / connection-oriented packet.
         pub const VSOCK_TYPE_STREAM: u16 = 1;
+        /// Sequential packet / connection-oriented packet, preserving message boundaries.
+        /// Only valid if VIRTIO_VSOCK_F_SEQPACKET was negotiated.
+        pub const VSOCK_TYPE_SEQPACKET: u16 = 2;
+
+        /// Vsock event IDs.
+        /// Defined in `/include/uapi/linux/virtio_vsock.h`.
+        ///
+        /// The communication has been interrupted; the driver must drop all established
+        /// connections and re-read the guest CID.
+        pub const VIRTIO_VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
 
         pub const VSOCK_HOST_CID: u64 = 2;
     }
@@ -113,10 +132,14 @@ pub enum VsockError {
     NoData,
     /// A data buffer was expected for the provided packet, but it is missing.
     PktBufMissing,
+    /// The event queue has no available descriptors.
+    EvqEmpty,
     /// Encountered an unexpected write-only virtio descriptor.
     UnreadableDescriptor,
     /// Encountered an unexpected read-only virtio descriptor.
     UnwritableDescriptor,
+    /// The queue size is not a power of 2 between 1 and 32768.
+    InvalidQueueSize(u16),
     /// Invalid virtio configuration.
     VirtioState(VirtioStateError),
     VsockUdsBackend(VsockUnixBackendError),
@@ -160,4 +183,22 @@ pub trait VsockChannel {
 /// The vsock backend, which is basically an epoll-event-driven vsock channel.
 /// Currently, the only implementation we have is `crate::virtio::unix::muxer::VsockMuxer`, which
 /// translates guest-side vsock connections to host-side Unix domain socket connections.
-pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {}
+pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {
+    /// Quiesce the backend ahead of the device state being saved.
+    ///
+    /// Connection state is not persisted through snapshots, so any data still buffered by the
+    /// backend must either be flushed to its destination, or dropped, at this point. Either way,
+    /// the outcome must not depend on events that have not yet been processed.
+    fn drain(&mut self) -> Result<()>;
+
+    /// Return the backend to its initial state, after the guest driver has reset the device.
+    ///
+    /// All connections, and any packets still queued up for the driver, are dropped. Host-side
+    /// listeners are kept, so that new connections can be established once the driver
+    /// re-initializes the device.
+    fn reset(&mut self);
+
+    /// Tell the backend whether the driver negotiated VIRTIO_VSOCK_F_SEQPACKET. Until it has,
+    /// the backend must reject VSOCK_TYPE_SEQPACKET packets.
+    fn set_seqpacket(&mut self, enabled: bool);
+}
@@ -14,10 +14,7 @@
 /// `VsockPacket` wraps these two buffers and provides direct access to the data stored
 /// in guest memory. This is done to avoid unnecessarily copying data from guest memory
 /// to temporary buffers, before passing it on to the vsock backend.
-use std::result;
-
 use utils::byte_order;
-use vm_memory::{self, GuestAddress, GuestMemory, GuestMemoryError};
 
 use super::super::DescriptorChain;
 use super::defs;
@@ -97,14 +94,6 @@ pub struct VsockPacket {
     buf_size: usize,
 }
 
-fn get_host_address<T: GuestMemory>(
-    mem: &T,
-    guest_addr: GuestAddress,
-    size: usize,
-) -> result::Result<*mut u8, GuestMemoryError> {
-    Ok(mem.get_slice(guest_addr, size)?.as_ptr())
-}
-
 impl VsockPacket {
     /// Create the packet wrapper from a TX virtq chain head.
     ///
@@ -124,7 +113,9 @@ impl VsockPacket {
         }
 
         let mut pkt = Self {
-            hdr: get_host_address(head.mem, head.addr, VSOCK_PKT_HDR_SIZE)
+            hdr: head
+                .mem
+                .checked_host_address(head.addr, VSOCK_PKT_HDR_SIZE)
                 .map_err(VsockError::GuestMemoryMmap)?,
             buf: None,
             buf_size: 0,
@@ -157,7 +148,9 @@ impl VsockPacket {
 
         pkt.buf_size = buf_desc.len as usize;
         pkt.buf = Some(
-            get_host_address(buf_desc.mem, buf_desc.addr, pkt.buf_size)
+            buf_desc
+                .mem
+                .checked_host_address(buf_desc.addr, pkt.buf_size)
                 .map_err(VsockError::GuestMemoryMmap)?,
         );
 
@@ -188,10 +181,14 @@ impl VsockPacket {
         let buf_size = buf_desc.len as usize;
 
         Ok(Self {
-            hdr: get_host_address(head.mem, head.addr, VSOCK_PKT_HDR_SIZE)
+            hdr: head
+                .mem
+                .checked_writable_host_address(head.addr, VSOCK_PKT_HDR_SIZE)
                 .map_err(VsockError::GuestMemoryMmap)?,
             buf: Some(
-                get_host_address(buf_desc.mem, buf_desc.addr, buf_size)
+                buf_desc
+                    .mem
+                    .checked_writable_host_address(buf_desc.addr, buf_size)
                     .map_err(VsockError::GuestMemoryMmap)?,
             ),
             buf_size,
@@ -341,7 +338,7 @@ impl VsockPacket {
 #[cfg(test)]
 mod tests {
 
-    use vm_memory::{GuestAddress, GuestMemoryMmap};
+    use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
 
     use super::*;
     use crate::virtio::test_utils::VirtqDesc as GuestQDesc;
@@ -381,7 +378,9 @@ mod tests {
 
     fn set_pkt_len(len: u32, guest_desc: &GuestQDesc, mem: &GuestMemoryMmap) {
         let hdr_gpa = guest_desc.addr.get();
-        let hdr_ptr = get_host_address(mem, GuestAddress(hdr_gpa), VSOCK_PKT_HDR_SIZE).unwrap();
+        let hdr_ptr = mem
+            .checked_host_address(GuestAddress(hdr_gpa), VSOCK_PKT_HDR_SIZE)
+            .unwrap();
         let len_ptr = unsafe { hdr_ptr.add(HDROFF_LEN) };
 
         byte_order::write_le_u32(unsafe { std::slice::from_raw_parts_mut(len_ptr, 4) }, len);
@@ -521,6 +520,18 @@ mod tests {
                 .set(VIRTQ_DESC_F_WRITE);
             expect_asm_error!(rx, test_ctx, handler_ctx, VsockError::BufDescMissing);
         }
+
+        // Test case: RX descriptors pointing into read-only guest memory.
+        {
+            create_context!(test_ctx, handler_ctx);
+            test_ctx
+                .mem
+                .find_region(GuestAddress(0))
+                .unwrap()
+                .set_protection(libc::PROT_READ)
+                .unwrap();
+            expect_asm_error!(rx, test_ctx, handler_ctx, VsockError::GuestMemoryMmap(_));
+        }
     }
 
     #[test]
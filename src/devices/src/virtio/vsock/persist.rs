@@ -6,13 +6,14 @@
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
+use super::device::validate_queue_size;
 use super::*;
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::GuestMemoryMmap;
 
-use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::persist::{QueueState, VirtioDeviceState};
 use crate::virtio::{DeviceState, TYPE_VSOCK};
 
 #[derive(Clone, Versionize)]
@@ -96,20 +97,28 @@ where
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
-        // Restore queues.
+        // Restore queues, with the size the device was configured with.
+        let queue_size = state
+            .virtio_state
+            .queues
+            .first()
+            .map_or(defs::QUEUE_SIZE, QueueState::max_size);
+        validate_queue_size(queue_size)?;
         let queues = state
             .virtio_state
             .build_queues_checked(
                 &constructor_args.mem,
                 TYPE_VSOCK,
                 defs::NUM_QUEUES,
-                defs::QUEUE_SIZE,
+                queue_size,
             )
             .map_err(VsockError::VirtioState)?;
         let mut vsock = Self::with_queues(state.cid, constructor_args.backend, queues)?;
 
         vsock.acked_features = state.virtio_state.acked_features;
         vsock.avail_features = state.virtio_state.avail_features;
+        vsock.setup_notif_suppression();
+        vsock.setup_seqpacket();
         vsock.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         vsock.device_state = if state.virtio_state.activated {
             DeviceState::Activated(constructor_args.mem)
@@ -230,4 +239,51 @@ pub(crate) mod tests {
         restored_device.read_config(2, &mut data);
         assert_eq!(data, [0u8, 1, 2, 3, 4, 5, 6, 7]);
     }
+
+    #[test]
+    fn test_persist_unprocessed_descriptors() {
+        // The device never holds on to popped descriptors: a descriptor the backend can't
+        // process is given back to the queue, so it is part of the saved queue state and gets
+        // processed after restore.
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+        ctx.mock_activate(test_ctx.mem.clone());
+
+        ctx.device.backend.set_tx_err(Some(VsockError::NoData));
+        assert!(!ctx.device.process_tx());
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 0);
+
+        let state = ctx.device.save();
+        let mut restored_device = Vsock::restore(
+            VsockConstructorArgs {
+                mem: test_ctx.mem.clone(),
+                backend: TestBackend::new(),
+            },
+            &state,
+        )
+        .unwrap();
+
+        assert!(restored_device.process_tx());
+        assert_eq!(restored_device.backend.tx_ok_cnt, 1);
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
+    }
+
+    #[test]
+    fn test_persist_queue_size() {
+        let ctx = TestContext::new();
+        let device = Vsock::with_queue_size(ctx.cid, TestBackend::new(), 512).unwrap();
+
+        let restored_device = Vsock::restore(
+            VsockConstructorArgs {
+                mem: ctx.mem.clone(),
+                backend: TestBackend::new(),
+            },
+            &device.save(),
+        )
+        .unwrap();
+        assert!(restored_device
+            .queues
+            .iter()
+            .all(|q| q.get_max_size() == 512));
+    }
 }
@@ -1,6 +1,13 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+//! Scaffolding for testing the vsock device, and the devices and components built around it.
+//!
+//! `TestBackend` is a vsock backend whose behavior can be scripted, e.g. to simulate a backend
+//! that stalls or fails, and `TestContext` sets up a vsock device using it, along with guest
+//! memory and virtio queues.
+
+use std::collections::VecDeque;
 use std::os::unix::io::{AsRawFd, RawFd};
 
 use crate::virtio::test_utils::VirtQueue as GuestQ;
@@ -18,34 +25,62 @@ use vm_memory::{GuestAddress, GuestMemoryMmap};
 
 type Result<T> = std::result::Result<T, VsockError>;
 
+/// A vsock backend that yields dummy RX packets and swallows TX packets, with scriptable
+/// outcomes.
 pub struct TestBackend {
     pub evfd: EventFd,
-    pub rx_err: Option<VsockError>,
-    pub tx_err: Option<VsockError>,
+    /// Outcomes of the upcoming `recv_pkt()` calls, in order, with `None` standing for success.
+    /// Calls succeed once the script runs out.
+    pub rx_script: VecDeque<Option<VsockError>>,
+    /// Outcomes of the upcoming `send_pkt()` calls, same as `rx_script`.
+    pub tx_script: VecDeque<Option<VsockError>>,
+    /// While stalled, `recv_pkt()` and `send_pkt()` fail with `VsockError::NoData`, without
+    /// consuming their scripts.
+    pub stalled: bool,
     pub pending_rx: bool,
     pub rx_ok_cnt: usize,
     pub tx_ok_cnt: usize,
     pub evset: Option<EventSet>,
+    pub drain_cnt: usize,
+    pub reset_cnt: usize,
+    pub seqpacket: bool,
 }
 
 impl TestBackend {
     pub fn new() -> Self {
         Self {
             evfd: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
-            rx_err: None,
-            tx_err: None,
+            rx_script: VecDeque::new(),
+            tx_script: VecDeque::new(),
+            stalled: false,
             pending_rx: false,
             rx_ok_cnt: 0,
             tx_ok_cnt: 0,
             evset: None,
+            drain_cnt: 0,
+            reset_cnt: 0,
+            seqpacket: false,
         }
     }
 
+    /// Makes the next `recv_pkt()` call fail with `err`, if any, and the following ones succeed.
     pub fn set_rx_err(&mut self, err: Option<VsockError>) {
-        self.rx_err = err;
+        self.rx_script = err.into_iter().map(Some).collect();
     }
+    /// Makes the next `send_pkt()` call fail with `err`, if any, and the following ones succeed.
     pub fn set_tx_err(&mut self, err: Option<VsockError>) {
-        self.tx_err = err;
+        self.tx_script = err.into_iter().map(Some).collect();
+    }
+    /// Appends outcomes to the `recv_pkt()` script.
+    pub fn script_rx<I: IntoIterator<Item = Option<VsockError>>>(&mut self, outcomes: I) {
+        self.rx_script.extend(outcomes);
+    }
+    /// Appends outcomes to the `send_pkt()` script.
+    pub fn script_tx<I: IntoIterator<Item = Option<VsockError>>>(&mut self, outcomes: I) {
+        self.tx_script.extend(outcomes);
+    }
+    pub fn set_stalled(&mut self, stalled: bool) {
+        self.stalled = stalled;
     }
     pub fn set_pending_rx(&mut self, prx: bool) {
         self.pending_rx = prx;
@@ -61,7 +96,10 @@ impl Default for TestBackend {
 impl VsockChannel for TestBackend {
     fn recv_pkt(&mut self, _pkt: &mut VsockPacket) -> Result<()> {
         let cool_buf = [0xDu8, 0xE, 0xA, 0xD, 0xB, 0xE, 0xE, 0xF];
-        match self.rx_err.take() {
+        if self.stalled {
+            return Err(VsockError::NoData);
+        }
+        match self.rx_script.pop_front().flatten() {
             None => {
                 if let Some(buf) = _pkt.buf_mut() {
                     for i in 0..buf.len() {
@@ -76,7 +114,10 @@ impl VsockChannel for TestBackend {
     }
 
     fn send_pkt(&mut self, _pkt: &VsockPacket) -> Result<()> {
-        match self.tx_err.take() {
+        if self.stalled {
+            return Err(VsockError::NoData);
+        }
+        match self.tx_script.pop_front().flatten() {
             None => {
                 self.tx_ok_cnt += 1;
                 Ok(())
@@ -104,7 +145,21 @@ impl VsockEpollListener for TestBackend {
         self.evset = Some(evset);
     }
 }
-impl VsockBackend for TestBackend {}
+impl VsockBackend for TestBackend {
+    fn drain(&mut self) -> Result<()> {
+        self.drain_cnt += 1;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.pending_rx = false;
+        self.reset_cnt += 1;
+    }
+
+    fn set_seqpacket(&mut self, enabled: bool) {
+        self.seqpacket = enabled;
+    }
+}
 
 pub struct TestContext {
     pub cid: u64,
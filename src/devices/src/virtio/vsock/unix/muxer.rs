@@ -30,9 +30,13 @@
 ///    other pollable FDs are then registered under this nested epoll FD.
 ///    To route all these events to their handlers, the muxer uses another `HashMap` object,
 ///    mapping `RawFd`s to `EpollListener`s.
+///
+/// Guest-initiated SOCK_SEQPACKET connections are forwarded to a SOCK_SEQPACKET Unix socket,
+/// listening at the same per-port path as its SOCK_STREAM counterpart. Host-initiated
+/// connections are always SOCK_STREAM.
 use std::collections::{HashMap, HashSet};
-use std::io::Read;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 
 use logger::{debug, error, info, warn, IncMetric, METRICS};
@@ -63,8 +67,12 @@ pub struct ConnMapKey {
 pub enum MuxerRx {
     /// The packet must be fetched from the connection identified by `ConnMapKey`.
     ConnRx(ConnMapKey),
-    /// The muxer must produce an RST packet.
-    RstPkt { local_port: u32, peer_port: u32 },
+    /// The muxer must produce an RST packet, of the given vsock socket type.
+    RstPkt {
+        local_port: u32,
+        peer_port: u32,
+        sock_type: u16,
+    },
 }
 
 /// An epoll listener, registered under the muxer's nested epoll FD.
@@ -109,6 +117,8 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// Whether the driver negotiated VIRTIO_VSOCK_F_SEQPACKET.
+    seqpacket: bool,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -133,6 +143,7 @@ impl VsockChannel for VsockMuxer {
                 MuxerRx::RstPkt {
                     local_port,
                     peer_port,
+                    sock_type,
                 } => {
                     pkt.set_op(uapi::VSOCK_OP_RST)
                         .set_src_cid(uapi::VSOCK_HOST_CID)
@@ -140,7 +151,7 @@ impl VsockChannel for VsockMuxer {
                         .set_src_port(local_port)
                         .set_dst_port(peer_port)
                         .set_len(0)
-                        .set_type(uapi::VSOCK_TYPE_STREAM)
+                        .set_type(sock_type)
                         .set_flags(0)
                         .set_buf_alloc(0)
                         .set_fwd_cnt(0);
@@ -203,10 +214,12 @@ impl VsockChannel for VsockMuxer {
             pkt.hdr()
         );
 
-        // If this packet has an unsupported type (!=stream), we must send back an RST.
+        // If this packet has an unsupported type (neither stream nor negotiated seqpacket), we
+        // must send back an RST.
         //
-        if pkt.type_() != uapi::VSOCK_TYPE_STREAM {
-            self.enq_rst(pkt.dst_port(), pkt.src_port());
+        let seqpacket = self.seqpacket && pkt.type_() == uapi::VSOCK_TYPE_SEQPACKET;
+        if pkt.type_() != uapi::VSOCK_TYPE_STREAM && !seqpacket {
+            self.enq_rst(pkt.dst_port(), pkt.src_port(), pkt.type_());
             return Ok(());
         }
 
@@ -229,7 +242,7 @@ impl VsockChannel for VsockMuxer {
                 self.handle_peer_request_pkt(&pkt);
             } else {
                 // Send back an RST, to let the drive know we weren't expecting this packet.
-                self.enq_rst(pkt.dst_port(), pkt.src_port());
+                self.enq_rst(pkt.dst_port(), pkt.src_port(), pkt.type_());
             }
             return Ok(());
         }
@@ -305,7 +318,42 @@ impl VsockEpollListener for VsockMuxer {
     }
 }
 
-impl VsockBackend for VsockMuxer {}
+impl VsockBackend for VsockMuxer {
+    /// Flush the TX buffers of all connections to their host-side streams.
+    ///
+    /// Connections that can't get their TX buffer fully flushed without blocking are killed,
+    /// rather than being left with a partially delivered byte stream.
+    fn drain(&mut self) -> VsockResult<()> {
+        let keys: Vec<ConnMapKey> = self.conn_map.keys().copied().collect();
+        for key in keys {
+            let mut flushed = true;
+            self.apply_conn_mutation(key, |conn| flushed = conn.flush_tx_buf());
+            if !flushed {
+                warn!(
+                    "vsock: killing connection (lp={}, pp={}) with unflushed TX data",
+                    key.local_port, key.peer_port
+                );
+                self.kill_connection(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        // The driver has forgotten about all connections, so there is no one to send RSTs to.
+        let keys: Vec<ConnMapKey> = self.conn_map.keys().copied().collect();
+        for key in keys {
+            self.remove_connection(key);
+        }
+        self.rxq = MuxerRxQ::new();
+        self.killq = MuxerKillQ::new();
+        self.seqpacket = false;
+    }
+
+    fn set_seqpacket(&mut self, enabled: bool) {
+        self.seqpacket = enabled;
+    }
+}
 
 impl VsockMuxer {
     /// Muxer constructor.
@@ -327,6 +375,7 @@ impl VsockMuxer {
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            seqpacket: false,
         };
 
         // Listen on the host initiated socket, for incomming connections.
@@ -593,29 +642,42 @@ impl VsockMuxer {
     /// the file system path corresponing to the destination port. If successful, a new
     /// connection object will be created and added to the connection pool. On failure, a new
     /// RST packet will be scheduled for delivery to the guest.
+    /// SOCK_SEQPACKET requests expect a SOCK_SEQPACKET Unix socket at the same path.
     fn handle_peer_request_pkt(&mut self, pkt: &VsockPacket) {
         let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+        let seqpacket = pkt.type_() == uapi::VSOCK_TYPE_SEQPACKET;
 
-        UnixStream::connect(port_path)
+        let connect_result = if seqpacket {
+            connect_seqpacket(&port_path)
+        } else {
+            UnixStream::connect(port_path)
+        };
+        connect_result
             .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
             .map_err(Error::UnixConnect)
             .and_then(|stream| {
+                let conn = MuxerConnection::new_peer_init(
+                    stream,
+                    uapi::VSOCK_HOST_CID,
+                    self.cid,
+                    pkt.dst_port(),
+                    pkt.src_port(),
+                    pkt.buf_alloc(),
+                );
+                let conn = if seqpacket {
+                    conn.with_seqpacket()
+                } else {
+                    conn
+                };
                 self.add_connection(
                     ConnMapKey {
                         local_port: pkt.dst_port(),
                         peer_port: pkt.src_port(),
                     },
-                    MuxerConnection::new_peer_init(
-                        stream,
-                        uapi::VSOCK_HOST_CID,
-                        self.cid,
-                        pkt.dst_port(),
-                        pkt.src_port(),
-                        pkt.buf_alloc(),
-                    ),
+                    conn,
                 )
             })
-            .unwrap_or_else(|_| self.enq_rst(pkt.dst_port(), pkt.src_port()));
+            .unwrap_or_else(|_| self.enq_rst(pkt.dst_port(), pkt.src_port(), pkt.type_()));
     }
 
     /// Perform an action that might mutate a connection's state.
@@ -755,10 +817,11 @@ impl VsockMuxer {
     /// Enqueue errors aren't propagated up the call chain, since there is nothing we can do to
     /// handle them. We do, however, log a warning, since not being able to enqueue an RST
     /// packet means we have to drop it, which is not normal operation.
-    fn enq_rst(&mut self, local_port: u32, peer_port: u32) {
+    fn enq_rst(&mut self, local_port: u32, peer_port: u32, sock_type: u16) {
         let pushed = self.rxq.push(MuxerRx::RstPkt {
             local_port,
             peer_port,
+            sock_type,
         });
         if !pushed {
             warn!(
@@ -769,6 +832,53 @@ impl VsockMuxer {
     }
 }
 
+/// Connect to a SOCK_SEQPACKET Unix socket listening at `path`.
+///
+/// The standard library only supports SOCK_STREAM Unix sockets, but the resulting socket can
+/// still be driven via `UnixStream`, since reading and writing go through the same syscalls.
+fn connect_seqpacket(path: &str) -> io::Result<UnixStream> {
+    // Safe because we check the return value.
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because `fd` is a valid socket that nothing else owns. Wrapping it right away makes
+    // sure it gets closed on the error paths below.
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+
+    let addr = sockaddr_un(path)?;
+    // Safe because `addr` is a valid `sockaddr_un` and we pass in its exact size.
+    let ret = unsafe {
+        libc::connect(
+            stream.as_raw_fd(),
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stream)
+}
+
+/// Build the `sockaddr_un` for a Unix socket path.
+fn sockaddr_un(path: &str) -> io::Result<libc::sockaddr_un> {
+    // Safe because `sockaddr_un` is a plain C struct, for which all zeroes is a valid value.
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    // Leave room for the NUL terminator.
+    if path.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Unix socket path too long",
+        ));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(path.as_bytes()) {
+        *dst = *src as libc::c_char;
+    }
+    Ok(addr)
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
@@ -964,6 +1074,45 @@ mod tests {
         }
     }
 
+    // The standard library has no SOCK_SEQPACKET Unix listener, so we roll our own.
+    struct SeqpacketListener {
+        path: String,
+        fd: RawFd,
+    }
+    impl SeqpacketListener {
+        fn new(path: String) -> Self {
+            let addr = sockaddr_un(&path).unwrap();
+            // Safe because we check the return values, and `addr` outlives the bind() call.
+            unsafe {
+                let fd = libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0);
+                assert!(fd >= 0);
+                let ret = libc::bind(
+                    fd,
+                    &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+                );
+                assert_eq!(ret, 0);
+                assert_eq!(libc::listen(fd, 1), 0);
+                Self { path, fd }
+            }
+        }
+        fn accept(&mut self) -> UnixStream {
+            // Safe because we check the return value, and take ownership of the new fd.
+            unsafe {
+                let fd = libc::accept(self.fd, std::ptr::null_mut(), std::ptr::null_mut());
+                assert!(fd >= 0);
+                UnixStream::from_raw_fd(fd)
+            }
+        }
+    }
+    impl Drop for SeqpacketListener {
+        fn drop(&mut self) {
+            // Safe because we own `self.fd`.
+            unsafe { libc::close(self.fd) };
+            std::fs::remove_file(&self.path).unwrap();
+        }
+    }
+
     #[test]
     fn test_muxer_epoll_listener() {
         let ctx = MuxerTestContext::new("muxer_epoll_listener");
@@ -975,7 +1124,7 @@ mod tests {
     fn test_bad_peer_pkt() {
         const LOCAL_PORT: u32 = 1026;
         const PEER_PORT: u32 = 1025;
-        const SOCK_DGRAM: u16 = 2;
+        const SOCK_DGRAM: u16 = 3;
 
         let mut ctx = MuxerTestContext::new("bad_peer_pkt");
         ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST)
@@ -983,7 +1132,7 @@ mod tests {
         ctx.send();
 
         // The guest sent a SOCK_DGRAM packet. Per the vsock spec, we need to reply with an RST
-        // packet, since vsock only supports stream sockets.
+        // packet, since vsock only supports stream and seqpacket sockets.
         assert!(ctx.muxer.has_pending_rx());
         ctx.recv();
         assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
@@ -1084,6 +1233,86 @@ mod tests {
         assert!(!ctx.muxer.has_pending_rx());
     }
 
+    #[test]
+    fn test_peer_seqpacket_connection() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("peer_seqpacket_connection");
+
+        // Test seqpacket requests are refused until the driver negotiates seqpacket support.
+        let _listener =
+            SeqpacketListener::new(format!("{}_{}", ctx.muxer.host_sock_path, LOCAL_PORT + 2));
+        ctx.init_pkt(LOCAL_PORT + 2, PEER_PORT, uapi::VSOCK_OP_REQUEST)
+            .set_type(uapi::VSOCK_TYPE_SEQPACKET);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+        assert!(ctx.muxer.conn_map.is_empty());
+        ctx.muxer.set_seqpacket(true);
+
+        // Test peer connection refused. A SOCK_STREAM listener can't serve a seqpacket request,
+        // and the RST should match the requested socket type.
+        let _stream_listener = ctx.create_local_listener(LOCAL_PORT + 1);
+        for local_port in [LOCAL_PORT, LOCAL_PORT + 1].iter() {
+            ctx.init_pkt(*local_port, PEER_PORT, uapi::VSOCK_OP_REQUEST)
+                .set_type(uapi::VSOCK_TYPE_SEQPACKET);
+            ctx.send();
+            ctx.recv();
+            assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+            assert_eq!(ctx.pkt.type_(), uapi::VSOCK_TYPE_SEQPACKET);
+            assert_eq!(ctx.pkt.src_port(), *local_port);
+            assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+        }
+        assert!(ctx.muxer.conn_map.is_empty());
+
+        // Test peer connection accepted.
+        let mut listener =
+            SeqpacketListener::new(format!("{}_{}", ctx.muxer.host_sock_path, LOCAL_PORT));
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST)
+            .set_type(uapi::VSOCK_TYPE_SEQPACKET);
+        ctx.send();
+        assert_eq!(ctx.muxer.conn_map.len(), 1);
+        let mut stream = listener.accept();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.pkt.type_(), uapi::VSOCK_TYPE_SEQPACKET);
+
+        // Test guest -> host data flow. A message split across packets should reach the host
+        // whole, and separately from the next one.
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &[1, 2])
+            .set_type(uapi::VSOCK_TYPE_SEQPACKET);
+        ctx.send();
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &[3])
+            .set_type(uapi::VSOCK_TYPE_SEQPACKET)
+            .set_flag(uapi::VSOCK_FLAGS_SEQ_EOM);
+        ctx.send();
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &[4, 5])
+            .set_type(uapi::VSOCK_TYPE_SEQPACKET)
+            .set_flag(uapi::VSOCK_FLAGS_SEQ_EOM);
+        ctx.send();
+        let mut buf = vec![0u8; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(buf[..3], [1, 2, 3]);
+        assert_eq!(stream.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..2], [4, 5]);
+
+        // Test host -> guest data flow. Each host message should end with an EOM packet.
+        stream.write_all(&[6, 7, 8]).unwrap();
+        stream.write_all(&[9]).unwrap();
+        for msg in [&[6u8, 7, 8][..], &[9u8][..]].iter() {
+            ctx.notify_muxer();
+            assert!(ctx.muxer.has_pending_rx());
+            ctx.recv();
+            assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+            assert_eq!(ctx.pkt.type_(), uapi::VSOCK_TYPE_SEQPACKET);
+            assert_ne!(ctx.pkt.flags() & uapi::VSOCK_FLAGS_SEQ_EOM, 0);
+            assert_eq!(ctx.pkt.len() as usize, msg.len());
+            assert_eq!(ctx.pkt.buf().unwrap()[..msg.len()], **msg);
+        }
+        assert!(!ctx.muxer.has_pending_rx());
+    }
+
     #[test]
     fn test_local_connection() {
         let mut ctx = MuxerTestContext::new("local_connection");
@@ -1112,6 +1341,32 @@ mod tests {
         assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
     }
 
+    #[test]
+    fn test_reset() {
+        let mut ctx = MuxerTestContext::new("reset");
+        let peer_port = 1025;
+        let (mut stream, local_port) = ctx.local_connect(peer_port);
+
+        // Leave some host -> guest data pending.
+        stream.write_all(&[1, 2, 3, 4]).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+        assert_eq!(ctx.count_epoll_listeners(), (0, 1));
+
+        ctx.muxer.reset();
+        assert!(!ctx.muxer.has_pending_rx());
+        assert!(ctx.muxer.conn_map.is_empty());
+        assert_eq!(ctx.count_epoll_listeners(), (0, 0));
+        assert!(!ctx.muxer.local_port_set.contains(&local_port));
+        // The host end of the connection sees it closed.
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+
+        // New connections can still be established.
+        let (_stream, _) = ctx.local_connect(peer_port);
+        assert_eq!(ctx.count_epoll_listeners(), (0, 1));
+    }
+
     #[test]
     fn test_local_close() {
         let peer_port = 1025;
@@ -223,10 +223,11 @@ pub(crate) fn run_with_api(
     };
 
     // Start the metrics.
-    firecracker_metrics
-        .lock()
-        .expect("Poisoned lock")
-        .start(super::metrics::WRITE_METRICS_PERIOD_MS);
+    {
+        let mut locked_metrics = firecracker_metrics.lock().expect("Poisoned lock");
+        locked_metrics.set_vmm(vmm.clone());
+        locked_metrics.start(super::metrics::WRITE_METRICS_PERIOD_MS);
+    }
 
     // Update the api shared instance info.
     api_shared_info.write().unwrap().started = true;
@@ -13,13 +13,14 @@ use std::sync::{Arc, Mutex};
 use logger::{error, info, IncMetric, LOGGER, METRICS};
 use polly::event_manager::EventManager;
 use seccomp::{BpfProgram, SeccompLevel};
+use snapshot::{Snapshot, SnapshotArch, SnapshotEndianness};
 use utils::arg_parser::{ArgParser, Argument};
 use utils::terminal::Terminal;
 use utils::validators::validate_instance_id;
 use vmm::default_syscalls::get_seccomp_filter;
 use vmm::resources::VmResources;
 use vmm::signal_handler::register_signal_handlers;
-use vmm::version_map::FC_VERSION_TO_SNAP_VERSION;
+use vmm::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::vmm_config::logger::{init_logger, LoggerConfig, LoggerLevel};
 
@@ -142,6 +143,11 @@ fn main() {
             Argument::new("version")
                 .takes_value(false)
                 .help("Print the binary version number and a list of supported snapshot data format versions.")
+        )
+        .arg(
+            Argument::new("inspect-snapshot")
+                .takes_value(true)
+                .help("Print the architecture and versions of the snapshot state file at the given path, even if it can't be loaded by this binary.")
         );
 
     let arguments = match arg_parser.parse_from_cmdline() {
@@ -166,6 +172,10 @@ fn main() {
                 process::exit(i32::from(vmm::FC_EXIT_CODE_OK));
             }
 
+            if let Some(snapshot_path) = arg_parser.arguments().single_value("inspect-snapshot") {
+                inspect_snapshot(snapshot_path);
+            }
+
             arg_parser.arguments()
         }
     };
@@ -274,6 +284,32 @@ fn print_supported_snapshot_versions() {
     println!("{}\n", snapshot_versions_str);
 }
 
+// Print the architecture and versions of a snapshot state file and exit.
+fn inspect_snapshot(snapshot_path: &str) -> ! {
+    let info = fs::File::open(snapshot_path)
+        .map_err(|err| format!("{}", err))
+        .and_then(|mut file| Snapshot::inspect(&mut file).map_err(|err| format!("{:?}", err)))
+        .unwrap_or_else(|err| {
+            error!("Cannot inspect snapshot {}: {}", snapshot_path, err);
+            process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+        });
+
+    println!("Architecture: {:?}", info.arch);
+    println!("Endianness: {:?}", info.endianness);
+    println!("Format version: {}", info.format_version);
+    match info.data_version {
+        Some(data_version) => println!("Data version: {}", data_version),
+        None => println!("Data version: unknown"),
+    }
+    let loadable = info.arch == SnapshotArch::host()
+        && info.endianness == SnapshotEndianness::Little
+        && info
+            .data_version
+            .map_or(false, |version| version <= VERSION_MAP.latest_version());
+    println!("Loadable by this binary: {}", loadable);
+    process::exit(i32::from(vmm::FC_EXIT_CODE_OK));
+}
+
 // Configure and start a microVM as described by the command-line JSON.
 fn build_microvm_from_json(
     seccomp_filter: BpfProgram,
@@ -318,10 +354,10 @@ fn run_without_api(
         .add_subscriber(firecracker_metrics.clone())
         .expect("Cannot register the metrics event to the event manager.");
 
-    // Build the microVm. We can ignore the returned values here because:
-    // - VmResources is not used without api,
-    // - An `Arc` reference of the built `Vmm` is plugged in the `EventManager` by the builder.
-    build_microvm_from_json(
+    // Build the microVm. We can ignore the returned `VmResources` because it is not used
+    // without api. An `Arc` reference of the built `Vmm` is plugged in the `EventManager`
+    // by the builder; the metrics keep another one for sampling guest memory.
+    let (_, vmm) = build_microvm_from_json(
         seccomp_filter,
         &mut event_manager,
         // Safe to unwrap since '--no-api' requires this to be set.
@@ -331,10 +367,11 @@ fn run_without_api(
     );
 
     // Start the metrics.
-    firecracker_metrics
-        .lock()
-        .expect("Poisoned lock")
-        .start(metrics::WRITE_METRICS_PERIOD_MS);
+    {
+        let mut locked_metrics = firecracker_metrics.lock().expect("Poisoned lock");
+        locked_metrics.set_vmm(vmm);
+        locked_metrics.start(metrics::WRITE_METRICS_PERIOD_MS);
+    }
 
     // Run the EventManager that drives everything in the microVM.
     loop {
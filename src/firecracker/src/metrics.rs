@@ -2,12 +2,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use logger::{error, warn, IncMetric, METRICS};
 use polly::event_manager::{EventManager, Subscriber};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::epoll::{EpollEvent, EventSet};
+use vmm::Vmm;
 
 /// Metrics reporting period.
 pub(crate) const WRITE_METRICS_PERIOD_MS: u64 = 60000;
@@ -15,6 +17,8 @@ pub(crate) const WRITE_METRICS_PERIOD_MS: u64 = 60000;
 /// Object to drive periodic reporting of metrics.
 pub(crate) struct PeriodicMetrics {
     write_metrics_event_fd: TimerFd,
+    // The microVM whose guest memory metrics are sampled before each write.
+    vmm: Option<Arc<Mutex<Vmm>>>,
     #[cfg(test)]
     flush_counter: u64,
 }
@@ -26,6 +30,7 @@ impl PeriodicMetrics {
             .expect("Cannot create the metrics timer fd.");
         PeriodicMetrics {
             write_metrics_event_fd,
+            vmm: None,
             #[cfg(test)]
             flush_counter: 0,
         }
@@ -45,7 +50,16 @@ impl PeriodicMetrics {
         self.write_metrics();
     }
 
+    /// Sample the guest memory metrics of `vmm` every time metrics are written.
+    pub(crate) fn set_vmm(&mut self, vmm: Arc<Mutex<Vmm>>) {
+        self.vmm = Some(vmm);
+    }
+
     fn write_metrics(&mut self) {
+        if let Some(vmm) = self.vmm.as_ref() {
+            vmm.lock().expect("Poisoned lock").update_memory_metrics();
+        }
+
         // Please note that, if METRICS has no output file configured yet, it will write to
         // stdout, so metrics writing will interfere with console output.
         if let Err(e) = METRICS.write() {
@@ -403,6 +403,23 @@ pub struct BlockDeviceMetrics {
     pub rate_limiter_throttled_events: SharedIncMetric,
 }
 
+/// Metrics related to guest memory.
+#[derive(Default, Serialize)]
+pub struct GuestMemoryMetrics {
+    /// Number of guest memory bytes locked in host RAM.
+    pub locked_bytes: SharedStoreMetric,
+    /// Number of guest memory pages resident in host memory, as last sampled.
+    pub resident_pages: SharedStoreMetric,
+    /// Total number of guest memory pages.
+    pub total_pages: SharedStoreMetric,
+    /// Number of times sampling the guest memory residency failed.
+    pub residency_fails: SharedIncMetric,
+    /// Number of times mapping guest memory in the host failed, e.g. due to hugepage exhaustion.
+    pub mmap_fails: SharedIncMetric,
+    /// Number of times guest memory was rejected because of overlapping regions.
+    pub region_overlap_fails: SharedIncMetric,
+}
+
 /// Metrics specific to the i8042 device.
 #[derive(Default, Serialize)]
 pub struct I8042DeviceMetrics {
@@ -555,6 +572,28 @@ pub struct PerformanceMetrics {
     pub vmm_pause_vm: SharedStoreMetric,
     /// Measures the microVM resuming duration, at the VMM level, in microseconds.
     pub vmm_resume_vm: SharedStoreMetric,
+    #[cfg(target_arch = "x86_64")]
+    /// Measures the time spent collecting the microVM state for a snapshot, in microseconds.
+    pub snapshot_save_state: SharedStoreMetric,
+    #[cfg(target_arch = "x86_64")]
+    /// Measures the time spent writing guest memory to the snapshot memory file, in
+    /// microseconds.
+    pub snapshot_write_memory: SharedStoreMetric,
+    #[cfg(target_arch = "x86_64")]
+    /// Measures the time spent serializing the microVM state to the snapshot file, in
+    /// microseconds.
+    pub snapshot_write_state: SharedStoreMetric,
+    #[cfg(target_arch = "x86_64")]
+    /// Measures the time spent reading and deserializing the snapshot file, in microseconds.
+    pub snapshot_read_state: SharedStoreMetric,
+    #[cfg(target_arch = "x86_64")]
+    /// Measures the time spent mapping guest memory from the snapshot memory file, in
+    /// microseconds.
+    pub snapshot_map_memory: SharedStoreMetric,
+    #[cfg(target_arch = "x86_64")]
+    /// Measures the time spent restoring the VM, devices and vCPUs from their state, in
+    /// microseconds.
+    pub snapshot_restore_microvm: SharedStoreMetric,
 }
 
 /// Metrics specific to the RTC device.
@@ -680,6 +719,8 @@ pub struct VsockDeviceMetrics {
     pub tx_write_fails: SharedIncMetric,
     /// Number of times read() has failed.
     pub rx_read_fails: SharedIncMetric,
+    /// Number of connections killed because the guest sent more data than it had credit for.
+    pub tx_credit_violations: SharedIncMetric,
 }
 
 // The sole purpose of this struct is to produce an UTC timestamp when an instance is serialized.
@@ -706,6 +747,8 @@ pub struct FirecrackerMetrics {
     pub block: BlockDeviceMetrics,
     /// Metrics related to API GET requests.
     pub get_api_requests: GetRequestsMetrics,
+    /// Metrics related to guest memory.
+    pub guest_memory: GuestMemoryMetrics,
     /// Metrics related to the i8042 device.
     pub i8042: I8042DeviceMetrics,
     /// Metrics related to performance measurements.
@@ -0,0 +1,13 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use snapshot::Snapshot;
+use snapshot_fuzz::{version_map, FuzzState};
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    // Any input must be rejected with an error, never with a panic or an abort.
+    let _: Result<FuzzState, _> = Snapshot::load(&mut reader, data.len(), version_map());
+});
@@ -0,0 +1,14 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use snapshot::Snapshot;
+use snapshot_fuzz::{version_map, FuzzState};
+
+// Skipping the CRC check lets the fuzzer reach the state deserializers directly instead
+// of spending most inputs on checksum mismatches.
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    let _: Result<FuzzState, _> = Snapshot::unchecked_load(&mut reader, version_map());
+});
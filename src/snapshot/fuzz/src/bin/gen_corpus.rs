@@ -0,0 +1,83 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writes valid snapshots of every data version into the seed corpora of the fuzz targets.
+//!
+//! Usage: `cargo run --bin gen_corpus [<corpus_root>]` (defaults to `corpus`).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use snapshot::Snapshot;
+use snapshot_fuzz::{version_map, FuzzDeviceKind, FuzzQueue, FuzzState};
+
+fn seed_states() -> Vec<FuzzState> {
+    vec![
+        FuzzState::default(),
+        FuzzState {
+            id: "block0".to_owned(),
+            kind: FuzzDeviceKind::Block(1),
+            queues: vec![FuzzQueue {
+                size: 256,
+                ready: true,
+                desc_table: 0x1000,
+                avail_ring: 0x2000,
+                used_ring: 0x3000,
+            }],
+            config_space: vec![0u8; 8],
+            irq: Some(5),
+            acked_features: 1 << 32,
+            label: "rootfs".to_owned(),
+        },
+        FuzzState {
+            id: "net0".to_owned(),
+            kind: FuzzDeviceKind::Net(vec![0x06, 0, 0xAC, 0x10, 0, 2]),
+            queues: vec![FuzzQueue::default(); 2],
+            config_space: vec![0xFF; 6],
+            irq: None,
+            acked_features: 0,
+            label: String::new(),
+        },
+        FuzzState {
+            id: "vsock0".to_owned(),
+            kind: FuzzDeviceKind::Vsock(3),
+            queues: vec![FuzzQueue::default(); 3],
+            config_space: Vec::new(),
+            irq: Some(7),
+            acked_features: 0x3,
+            label: "vsock".to_owned(),
+        },
+    ]
+}
+
+fn write_seed(dir: &Path, name: &str, bytes: &[u8]) {
+    fs::create_dir_all(dir).expect("Cannot create corpus directory");
+    fs::write(dir.join(name), bytes).expect("Cannot write corpus entry");
+}
+
+fn main() {
+    let root = PathBuf::from(
+        std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "corpus".to_owned()),
+    );
+    let vm = version_map();
+
+    for (idx, state) in seed_states().iter().enumerate() {
+        for version in 1..=vm.latest_version() {
+            let name = format!("state{}_v{}", idx, version);
+
+            let mut checked = Vec::new();
+            Snapshot::new(vm.clone(), version)
+                .save(&mut checked, state)
+                .expect("Cannot save snapshot");
+            write_seed(&root.join("snapshot_load"), &name, &checked);
+
+            let mut unchecked = Vec::new();
+            Snapshot::new(vm.clone(), version)
+                .save_without_crc(&mut unchecked, state)
+                .expect("Cannot save snapshot");
+            write_seed(&root.join("snapshot_unchecked_load"), &name, &unchecked);
+        }
+    }
+}
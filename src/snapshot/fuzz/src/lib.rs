@@ -0,0 +1,83 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shared state definitions for the snapshot fuzz targets.
+//!
+//! The state mixes the shapes found in real device state (nested structures, enums,
+//! vectors, strings, options and fields that only exist in some versions) so the fuzzer
+//! exercises every deserialization path of the `Versionize` primitives and derived code.
+
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// Queue-like nested state.
+#[derive(Clone, Debug, Default, PartialEq, Versionize)]
+pub struct FuzzQueue {
+    pub size: u16,
+    pub ready: bool,
+    pub desc_table: u64,
+    pub avail_ring: u64,
+    pub used_ring: u64,
+}
+
+/// Enum with a variant that only exists starting with version 2.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+pub enum FuzzDeviceKind {
+    Block(u32),
+    Net(Vec<u8>),
+    #[version(start = 2, default_fn = "default_kind")]
+    Vsock(u64),
+}
+
+impl FuzzDeviceKind {
+    fn default_kind(&self, target_version: u16) -> VersionizeResult<FuzzDeviceKind> {
+        match target_version {
+            1 => Ok(FuzzDeviceKind::Block(0)),
+            i => Err(VersionizeError::Serialize(format!(
+                "Unknown target version: {}",
+                i
+            ))),
+        }
+    }
+}
+
+impl Default for FuzzDeviceKind {
+    fn default() -> Self {
+        FuzzDeviceKind::Block(0)
+    }
+}
+
+/// Top level state loaded by the fuzz targets.
+#[derive(Clone, Debug, Default, PartialEq, Versionize)]
+pub struct FuzzState {
+    pub id: String,
+    pub kind: FuzzDeviceKind,
+    pub queues: Vec<FuzzQueue>,
+    pub config_space: Vec<u8>,
+    pub irq: Option<u32>,
+    #[version(start = 2, default_fn = "default_features")]
+    pub acked_features: u64,
+    #[version(start = 3, default_fn = "default_label")]
+    pub label: String,
+}
+
+impl FuzzState {
+    fn default_features(_: u16) -> u64 {
+        0
+    }
+
+    fn default_label(_: u16) -> String {
+        String::new()
+    }
+}
+
+/// Returns the version map used by both the fuzz targets and the corpus generator.
+pub fn version_map() -> VersionMap {
+    let mut vm = VersionMap::new();
+    vm.new_version()
+        .set_type_version(FuzzState::type_id(), 2)
+        .set_type_version(FuzzDeviceKind::type_id(), 2)
+        .new_version()
+        .set_type_version(FuzzState::type_id(), 3);
+    vm
+}
@@ -25,8 +25,23 @@
 //! implementation does not have any logic dependent on it.
 //!  - **the data version** which refers to the state.
 //!
+//! The snapshot bytes only depend on the state and the target version, so identical state always
+//! produces identical snapshots. State structures should therefore not hold unordered collections
+//! such as `HashMap`, whose iteration order changes between runs.
+//!
+//! Snapshots can be written to any `std::io::Write`. Destinations that receive data in chunks,
+//! such as object storage uploads, implement `SnapshotSink` and are wrapped in a `SinkWriter`.
+//!
 mod persist;
+mod sink;
+mod std_types;
 pub use crate::persist::Persist;
+pub use crate::sink::{
+    ChunkTransport, ChunkedUploader, FileSink, SinkWriter, SnapshotSink, DEFAULT_PART_SIZE,
+};
+pub use crate::std_types::{
+    VersionizedDuration, VersionizedIpv4Addr, VersionizedSocketAddrV4, VersionizedSystemTime,
+};
 
 use std::io::{Read, Write};
 use versionize::crc::{CRC64Reader, CRC64Writer};
@@ -36,11 +51,61 @@ use versionize_derive::Versionize;
 const SNAPSHOT_FORMAT_VERSION: u16 = 1;
 const BASE_MAGIC_ID_MASK: u64 = !0xFFFFu64;
 
+const X86_64_MAGIC_ID: u64 = 0x0710_1984_8664_0000u64;
+const AARCH64_MAGIC_ID: u64 = 0x0710_1984_AAAA_0000u64;
+
 #[cfg(target_arch = "x86_64")]
-const BASE_MAGIC_ID: u64 = 0x0710_1984_8664_0000u64;
+const BASE_MAGIC_ID: u64 = X86_64_MAGIC_ID;
 
 #[cfg(target_arch = "aarch64")]
-const BASE_MAGIC_ID: u64 = 0x0710_1984_AAAA_0000u64;
+const BASE_MAGIC_ID: u64 = AARCH64_MAGIC_ID;
+
+/// Architecture a snapshot was created on, as encoded in its magic id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotArch {
+    /// x86_64.
+    X86_64,
+    /// aarch64.
+    Aarch64,
+}
+
+impl SnapshotArch {
+    /// Returns the architecture snapshots created by this build belong to.
+    pub fn host() -> Self {
+        Self::from_base_magic_id(BASE_MAGIC_ID).unwrap()
+    }
+
+    fn from_base_magic_id(base_magic_id: u64) -> Option<Self> {
+        match base_magic_id {
+            X86_64_MAGIC_ID => Some(SnapshotArch::X86_64),
+            AARCH64_MAGIC_ID => Some(SnapshotArch::Aarch64),
+            _ => None,
+        }
+    }
+}
+
+/// Byte order of a snapshot, as detected from its magic id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotEndianness {
+    /// Little endian, the byte order snapshots are written in.
+    Little,
+    /// Big endian.
+    Big,
+}
+
+/// Information about a snapshot, read from its magic id and header without validating them
+/// against the running build.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SnapshotInfo {
+    /// Architecture the snapshot was created on.
+    pub arch: SnapshotArch,
+    /// Byte order of the snapshot.
+    pub endianness: SnapshotEndianness,
+    /// Snapshot format version.
+    pub format_version: u16,
+    /// Snapshot data version, only read for little endian snapshots.
+    pub data_version: Option<u16>,
+}
 
 /// Error definitions for the Snapshot API.
 #[derive(Debug, PartialEq)]
@@ -51,12 +116,28 @@ pub enum Error {
     InvalidDataVersion(u16),
     /// Invalid format version.
     InvalidFormatVersion(u16),
-    /// Magic value does not match arch.
+    /// The snapshot was created on another architecture.
+    ArchMismatch {
+        /// Architecture the snapshot was created on.
+        found: SnapshotArch,
+        /// Architecture of the running build.
+        expected: SnapshotArch,
+    },
+    /// The snapshot was written in another byte order.
+    EndiannessMismatch {
+        /// Byte order of the snapshot.
+        found: SnapshotEndianness,
+        /// Byte order of the running build.
+        expected: SnapshotEndianness,
+    },
+    /// Magic value does not identify a snapshot.
     InvalidMagic(u64),
     /// Snapshot file is smaller than CRC length.
     InvalidSnapshotSize,
     /// An IO error occurred.
     Io(i32),
+    /// There is no data version supported by both the local and the peer version maps.
+    NoCommonDataVersion(u16),
     /// A versioned serialization/deserialization error occurred.
     Versionize(versionize::VersionizeError),
 }
@@ -78,19 +159,57 @@ pub struct Snapshot {
     target_version: u16,
 }
 
-// Parse a magic_id and return the format version.
+// Parse a magic_id of any architecture and byte order, returning the architecture, the byte
+// order and the format version.
+fn parse_magic_id(magic_id: u64) -> Result<(SnapshotArch, SnapshotEndianness, u16), Error> {
+    let candidates = [
+        (magic_id, SnapshotEndianness::Little),
+        (magic_id.swap_bytes(), SnapshotEndianness::Big),
+    ];
+    candidates
+        .iter()
+        .find_map(|(magic_id, endianness)| {
+            SnapshotArch::from_base_magic_id(magic_id & BASE_MAGIC_ID_MASK)
+                .map(|arch| (arch, *endianness, (magic_id & !BASE_MAGIC_ID_MASK) as u16))
+        })
+        .ok_or(Error::InvalidMagic(magic_id))
+}
+
+// Parse a magic_id and return the format version, rejecting snapshots of other architectures
+// and byte orders.
 fn get_format_version(magic_id: u64) -> Result<u16, Error> {
-    let magic_arch = magic_id & BASE_MAGIC_ID_MASK;
-    if magic_arch == BASE_MAGIC_ID {
-        return Ok((magic_id & !BASE_MAGIC_ID_MASK) as u16);
+    let (arch, endianness, format_version) = parse_magic_id(magic_id)?;
+    if endianness != SnapshotEndianness::Little {
+        return Err(Error::EndiannessMismatch {
+            found: endianness,
+            expected: SnapshotEndianness::Little,
+        });
     }
-    Err(Error::InvalidMagic(magic_id))
+    if arch != SnapshotArch::host() {
+        return Err(Error::ArchMismatch {
+            found: arch,
+            expected: SnapshotArch::host(),
+        });
+    }
+    Ok(format_version)
 }
 
 fn build_magic_id(format_version: u16) -> u64 {
     BASE_MAGIC_ID | format_version as u64
 }
 
+/// Returns the highest data version supported by both the local `version_map` and a peer
+/// that advertises `peer_version` as its latest supported data version.
+///
+/// Data versions are cumulative (supporting version N implies supporting all versions up to
+/// N), so the result is the lowest of the two latest versions.
+pub fn negotiate_data_version(version_map: &VersionMap, peer_version: u16) -> Result<u16, Error> {
+    if peer_version == 0 {
+        return Err(Error::NoCommonDataVersion(peer_version));
+    }
+    Ok(std::cmp::min(version_map.latest_version(), peer_version))
+}
+
 impl Snapshot {
     /// Creates a new instance which can only be used to save a new snapshot.
     pub fn new(version_map: VersionMap, target_version: u16) -> Snapshot {
@@ -102,6 +221,45 @@ impl Snapshot {
         }
     }
 
+    /// Creates a new instance which can only be used to save a new snapshot, targeting the
+    /// highest data version supported by both the local `version_map` and a peer that
+    /// advertises `peer_version` as its latest supported data version (e.g. during a live
+    /// update handshake).
+    pub fn new_for_peer(version_map: VersionMap, peer_version: u16) -> Result<Snapshot, Error> {
+        let target_version = negotiate_data_version(&version_map, peer_version)?;
+        Ok(Snapshot::new(version_map, target_version))
+    }
+
+    /// Reads the magic id and header of a snapshot without validating them against the running
+    /// build, so that tooling can tell which architecture and versions a snapshot targets even
+    /// when it can't be loaded here. The state itself is not read.
+    pub fn inspect<T: Read>(mut reader: &mut T) -> Result<SnapshotInfo, Error> {
+        let format_version_map = Self::format_version_map();
+        let magic_id =
+            <u64 as Versionize>::deserialize(&mut reader, &format_version_map, 0 /* unused */)
+                .map_err(Error::Versionize)?;
+        let (arch, endianness, format_version) = parse_magic_id(magic_id)?;
+
+        // The header can only be decoded in the byte order snapshots are written in.
+        let data_version = if endianness == SnapshotEndianness::Little
+            && format_version != 0
+            && format_version <= format_version_map.latest_version()
+        {
+            let hdr = SnapshotHdr::deserialize(&mut reader, &format_version_map, format_version)
+                .map_err(Error::Versionize)?;
+            Some(hdr.data_version)
+        } else {
+            None
+        };
+
+        Ok(SnapshotInfo {
+            arch,
+            endianness,
+            format_version,
+            data_version,
+        })
+    }
+
     /// Attempts to load an existing snapshot without CRC validation.
     pub fn unchecked_load<T, O>(mut reader: &mut T, version_map: VersionMap) -> Result<O, Error>
     where
@@ -312,6 +470,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_negotiate_data_version() {
+        let mut vm = VersionMap::new();
+        vm.new_version()
+            .set_type_version(Test::type_id(), 2)
+            .new_version()
+            .set_type_version(Test::type_id(), 3);
+        assert_eq!(vm.latest_version(), 3);
+
+        // The peer is older.
+        assert_eq!(negotiate_data_version(&vm, 2), Ok(2));
+        // The peer is on par.
+        assert_eq!(negotiate_data_version(&vm, 3), Ok(3));
+        // The peer is newer.
+        assert_eq!(negotiate_data_version(&vm, 10), Ok(3));
+        // The peer doesn't support any version.
+        assert_eq!(
+            negotiate_data_version(&vm, 0),
+            Err(Error::NoCommonDataVersion(0))
+        );
+
+        // A snapshot negotiated for an older peer can be loaded by that peer.
+        let state = Test {
+            field_x: 0,
+            field0: 0,
+            field1: 1,
+            field2: 2,
+            field3: "test".to_owned(),
+            field4: vec![4, 3, 2, 1],
+        };
+        let mut snapshot_mem = vec![0u8; 1024];
+        let mut snapshot = Snapshot::new_for_peer(vm.clone(), 2).unwrap();
+        snapshot
+            .save_without_crc(&mut snapshot_mem.as_mut_slice(), &state)
+            .unwrap();
+
+        let mut peer_vm = VersionMap::new();
+        peer_vm.new_version().set_type_version(Test::type_id(), 2);
+        let restored_state: Test =
+            Snapshot::unchecked_load(&mut snapshot_mem.as_slice(), peer_vm).unwrap();
+        assert_eq!(restored_state.field2, 2);
+        // Field 3 doesn't exist in version 2, so it was restored to its default value.
+        assert_eq!(restored_state.field3, "default");
+
+        assert!(Snapshot::new_for_peer(vm, 0).is_err());
+    }
+
     #[test]
     fn test_get_format_version() {
         // Check if `get_format_version()` returns indeed the format
@@ -330,6 +535,63 @@ mod tests {
             get_format_version(invalid_magic_id).unwrap_err(),
             Error::InvalidMagic(invalid_magic_id)
         );
+
+        // Snapshots of other architectures and byte orders are told apart from garbage.
+        #[cfg(target_arch = "x86_64")]
+        let (foreign_magic_id, foreign_arch) = (AARCH64_MAGIC_ID | 1, SnapshotArch::Aarch64);
+        #[cfg(target_arch = "aarch64")]
+        let (foreign_magic_id, foreign_arch) = (X86_64_MAGIC_ID | 1, SnapshotArch::X86_64);
+        assert_eq!(
+            get_format_version(foreign_magic_id).unwrap_err(),
+            Error::ArchMismatch {
+                found: foreign_arch,
+                expected: SnapshotArch::host(),
+            }
+        );
+        assert_eq!(
+            get_format_version(good_magic_id.swap_bytes()).unwrap_err(),
+            Error::EndiannessMismatch {
+                found: SnapshotEndianness::Big,
+                expected: SnapshotEndianness::Little,
+            }
+        );
+    }
+
+    #[test]
+    fn test_inspect() {
+        let vm = VersionMap::new();
+        let mut snapshot_mem = vec![0u8; 64];
+        Snapshot::new(vm.clone(), 1)
+            .save(&mut snapshot_mem.as_mut_slice(), &42u64)
+            .unwrap();
+        assert_eq!(
+            Snapshot::inspect(&mut snapshot_mem.as_slice()).unwrap(),
+            SnapshotInfo {
+                arch: SnapshotArch::host(),
+                endianness: SnapshotEndianness::Little,
+                format_version: SNAPSHOT_FORMAT_VERSION,
+                data_version: Some(1),
+            }
+        );
+
+        // A snapshot of another architecture can be inspected, but not loaded.
+        let foreign_magic_id = match SnapshotArch::host() {
+            SnapshotArch::X86_64 => AARCH64_MAGIC_ID,
+            SnapshotArch::Aarch64 => X86_64_MAGIC_ID,
+        } | SNAPSHOT_FORMAT_VERSION as u64;
+        snapshot_mem[..8].copy_from_slice(&foreign_magic_id.to_le_bytes());
+        let info = Snapshot::inspect(&mut snapshot_mem.as_slice()).unwrap();
+        assert_ne!(info.arch, SnapshotArch::host());
+        assert_eq!(info.data_version, Some(1));
+        let load_result: Result<u64, Error> =
+            Snapshot::unchecked_load(&mut snapshot_mem.as_slice(), vm);
+        assert!(matches!(load_result, Err(Error::ArchMismatch { .. })));
+
+        // Only the magic id of a byte swapped snapshot is decoded.
+        snapshot_mem[..8].copy_from_slice(&foreign_magic_id.to_be_bytes());
+        let info = Snapshot::inspect(&mut snapshot_mem.as_slice()).unwrap();
+        assert_eq!(info.endianness, SnapshotEndianness::Big);
+        assert_eq!(info.data_version, None);
     }
 
     #[test]
@@ -512,6 +774,168 @@ mod tests {
         let _: Test1 = Snapshot::load(&mut snapshot_mem.as_slice(), 38, vm).unwrap();
     }
 
+    #[test]
+    fn test_deterministic_output() {
+        let vm = VersionMap::new();
+        let state = vec![
+            Test1 {
+                field_x: 1,
+                field0: 2,
+                field1: 3,
+            },
+            Test1 {
+                field_x: 4,
+                field0: 5,
+                field1: 6,
+            },
+        ];
+
+        // Identical state must always produce identical snapshot bytes.
+        let mut first = Vec::new();
+        Snapshot::new(vm.clone(), 1)
+            .save(&mut first, &state)
+            .unwrap();
+        let mut second = Vec::new();
+        Snapshot::new(vm, 1).save(&mut second, &state).unwrap();
+        assert_eq!(first, second);
+    }
+
+    // Fault injection settings for `FaultyWriter` and `FaultyReader`.
+    #[derive(Clone, Copy)]
+    struct Faults {
+        // Max number of bytes transferred by a single call.
+        max_chunk: usize,
+        // If set, every `interrupt_every`-th call fails with `EINTR`.
+        interrupt_every: Option<usize>,
+        // If set, the stream ends at this offset.
+        eof_at: Option<usize>,
+    }
+
+    struct FaultyStream {
+        faults: Faults,
+        calls: usize,
+        offset: usize,
+    }
+
+    impl FaultyStream {
+        fn new(faults: Faults) -> Self {
+            FaultyStream {
+                faults,
+                calls: 0,
+                offset: 0,
+            }
+        }
+
+        // Returns how many of the `len` requested bytes the next call transfers.
+        fn next_call(&mut self, len: usize) -> std::io::Result<usize> {
+            self.calls += 1;
+            if let Some(n) = self.faults.interrupt_every {
+                if self.calls % n == 0 {
+                    return Err(std::io::Error::from_raw_os_error(libc::EINTR));
+                }
+            }
+            let remaining = self
+                .faults
+                .eof_at
+                .map_or(usize::max_value(), |eof| eof.saturating_sub(self.offset));
+            let count = len.min(self.faults.max_chunk).min(remaining);
+            self.offset += count;
+            Ok(count)
+        }
+    }
+
+    struct FaultyWriter {
+        stream: FaultyStream,
+        buf: Vec<u8>,
+    }
+
+    impl Write for FaultyWriter {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            let count = self.stream.next_call(data.len())?;
+            self.buf.extend_from_slice(&data[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct FaultyReader<'a> {
+        stream: FaultyStream,
+        data: &'a [u8],
+    }
+
+    impl Read for FaultyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let offset = self.stream.offset;
+            let available = std::cmp::min(buf.len(), self.data.len() - offset);
+            let count = self.stream.next_call(available)?;
+            buf[..count].copy_from_slice(&self.data[offset..offset + count]);
+            Ok(count)
+        }
+    }
+
+    fn faulty_save(state: &Test1, faults: Faults) -> Result<Vec<u8>, Error> {
+        let mut writer = FaultyWriter {
+            stream: FaultyStream::new(faults),
+            buf: Vec::new(),
+        };
+        Snapshot::new(VersionMap::new(), 1).save(&mut writer, state)?;
+        Ok(writer.buf)
+    }
+
+    fn faulty_load(data: &[u8], faults: Faults) -> Result<Test1, Error> {
+        let mut reader = FaultyReader {
+            stream: FaultyStream::new(faults),
+            data,
+        };
+        Snapshot::load(&mut reader, data.len(), VersionMap::new())
+    }
+
+    #[test]
+    fn test_faulty_io() {
+        let state = Test1 {
+            field_x: 1,
+            field0: 2,
+            field1: 3,
+        };
+        let mut expected = Vec::new();
+        Snapshot::new(VersionMap::new(), 1)
+            .save(&mut expected, &state)
+            .unwrap();
+
+        // Short transfers and interrupted calls are retried, and don't affect the outcome.
+        let faults = Faults {
+            max_chunk: 3,
+            interrupt_every: Some(2),
+            eof_at: None,
+        };
+        assert_eq!(faulty_save(&state, faults).unwrap(), expected);
+        let restored = faulty_load(&expected, faults).unwrap();
+        assert_eq!(restored.field_x, state.field_x);
+        assert_eq!(restored.field0, state.field0);
+        assert_eq!(restored.field1, state.field1);
+
+        // Running out of space or data fails the whole operation, wherever it happens.
+        for eof in [0, 4, 20, expected.len() - 1].iter() {
+            let faults = Faults {
+                max_chunk: 3,
+                interrupt_every: Some(2),
+                eof_at: Some(*eof),
+            };
+            match faulty_save(&state, faults) {
+                Err(Error::Versionize(_)) => (),
+                other => panic!("Unexpected save result at offset {}: {:?}", eof, other),
+            }
+            // Depending on the offset, either the state or the stored checksum is cut short.
+            match faulty_load(&expected, faults) {
+                Err(Error::Io(libc::EINVAL)) | Err(Error::Versionize(_)) => (),
+                other => panic!("Unexpected load result at offset {}: {:?}", eof, other),
+            }
+        }
+    }
+
     #[test]
     fn test_invalid_snapshot_size() {
         let vm = VersionMap::new();
@@ -2,8 +2,21 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Defines an abstract interface for saving/restoring a component from state.
+//!
+//! `Persist` and `Versionize` have separate responsibilities:
+//! - `Persist` converts a live component (which may own file descriptors, threads, guest
+//! memory references, etc.) to and from a plain `State` object;
+//! - `Versionize` serializes that `State` object in a version tolerant way.
+//!
+//! Components should therefore implement `Persist` with a `State` that derives `Versionize`,
+//! and never implement `Versionize` on the live component itself. Anything the component
+//! needs at restore time that cannot be part of its state (e.g. the guest memory, or a backend
+//! created by the caller) belongs in `ConstructorArgs`.
 
 /// An abstract interface for saving/restoring a component using a specific state.
+///
+/// This is the single persistence interface shared by all Firecracker components that are
+/// part of a snapshot.
 pub trait Persist<'a>
 where
     Self: Sized,
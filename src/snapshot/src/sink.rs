@@ -0,0 +1,270 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Destinations snapshots can be streamed to.
+//!
+//! A `SnapshotSink` receives the snapshot as a sequence of chunks and is finalized once all of
+//! them were written, which lets snapshots go straight to remote storage without first being
+//! staged in a local file. `SinkWriter` adapts any sink to `std::io::Write`, so it can be passed
+//! to `Snapshot::save` or used to dump guest memory.
+
+use std::fs::File;
+use std::io::{self, Write};
+
+/// Default size of the parts uploaded by a `ChunkedUploader`.
+pub const DEFAULT_PART_SIZE: usize = 8 << 20;
+
+/// Receives a snapshot as a stream of chunks.
+pub trait SnapshotSink {
+    /// Writes the next chunk of the snapshot.
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()>;
+    /// Completes the snapshot. No chunks may be written afterwards.
+    fn finalize(&mut self) -> io::Result<()>;
+}
+
+/// Writes the snapshot to a local file, which is synced to disk on `finalize`.
+#[derive(Debug)]
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    /// Creates a sink writing to `file`, starting at its current offset.
+    pub fn new(file: File) -> Self {
+        FileSink { file }
+    }
+}
+
+impl SnapshotSink for FileSink {
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+}
+
+/// Transport used by a `ChunkedUploader` to send parts to remote storage, e.g. an S3-style
+/// multipart upload.
+pub trait ChunkTransport {
+    /// Uploads part number `part_number` (starting at 1) of the snapshot.
+    fn upload_part(&mut self, part_number: u32, data: &[u8]) -> io::Result<()>;
+    /// Completes the upload, which consists of `part_count` parts.
+    fn complete(&mut self, part_count: u32) -> io::Result<()>;
+}
+
+/// Groups the snapshot into fixed size parts and hands them to a `ChunkTransport`.
+///
+/// All parts except the last one are exactly `part_size` bytes long. At most one part is
+/// buffered in memory at any time.
+#[derive(Debug)]
+pub struct ChunkedUploader<T: ChunkTransport> {
+    transport: T,
+    part_size: usize,
+    buffer: Vec<u8>,
+    part_count: u32,
+}
+
+impl<T: ChunkTransport> ChunkedUploader<T> {
+    /// Creates an uploader sending parts of `part_size` bytes through `transport`.
+    pub fn new(transport: T, part_size: usize) -> Self {
+        // A part needs to carry at least one byte.
+        let part_size = part_size.max(1);
+        ChunkedUploader {
+            transport,
+            part_size,
+            buffer: Vec::with_capacity(part_size),
+            part_count: 0,
+        }
+    }
+
+    /// Returns the number of parts uploaded so far.
+    pub fn part_count(&self) -> u32 {
+        self.part_count
+    }
+
+    /// Consumes the uploader, returning the transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    fn upload_buffer(&mut self) -> io::Result<()> {
+        self.transport
+            .upload_part(self.part_count + 1, &self.buffer)?;
+        self.part_count += 1;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<T: ChunkTransport> SnapshotSink for ChunkedUploader<T> {
+    fn write_chunk(&mut self, mut chunk: &[u8]) -> io::Result<()> {
+        while !chunk.is_empty() {
+            let len = (self.part_size - self.buffer.len()).min(chunk.len());
+            self.buffer.extend_from_slice(&chunk[..len]);
+            chunk = &chunk[len..];
+            if self.buffer.len() == self.part_size {
+                self.upload_buffer()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        // An empty snapshot still gets uploaded as one (empty) part.
+        if !self.buffer.is_empty() || self.part_count == 0 {
+            self.upload_buffer()?;
+        }
+        self.transport.complete(self.part_count)
+    }
+}
+
+/// Exposes a `SnapshotSink` through `std::io::Write`.
+///
+/// Flushing does not finalize the sink, call `finalize` once the whole snapshot was written.
+#[derive(Debug)]
+pub struct SinkWriter<S: SnapshotSink> {
+    sink: S,
+}
+
+impl<S: SnapshotSink> SinkWriter<S> {
+    /// Creates a writer forwarding all writes to `sink`.
+    pub fn new(sink: S) -> Self {
+        SinkWriter { sink }
+    }
+
+    /// Finalizes the sink and returns it.
+    pub fn finalize(mut self) -> io::Result<S> {
+        self.sink.finalize()?;
+        Ok(self.sink)
+    }
+}
+
+impl<S: SnapshotSink> Write for SinkWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sink.write_chunk(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Snapshot;
+
+    use std::fs::{self, OpenOptions};
+
+    use versionize::VersionMap;
+
+    #[derive(Debug, Default)]
+    struct MockTransport {
+        parts: Vec<(u32, Vec<u8>)>,
+        completed: Option<u32>,
+        fail_part: Option<u32>,
+    }
+
+    impl ChunkTransport for MockTransport {
+        fn upload_part(&mut self, part_number: u32, data: &[u8]) -> io::Result<()> {
+            if self.fail_part == Some(part_number) {
+                return Err(io::Error::from_raw_os_error(libc::ECONNRESET));
+            }
+            self.parts.push((part_number, data.to_vec()));
+            Ok(())
+        }
+
+        fn complete(&mut self, part_count: u32) -> io::Result<()> {
+            self.completed = Some(part_count);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_chunked_uploader() {
+        let mut uploader = ChunkedUploader::new(MockTransport::default(), 4);
+        uploader.write_chunk(&[1, 2, 3]).unwrap();
+        assert_eq!(uploader.part_count(), 0);
+        uploader.write_chunk(&[4, 5, 6, 7, 8, 9, 10]).unwrap();
+        assert_eq!(uploader.part_count(), 2);
+        uploader.write_chunk(&[]).unwrap();
+        uploader.finalize().unwrap();
+        assert_eq!(uploader.part_count(), 3);
+
+        let transport = uploader.into_inner();
+        assert_eq!(
+            transport.parts,
+            vec![
+                (1, vec![1, 2, 3, 4]),
+                (2, vec![5, 6, 7, 8]),
+                (3, vec![9, 10])
+            ]
+        );
+        assert_eq!(transport.completed, Some(3));
+
+        // Nothing gets uploaded twice when the data ends on a part boundary.
+        let mut uploader = ChunkedUploader::new(MockTransport::default(), 2);
+        uploader.write_chunk(&[1, 2]).unwrap();
+        uploader.finalize().unwrap();
+        assert_eq!(uploader.into_inner().completed, Some(1));
+
+        // An empty snapshot is uploaded as a single empty part.
+        let mut uploader = ChunkedUploader::new(MockTransport::default(), 2);
+        uploader.finalize().unwrap();
+        let transport = uploader.into_inner();
+        assert_eq!(transport.parts, vec![(1, vec![])]);
+        assert_eq!(transport.completed, Some(1));
+
+        // Transport errors are propagated.
+        let transport = MockTransport {
+            fail_part: Some(2),
+            ..Default::default()
+        };
+        let mut uploader = ChunkedUploader::new(transport, 2);
+        assert!(uploader.write_chunk(&[1, 2, 3, 4]).is_err());
+        assert_eq!(uploader.part_count(), 1);
+    }
+
+    #[test]
+    fn test_sink_writer() {
+        let vm = VersionMap::new();
+        let state: Vec<u64> = vec![1, 2, 3, 0xdead_beef];
+
+        // Save through the uploader and load back from the concatenated parts.
+        let mut writer = SinkWriter::new(ChunkedUploader::new(MockTransport::default(), 7));
+        let mut snapshot = Snapshot::new(vm.clone(), 1);
+        snapshot.save(&mut writer, &state).unwrap();
+        let transport = writer.finalize().unwrap().into_inner();
+        assert!(transport.parts.len() > 1);
+        assert_eq!(transport.completed, Some(transport.parts.len() as u32));
+
+        let data: Vec<u8> = transport
+            .parts
+            .into_iter()
+            .flat_map(|(_, part)| part)
+            .collect();
+        let restored: Vec<u64> =
+            Snapshot::load(&mut data.as_slice(), data.len(), vm.clone()).unwrap();
+        assert_eq!(restored, state);
+
+        // Same with a local file.
+        let path = std::env::temp_dir().join(format!("snapshot_sink_{}", std::process::id()));
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut writer = SinkWriter::new(FileSink::new(file));
+        snapshot.save(&mut writer, &state).unwrap();
+        writer.finalize().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let restored: Vec<u64> = Snapshot::load(&mut data.as_slice(), data.len(), vm).unwrap();
+        assert_eq!(restored, state);
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Versioned representations of standard library types commonly found in device state.
+//!
+//! `Versionize` can't be implemented for foreign types outside of the `versionize` crate,
+//! so each type gets a small state structure that converts to and from the `std` value.
+//! Fixed size byte arrays (e.g. MAC addresses) are supported by `versionize` directly.
+
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// Versioned `std::time::Duration`.
+///
+/// Out of range values, which only corrupted snapshots hold, saturate to the longest duration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Versionize)]
+pub struct VersionizedDuration {
+    secs: u64,
+    nanos: u32,
+}
+
+impl From<Duration> for VersionizedDuration {
+    fn from(duration: Duration) -> Self {
+        VersionizedDuration {
+            secs: duration.as_secs(),
+            nanos: duration.subsec_nanos(),
+        }
+    }
+}
+
+impl From<VersionizedDuration> for Duration {
+    fn from(state: VersionizedDuration) -> Self {
+        // `nanos` may exceed a second, so adding it can overflow.
+        Duration::from_secs(state.secs)
+            .checked_add(Duration::from_nanos(u64::from(state.nanos)))
+            .unwrap_or_else(|| Duration::new(u64::MAX, 999_999_999))
+    }
+}
+
+/// Versioned `std::time::SystemTime`, stored as nanoseconds relative to the unix epoch.
+///
+/// Times further than ~292 years from the epoch saturate to the representable range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Versionize)]
+pub struct VersionizedSystemTime {
+    epoch_ns: i64,
+}
+
+impl From<SystemTime> for VersionizedSystemTime {
+    fn from(time: SystemTime) -> Self {
+        let epoch_ns = match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => i64::try_from(after.as_nanos()).unwrap_or(i64::MAX),
+            Err(err) => i64::try_from(err.duration().as_nanos())
+                .map(|ns| -ns)
+                .unwrap_or(i64::MIN),
+        };
+        VersionizedSystemTime { epoch_ns }
+    }
+}
+
+impl From<VersionizedSystemTime> for SystemTime {
+    fn from(state: VersionizedSystemTime) -> Self {
+        if state.epoch_ns < 0 {
+            // `wrapping_neg` keeps `i64::MIN` representable once reinterpreted as unsigned.
+            UNIX_EPOCH - Duration::from_nanos(state.epoch_ns.wrapping_neg() as u64)
+        } else {
+            UNIX_EPOCH + Duration::from_nanos(state.epoch_ns as u64)
+        }
+    }
+}
+
+/// Versioned `std::net::Ipv4Addr`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Versionize)]
+pub struct VersionizedIpv4Addr {
+    octets: [u8; 4],
+}
+
+impl From<Ipv4Addr> for VersionizedIpv4Addr {
+    fn from(addr: Ipv4Addr) -> Self {
+        VersionizedIpv4Addr {
+            octets: addr.octets(),
+        }
+    }
+}
+
+impl From<VersionizedIpv4Addr> for Ipv4Addr {
+    fn from(state: VersionizedIpv4Addr) -> Self {
+        Ipv4Addr::from(state.octets)
+    }
+}
+
+/// Versioned `std::net::SocketAddrV4`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Versionize)]
+pub struct VersionizedSocketAddrV4 {
+    ip: VersionizedIpv4Addr,
+    port: u16,
+}
+
+impl From<SocketAddrV4> for VersionizedSocketAddrV4 {
+    fn from(addr: SocketAddrV4) -> Self {
+        VersionizedSocketAddrV4 {
+            ip: VersionizedIpv4Addr::from(*addr.ip()),
+            port: addr.port(),
+        }
+    }
+}
+
+impl From<VersionizedSocketAddrV4> for SocketAddrV4 {
+    fn from(state: VersionizedSocketAddrV4) -> Self {
+        SocketAddrV4::new(Ipv4Addr::from(state.ip), state.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<T: Versionize>(state: &T) -> T {
+        let vm = VersionMap::new();
+        let mut mem = vec![0u8; 64];
+        state.serialize(&mut mem.as_mut_slice(), &vm, 1).unwrap();
+        T::deserialize(&mut mem.as_slice(), &vm, 1).unwrap()
+    }
+
+    #[test]
+    fn test_duration() {
+        let duration = Duration::new(12, 345_678_901);
+        let restored = roundtrip(&VersionizedDuration::from(duration));
+        assert_eq!(Duration::from(restored), duration);
+
+        // Durations that don't fit saturate instead of panicking.
+        let state = VersionizedDuration {
+            secs: u64::MAX,
+            nanos: u32::MAX,
+        };
+        assert_eq!(Duration::from(state), Duration::new(u64::MAX, 999_999_999));
+        let state = VersionizedDuration {
+            secs: 1,
+            nanos: 2_500_000_000,
+        };
+        assert_eq!(Duration::from(state), Duration::new(3, 500_000_000));
+    }
+
+    #[test]
+    fn test_system_time() {
+        let now = SystemTime::now();
+        let restored = roundtrip(&VersionizedSystemTime::from(now));
+        assert_eq!(SystemTime::from(restored), now);
+
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(3600);
+        let restored = roundtrip(&VersionizedSystemTime::from(before_epoch));
+        assert_eq!(SystemTime::from(restored), before_epoch);
+    }
+
+    #[test]
+    fn test_net_addrs() {
+        let ip = Ipv4Addr::new(169, 254, 169, 254);
+        let restored = roundtrip(&VersionizedIpv4Addr::from(ip));
+        assert_eq!(Ipv4Addr::from(restored), ip);
+
+        let addr = SocketAddrV4::new(ip, 8080);
+        let restored = roundtrip(&VersionizedSocketAddrV4::from(addr));
+        assert_eq!(SocketAddrV4::from(restored), addr);
+    }
+}
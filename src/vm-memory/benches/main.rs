@@ -0,0 +1,111 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use vm_memory::{Bytes, FileOffset, GuestAddress, GuestMemory, GuestMemoryMmap};
+use vmm_sys_util::tempfile::TempFile;
+
+const REGION_SIZE: usize = 0x10_0000;
+const COPY_SIZE: usize = 0x1_0000;
+
+/// Two adjacent anonymous regions, so that accesses can straddle the boundary between them.
+fn anon_memory() -> GuestMemoryMmap {
+    GuestMemoryMmap::from_ranges(&[
+        (GuestAddress(0), REGION_SIZE),
+        (GuestAddress(REGION_SIZE as u64), REGION_SIZE),
+    ])
+    .unwrap()
+}
+
+/// The same layout as `anon_memory()`, with each region backed by its own file.
+fn file_memory() -> GuestMemoryMmap {
+    let ranges = (0..2).map(|i| {
+        let f = TempFile::new().unwrap().into_file();
+        f.set_len(REGION_SIZE as u64).unwrap();
+        (
+            GuestAddress((i * REGION_SIZE) as u64),
+            REGION_SIZE,
+            Some(FileOffset::new(f, 0)),
+        )
+    });
+    GuestMemoryMmap::from_ranges_with_files(ranges, false).unwrap()
+}
+
+fn bench_obj(c: &mut Criterion) {
+    let mem = anon_memory();
+    let addr = GuestAddress(0x1000);
+
+    c.bench_function("write_obj u64", |b| {
+        b.iter(|| mem.write_obj(black_box(0xdead_beef_u64), black_box(addr)))
+    });
+    c.bench_function("read_obj u64", |b| {
+        b.iter(|| mem.read_obj::<u64>(black_box(addr)))
+    });
+}
+
+fn bench_slice(c: &mut Criterion) {
+    let mem = anon_memory();
+    let mut buf = vec![0u8; COPY_SIZE];
+    let within = GuestAddress(0);
+    let across = GuestAddress((REGION_SIZE - COPY_SIZE / 2) as u64);
+
+    c.bench_function("write_slice within region", |b| {
+        b.iter(|| mem.write_slice(black_box(&buf), black_box(within)))
+    });
+    c.bench_function("write_slice across regions", |b| {
+        b.iter(|| mem.write_slice(black_box(&buf), black_box(across)))
+    });
+    c.bench_function("read_slice within region", |b| {
+        b.iter(|| mem.read_slice(black_box(&mut buf), black_box(within)))
+    });
+    c.bench_function("read_slice across regions", |b| {
+        b.iter(|| mem.read_slice(black_box(&mut buf), black_box(across)))
+    });
+}
+
+fn bench_volatile(c: &mut Criterion) {
+    let mem = anon_memory();
+    let mut buf = vec![0u8; COPY_SIZE];
+    let addr = GuestAddress(0);
+
+    c.bench_function("volatile slice copy", |b| {
+        b.iter(|| {
+            mem.get_slice(black_box(addr), COPY_SIZE)
+                .unwrap()
+                .copy_to(black_box(&mut buf[..]))
+        })
+    });
+    c.bench_function("non-volatile copy", |b| {
+        b.iter(|| {
+            let host_addr = mem.get_host_address(black_box(addr)).unwrap();
+            // Safe because the range is within a single region, which outlives the copy.
+            unsafe { std::ptr::copy_nonoverlapping(host_addr, buf.as_mut_ptr(), COPY_SIZE) };
+            black_box(&buf);
+        })
+    });
+}
+
+fn bench_backing(c: &mut Criterion) {
+    let anon = anon_memory();
+    let file = file_memory();
+    let buf = vec![0xa5u8; COPY_SIZE];
+    let addr = GuestAddress(0);
+
+    c.bench_function("write_slice anonymous", |b| {
+        b.iter(|| anon.write_slice(black_box(&buf), black_box(addr)))
+    });
+    c.bench_function("write_slice file-backed", |b| {
+        b.iter(|| file.write_slice(black_box(&buf), black_box(addr)))
+    });
+    c.bench_function("create anonymous", |b| b.iter(anon_memory));
+    c.bench_function("create file-backed", |b| b.iter(file_memory));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(200);
+    targets = bench_obj, bench_slice, bench_volatile, bench_backing
+}
+
+criterion_main! {
+    benches
+}
@@ -0,0 +1,230 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+//! Translation of the I/O virtual addresses (IOVAs) devices use for DMA into guest physical
+//! addresses.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::result;
+
+use vm_memory_upstream::{Address, GuestAddress};
+
+/// Errors associated with IOVA translation.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The (IOVA, length) range is empty or wraps around the address space.
+    InvalidRange(u64, u64),
+    /// The IOVA range overlaps an existing mapping.
+    Overlap(u64),
+    /// The (IOVA, length) range is not entirely covered by a single mapping.
+    Unmapped(u64, u64),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+
+        match self {
+            InvalidRange(iova, len) => {
+                write!(f, "Invalid IOVA range of {:#x} bytes at {:#x}", len, iova)
+            }
+            Overlap(iova) => write!(
+                f,
+                "The IOVA range at {:#x} overlaps an existing mapping",
+                iova
+            ),
+            Unmapped(iova, len) => write!(
+                f,
+                "The IOVA range of {:#x} bytes at {:#x} is not mapped",
+                len, iova
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// `IovaTable` translates the addresses a device uses for DMA into guest physical addresses.
+///
+/// An identity table, which is what devices without an IOMMU see, uses IOVAs as guest physical
+/// addresses. Otherwise, only IOVAs covered by the mappings added through `map()` translate, and
+/// each access must fall within a single mapping.
+#[derive(Debug, Default)]
+pub struct IovaTable {
+    // Whether IOVAs are translated through `mappings`, instead of being used as they are.
+    translated: bool,
+    // The mappings, indexed by their first IOVA, as (length, guest physical address) pairs.
+    mappings: BTreeMap<u64, (u64, GuestAddress)>,
+}
+
+impl IovaTable {
+    /// Creates a table that translates each IOVA to the same guest physical address.
+    pub fn identity() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty table, under which no IOVA translates until it gets mapped.
+    pub fn new() -> Self {
+        IovaTable {
+            translated: true,
+            mappings: BTreeMap::new(),
+        }
+    }
+
+    /// Returns whether IOVAs are used as guest physical addresses.
+    pub fn is_identity(&self) -> bool {
+        !self.translated
+    }
+
+    /// Maps the `len` bytes starting at `iova` to the guest physical range starting at `gpa`.
+    ///
+    /// Mappings can't be added to an identity table, nor overlap each other.
+    pub fn map(&mut self, iova: u64, gpa: GuestAddress, len: u64) -> Result<()> {
+        let end = Self::range_end(iova, len)?;
+        if gpa.checked_add(len - 1).is_none() {
+            return Err(Error::InvalidRange(gpa.raw_value(), len));
+        }
+        if !self.translated {
+            return Err(Error::Overlap(iova));
+        }
+        if let Some((&start, &(mapping_len, _))) = self.mappings.range(..=end).next_back() {
+            if start + (mapping_len - 1) >= iova {
+                return Err(Error::Overlap(iova));
+            }
+        }
+        self.mappings.insert(iova, (len, gpa));
+        Ok(())
+    }
+
+    /// Removes the mapping that starts at `iova`.
+    pub fn unmap(&mut self, iova: u64) -> Result<()> {
+        self.mappings
+            .remove(&iova)
+            .map(|_| ())
+            .ok_or(Error::Unmapped(iova, 0))
+    }
+
+    /// Translates the access of `len` bytes at `iova` into the guest physical address it
+    /// targets.
+    pub fn translate(&self, iova: u64, len: u64) -> Result<GuestAddress> {
+        // Zero-length accesses still need a valid address.
+        let end = Self::range_end(iova, std::cmp::max(len, 1))?;
+        if !self.translated {
+            return Ok(GuestAddress(iova));
+        }
+        match self.mappings.range(..=iova).next_back() {
+            Some((&start, &(mapping_len, gpa))) if end <= start + (mapping_len - 1) => {
+                Ok(gpa.unchecked_add(iova - start))
+            }
+            _ => Err(Error::Unmapped(iova, len)),
+        }
+    }
+
+    // Returns the last IOVA of the `len` bytes range starting at `iova`.
+    fn range_end(iova: u64, len: u64) -> Result<u64> {
+        len.checked_sub(1)
+            .and_then(|last| iova.checked_add(last))
+            .ok_or(Error::InvalidRange(iova, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity() {
+        let table = IovaTable::identity();
+        assert!(table.is_identity());
+        assert_eq!(table.translate(0x1000, 8).unwrap(), GuestAddress(0x1000));
+        assert_eq!(table.translate(0x1000, 0).unwrap(), GuestAddress(0x1000));
+        assert_eq!(
+            table.translate(u64::max_value(), 2).unwrap_err(),
+            Error::InvalidRange(u64::max_value(), 2)
+        );
+
+        let mut table = IovaTable::identity();
+        assert_eq!(
+            table.map(0, GuestAddress(0x1000), 0x1000).unwrap_err(),
+            Error::Overlap(0)
+        );
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut table = IovaTable::new();
+        assert!(!table.is_identity());
+        assert_eq!(table.translate(0, 1).unwrap_err(), Error::Unmapped(0, 1));
+
+        table.map(0x1000, GuestAddress(0x10_0000), 0x1000).unwrap();
+        table.map(0x3000, GuestAddress(0x8000), 0x1000).unwrap();
+
+        assert_eq!(table.translate(0x1000, 4).unwrap(), GuestAddress(0x10_0000));
+        assert_eq!(table.translate(0x1ffc, 4).unwrap(), GuestAddress(0x10_0ffc));
+        assert_eq!(table.translate(0x3800, 0).unwrap(), GuestAddress(0x8800));
+
+        // Accesses must fall within a single mapping.
+        assert_eq!(
+            table.translate(0x1ffc, 8).unwrap_err(),
+            Error::Unmapped(0x1ffc, 8)
+        );
+        assert_eq!(
+            table.translate(0x2000, 4).unwrap_err(),
+            Error::Unmapped(0x2000, 4)
+        );
+        assert_eq!(
+            table.translate(0xfff, 1).unwrap_err(),
+            Error::Unmapped(0xfff, 1)
+        );
+
+        // Mappings can't overlap.
+        assert_eq!(
+            table.map(0x1800, GuestAddress(0), 0x1000).unwrap_err(),
+            Error::Overlap(0x1800)
+        );
+        assert_eq!(
+            table.map(0x800, GuestAddress(0), 0x1000).unwrap_err(),
+            Error::Overlap(0x800)
+        );
+        table.map(0x2000, GuestAddress(0), 0x1000).unwrap();
+
+        // Neither can they be empty or wrap around.
+        assert_eq!(
+            table.map(0x5000, GuestAddress(0), 0).unwrap_err(),
+            Error::InvalidRange(0x5000, 0)
+        );
+        assert_eq!(
+            table
+                .map(u64::max_value(), GuestAddress(0), 0x1000)
+                .unwrap_err(),
+            Error::InvalidRange(u64::max_value(), 0x1000)
+        );
+        assert_eq!(
+            table
+                .map(0x5000, GuestAddress(u64::max_value()), 0x1000)
+                .unwrap_err(),
+            Error::InvalidRange(u64::max_value(), 0x1000)
+        );
+
+        table.unmap(0x1000).unwrap();
+        assert_eq!(table.unmap(0x1000).unwrap_err(), Error::Unmapped(0x1000, 0));
+        assert_eq!(
+            table.translate(0x1000, 4).unwrap_err(),
+            Error::Unmapped(0x1000, 4)
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        for err in [
+            Error::InvalidRange(0, 0),
+            Error::Overlap(0x1000),
+            Error::Unmapped(0x1000, 8),
+        ]
+        .iter()
+        {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -10,10 +10,12 @@
 //! This crate implements a custom vm-memory backend implementation that overrides the
 //! upstream implementation and adds dirty page tracking functionality.
 pub mod bitmap;
+pub mod iova;
 pub mod mmap;
 
 // Export local backend implementation.
-pub use mmap::{GuestMemoryMmap, GuestRegionMmap};
+pub use iova::IovaTable;
+pub use mmap::{GuestMemoryMmap, GuestMemoryWindow, GuestRegionMmap, MapOptions, PageResidency};
 
 // Re-export only what is needed in Firecracker.
 pub use vm_memory_upstream::{
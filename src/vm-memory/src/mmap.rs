@@ -13,10 +13,13 @@
 //! This implementation is mmap-ing the memory of the guest into the current process.
 
 use std::borrow::Borrow;
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::ops::Deref;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
 use std::result;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
 
 use vm_memory_upstream::address::Address;
@@ -40,6 +43,63 @@ pub use vm_memory_upstream::mmap::{check_file_offset, Error};
 
 // The maximum number of bytes that can be read/written at a time.
 static MAX_ACCESS_CHUNK: usize = 4096;
+// The most iovecs a single `preadv()` / `pwritev()` call accepts on Linux.
+const IOV_MAX: usize = 1024;
+// Magic number identifying a hugetlbfs mount, as reported by `statfs`.
+// Defined in `include/uapi/linux/magic.h`.
+const HUGETLBFS_MAGIC: i64 = 0x9584_58f6;
+
+// Memory policy restricting allocations to a set of NUMA nodes.
+// Defined in `include/uapi/linux/mempolicy.h`.
+const MPOL_BIND: libc::c_int = 2;
+// `mbind()` flag migrating the pages already allocated outside of the policy's nodes.
+// Defined in `include/uapi/linux/mempolicy.h`.
+const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+// `mlock2()` flag deferring the locking of pages until they are faulted in.
+// Defined in `include/uapi/asm-generic/mman-common.h`.
+const MLOCK_ONFAULT: libc::c_uint = 1;
+
+/// Residency of the pages backing guest memory, as reported by `mincore()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PageResidency {
+    /// Number of pages that are resident in memory.
+    pub resident: usize,
+    /// Total number of pages, including the ones that were not faulted in yet.
+    pub total: usize,
+}
+
+/// Optional `mmap()` behaviours for guest memory regions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MapOptions {
+    /// Pre-fault the whole mapping in the kernel (`MAP_POPULATE`), instead of on first access.
+    ///
+    /// The kernel doesn't report population failures, e.g. under memory pressure; use
+    /// `GuestMemoryMmap::unpopulated_regions()` to find out which regions were left short.
+    pub populate: bool,
+    /// Map the region read-only, e.g. for firmware or ROM. Guest writes to it exit to the VMM,
+    /// and the VMM's own writes fail instead of faulting.
+    pub read_only: bool,
+}
+
+impl MapOptions {
+    /// Returns the `mmap()` flags implementing these options.
+    pub fn flags(self) -> i32 {
+        let mut flags = 0;
+        if self.populate {
+            flags |= libc::MAP_POPULATE;
+        }
+        flags
+    }
+
+    /// Returns the `mmap()` protection implementing these options.
+    pub fn prot(self) -> i32 {
+        if self.read_only {
+            libc::PROT_READ
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        }
+    }
+}
 
 /// [`GuestMemoryRegion`](trait.GuestMemoryRegion.html) implementation that mmaps the guest's
 /// memory region in the current process.
@@ -52,6 +112,9 @@ pub struct GuestRegionMmap {
     guest_base: GuestAddress,
     // handles dirty page tracking
     dirty_bitmap: Option<Bitmap>,
+    // current protection of the mapping, which can diverge from `mapping.prot()` after
+    // `set_protection()` calls
+    prot: AtomicI32,
 }
 
 impl GuestRegionMmap {
@@ -61,12 +124,233 @@ impl GuestRegionMmap {
             return Err(Error::InvalidGuestRegion);
         }
         Ok(GuestRegionMmap {
+            prot: AtomicI32::new(mapping.prot()),
             mapping,
             guest_base,
             dirty_bitmap: None,
         })
     }
 
+    /// Get the current protection (`PROT_*` flags) of the region's mapping.
+    pub fn protection(&self) -> i32 {
+        self.prot.load(Ordering::Acquire)
+    }
+
+    /// Change the protection of the region's mapping to `prot`, e.g. to make firmware or ROM
+    /// regions read-only. Writes through the `Bytes` accessors fail once `PROT_WRITE` is
+    /// cleared.
+    ///
+    /// Only `PROT_READ` and `PROT_READ | PROT_WRITE` are accepted, anything else fails with
+    /// `EINVAL`. Write-only regions can't be honored, since neither the MMU nor KVM memory
+    /// slots can map memory that is writable but not readable.
+    ///
+    /// This only changes the VMM's view of the region. For a region that is mapped into the
+    /// guest, use `Vm::set_region_protection()`, which updates its KVM memory slot as well.
+    pub fn set_protection(&self, prot: i32) -> io::Result<()> {
+        if prot != libc::PROT_READ && prot != libc::PROT_READ | libc::PROT_WRITE {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        // Safe because the address and length describe the mapping owned by this region.
+        let ret = unsafe {
+            libc::mprotect(
+                self.mapping.as_ptr() as *mut libc::c_void,
+                self.mapping.len(),
+                prot,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.prot.store(prot, Ordering::Release);
+        Ok(())
+    }
+
+    // Fails with `EACCES` if the region's mapping doesn't allow writes, so that writing to a
+    // read-only region returns an error instead of faulting.
+    fn check_writable(&self) -> guest_memory::Result<()> {
+        if self.protection() & libc::PROT_WRITE == 0 {
+            return Err(guest_memory::Error::IOError(io::Error::from_raw_os_error(
+                libc::EACCES,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Bind the region's memory to the NUMA `node`, so that its pages are only allocated on
+    /// that node. Pages that are already populated, e.g. because the region was mapped with
+    /// `MapOptions::populate`, are migrated to the node.
+    pub fn bind_numa_node(&self, node: u32) -> io::Result<()> {
+        let bits_per_long = 8 * std::mem::size_of::<libc::c_ulong>();
+        let mut nodemask = vec![0 as libc::c_ulong; node as usize / bits_per_long + 1];
+        nodemask[node as usize / bits_per_long] |= 1 << (node as usize % bits_per_long);
+        // Safe because the address and length describe the mapping owned by this region, and
+        // `nodemask` holds `maxnode - 1` bits (the kernel ignores the last one).
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_mbind,
+                self.mapping.as_ptr() as *mut libc::c_void,
+                self.mapping.len(),
+                MPOL_BIND,
+                nodemask.as_ptr(),
+                nodemask.len() * bits_per_long + 1,
+                MPOL_MF_MOVE,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Lock the region's memory in RAM, so that its pages can't be swapped out. Unless
+    /// `on_fault` is set, all pages are populated right away; otherwise, pages get locked as
+    /// they are faulted in. The lock is released when the region is unmapped.
+    pub fn lock(&self, on_fault: bool) -> io::Result<()> {
+        let flags = if on_fault { MLOCK_ONFAULT } else { 0 };
+        // Safe because the address and length describe the mapping owned by this region.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_mlock2,
+                self.mapping.as_ptr() as *const libc::c_void,
+                self.mapping.len(),
+                flags,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Discard the `len` bytes of the region starting at `offset`, releasing the memory backing
+    /// them. `offset` and `len` must be aligned to the region's page size, which is the huge
+    /// page size for hugetlbfs-backed regions, otherwise `EINVAL` is returned.
+    ///
+    /// Anonymous memory reads as zero afterwards. For file-backed regions, a hole is punched in
+    /// the backing file first, so that the space is returned to the filesystem and the range
+    /// reads as the (now zeroed) file contents. The file must then be open for writing, else
+    /// `EBADF` is returned before anything changes, and must not be shared with anything
+    /// expecting its contents to be preserved, e.g. other microVMs restored from the same
+    /// snapshot memory file.
+    pub fn discard(&self, offset: usize, len: usize) -> io::Result<()> {
+        let page_size = self.page_size()?;
+        if offset % page_size != 0
+            || len % page_size != 0
+            || offset
+                .checked_add(len)
+                .map_or(true, |end| end > self.mapping.len())
+        {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        // Punch the hole before dropping any page: if that fails, neither the file nor the
+        // mapping changed, instead of the guest silently seeing the old file contents again.
+        if let Some(file_offset) = self.mapping.file_offset() {
+            let fd = file_offset.file().as_raw_fd();
+            // Safe because we only pass a valid file descriptor, and check the return value.
+            let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+            if flags < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if flags & libc::O_ACCMODE == libc::O_RDONLY {
+                return Err(io::Error::from_raw_os_error(libc::EBADF));
+            }
+            // Safe because we only pass a valid file descriptor, and check the return value.
+            let ret = unsafe {
+                libc::fallocate(
+                    fd,
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    (file_offset.start() + offset as u64) as libc::off_t,
+                    len as libc::off_t,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        // Drop the private pages, be they anonymous or copy-on-write copies of file pages.
+        // Shared mappings directly reflect the file contents.
+        if self.mapping.flags() & libc::MAP_SHARED == 0 {
+            // Safe because the range is within the mapping owned by this region.
+            let ret = unsafe {
+                libc::madvise(
+                    self.mapping.as_ptr().add(offset) as *mut libc::c_void,
+                    len,
+                    libc::MADV_DONTNEED,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the size of the pages backing the region: the huge page size for regions backed
+    // by a hugetlbfs file, and the base page size otherwise.
+    fn page_size(&self) -> io::Result<usize> {
+        if let Some(file_offset) = self.mapping.file_offset() {
+            // Safe because `statfs` is plain old data, which gets fully overwritten below.
+            let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+            // Safe because we only pass a valid file descriptor, and check the return value.
+            if unsafe { libc::fstatfs(file_offset.file().as_raw_fd(), &mut stat) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if stat.f_type as i64 == HUGETLBFS_MAGIC {
+                return Ok(stat.f_bsize as usize);
+            }
+        }
+        match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            -1 => Err(io::Error::last_os_error()),
+            ps => Ok(ps as usize),
+        }
+    }
+
+    /// Report how many pages of the region are resident in memory.
+    ///
+    /// Pages that were never touched, or that were not yet populated after a lazy restore,
+    /// are counted as non-resident.
+    pub fn resident_pages(&self) -> io::Result<PageResidency> {
+        let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            -1 => return Err(io::Error::last_os_error()),
+            ps => ps as usize,
+        };
+        let total = (self.mapping.len() + page_size - 1) / page_size;
+        let mut residency = vec![0u8; total];
+        // Safe because the address and length describe the mapping owned by this region, and
+        // `residency` holds one byte for each of its pages.
+        let ret = unsafe {
+            libc::mincore(
+                self.mapping.as_ptr() as *mut libc::c_void,
+                self.mapping.len(),
+                residency.as_mut_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PageResidency {
+            // Only the least significant bit is defined, the others are reserved.
+            resident: residency.iter().filter(|&&page| page & 1 != 0).count(),
+            total,
+        })
+    }
+
+    // Returns whether the `len` bytes of the region starting at `offset` are all zero. The range
+    // must be within the region.
+    fn is_zero(&self, offset: usize, len: usize) -> bool {
+        // Safe because the range is within the mapping owned by this region. Memory is read in
+        // place, so concurrent guest writes can only make the result stale.
+        let bytes = unsafe { std::slice::from_raw_parts(self.mapping.as_ptr().add(offset), len) };
+        // Compare whole words, which the compiler vectorizes, and the unaligned ends bytewise.
+        // Safe because any bit pattern is a valid u64.
+        let (head, words, tail) = unsafe { bytes.align_to::<u64>() };
+        head.iter().all(|&byte| byte == 0)
+            && words.iter().all(|&word| word == 0)
+            && tail.iter().all(|&byte| byte == 0)
+    }
+
     /// Provide the region with a dedicated bitmap to handle dirty page tracking.
     pub fn enable_dirty_page_tracking(&mut self) {
         let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
@@ -113,6 +397,7 @@ impl Bytes<MemoryRegionAddress> for GuestRegionMmap {
     type E = guest_memory::Error;
 
     fn write(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
+        self.check_writable()?;
         let maddr = addr.raw_value() as usize;
         let bytes = self
             .local_volatile_slice()
@@ -130,6 +415,7 @@ impl Bytes<MemoryRegionAddress> for GuestRegionMmap {
     }
 
     fn write_slice(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
+        self.check_writable()?;
         let maddr = addr.raw_value() as usize;
         match self.local_volatile_slice().write_slice(buf, maddr) {
             Ok(()) => {
@@ -178,6 +464,7 @@ impl Bytes<MemoryRegionAddress> for GuestRegionMmap {
     where
         F: Read,
     {
+        self.check_writable()?;
         let maddr = addr.raw_value() as usize;
         let bytes = self
             .local_volatile_slice()
@@ -196,6 +483,7 @@ impl Bytes<MemoryRegionAddress> for GuestRegionMmap {
     where
         F: Read,
     {
+        self.check_writable()?;
         let maddr = addr.raw_value() as usize;
         self.local_volatile_slice()
             .read_exact_from::<F>(maddr, src, count)
@@ -349,6 +637,32 @@ impl GuestMemoryMmap {
         Self::from_ranges_with_files(ranges.iter().map(|r| (r.0, r.1, None)), true)
     }
 
+    /// Creates a container and allocates anonymous memory for guest memory regions, each
+    /// mapped according to its own options.
+    ///
+    /// # Arguments
+    ///
+    /// * 'ranges' - Slice of (Address, Size, MapOptions) tuples sorted by Address.
+    /// * 'track_dirty_pages' - Whether or not dirty page tracking is enabled.
+    pub fn from_ranges_with_options(
+        ranges: &[(GuestAddress, usize, MapOptions)],
+        track_dirty_pages: bool,
+    ) -> result::Result<Self, Error> {
+        let mut regions = Vec::with_capacity(ranges.len());
+        for &(guest_base, size, options) in ranges {
+            let flags =
+                libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_PRIVATE | options.flags();
+            let mapping =
+                MmapRegion::build(None, size, options.prot(), flags).map_err(Error::MmapRegion)?;
+            let mut region = GuestRegionMmap::new(mapping, guest_base)?;
+            if track_dirty_pages {
+                region.enable_dirty_page_tracking();
+            }
+            regions.push(region);
+        }
+        Self::from_regions(regions)
+    }
+
     /// Creates a container and allocates anonymous memory for guest memory regions.
     ///
     /// # Arguments
@@ -391,6 +705,47 @@ impl GuestMemoryMmap {
         )
     }
 
+    /// Creates a container and maps guest memory regions over consecutive ranges of a backing
+    /// file, starting at file offset 0.
+    ///
+    /// # Arguments
+    ///
+    /// * 'ranges' - Slice of (Address, Size, MapOptions) tuples sorted by Address.
+    /// * 'file' - The backing file. It must be at least as large as the sum of the range sizes.
+    /// * 'shared' - If set, guest writes are carried through to the file (`MAP_SHARED`);
+    ///              otherwise the file is mapped copy-on-write (`MAP_PRIVATE`).
+    /// * 'track_dirty_pages' - Whether or not dirty page tracking is enabled.
+    pub fn from_ranges_with_backing_file(
+        ranges: &[(GuestAddress, usize, MapOptions)],
+        file: Arc<File>,
+        shared: bool,
+        track_dirty_pages: bool,
+    ) -> result::Result<Self, Error> {
+        let share_flags = if shared {
+            libc::MAP_SHARED
+        } else {
+            libc::MAP_PRIVATE
+        };
+        let mut offset = 0;
+        let mut regions = Vec::with_capacity(ranges.len());
+        for &(guest_base, size, options) in ranges {
+            let mapping = MmapRegion::build(
+                Some(FileOffset::from_arc(file.clone(), offset)),
+                size,
+                options.prot(),
+                libc::MAP_NORESERVE | share_flags | options.flags(),
+            )
+            .map_err(Error::MmapRegion)?;
+            let mut region = GuestRegionMmap::new(mapping, guest_base)?;
+            if track_dirty_pages {
+                region.enable_dirty_page_tracking();
+            }
+            regions.push(region);
+            offset += size as u64;
+        }
+        Self::from_regions(regions)
+    }
+
     /// Creates a new `GuestMemoryMmap` from a vector of regions.
     ///
     /// # Arguments
@@ -457,6 +812,98 @@ impl GuestMemoryMmap {
         Self::from_arc_regions(regions)
     }
 
+    /// Grow the file-backed region starting at `base` to `new_size` bytes and return a new
+    /// `GuestMemoryMmap`.
+    ///
+    /// The backing file is extended if needed and the added range is mapped as a new region
+    /// right after the existing one, so host addresses of the existing mapping stay valid.
+    /// The size of a region that was already grown includes the regions added for it, i.e.
+    /// the regions following it both in guest memory and in its backing file.
+    /// The added guest range must not overlap other regions, and the added file range must not
+    /// be mapped by other regions. Shrinking is not supported; use `remove_region()` instead.
+    ///
+    /// # Arguments
+    /// * `base` - base address of the region to be grown
+    /// * `new_size` - size of the region after growing it
+    pub fn resize_region(
+        &self,
+        base: GuestAddress,
+        new_size: usize,
+    ) -> result::Result<GuestMemoryMmap, Error> {
+        let region = self
+            .regions
+            .iter()
+            .find(|region| region.start_addr() == base)
+            .ok_or(Error::InvalidGuestRegion)?;
+        let mut old_size = region.len() as usize;
+        if let Some(file_offset) = region.file_offset() {
+            while let Some(extension) = self.regions.iter().find(|other| {
+                other.start_addr() == base.unchecked_add(old_size as u64)
+                    && other.file_offset().map_or(false, |other_offset| {
+                        Arc::ptr_eq(other_offset.arc(), file_offset.arc())
+                            && other_offset.start() == file_offset.start() + old_size as u64
+                    })
+            }) {
+                old_size += extension.len() as usize;
+            }
+        }
+        if new_size < old_size {
+            return Err(Error::InvalidGuestRegion);
+        }
+        if new_size == old_size {
+            return Ok(self.clone());
+        }
+        let file_offset = region.file_offset().ok_or(Error::InvalidGuestRegion)?;
+        let ext_base = base.unchecked_add(old_size as u64);
+        let ext_last = base.unchecked_add(new_size as u64 - 1);
+        if self
+            .regions
+            .iter()
+            .any(|other| other.start_addr() >= ext_base && other.start_addr() <= ext_last)
+        {
+            return Err(Error::MemoryRegionOverlap);
+        }
+        let ext_start = file_offset.start() + old_size as u64;
+        let ext_end = file_offset.start() + new_size as u64;
+
+        let ext_file = file_offset.file();
+        let ext_meta = ext_file
+            .metadata()
+            .map_err(|err| Error::MmapRegion(MmapRegionError::Mmap(err)))?;
+        // Growing into a file range that another region maps would alias guest memory.
+        for other in self.regions.iter() {
+            let other_offset = match other.file_offset() {
+                Some(other_offset) => other_offset,
+                None => continue,
+            };
+            let same_file = other_offset
+                .file()
+                .metadata()
+                .map(|meta| meta.dev() == ext_meta.dev() && meta.ino() == ext_meta.ino())
+                .unwrap_or(false);
+            let other_end = other_offset.start() + other.len();
+            if same_file && other_offset.start() < ext_end && ext_start < other_end {
+                return Err(Error::MemoryRegionOverlap);
+            }
+        }
+
+        if ext_meta.len() < ext_end {
+            ext_file
+                .set_len(ext_end)
+                .map_err(|err| Error::MmapRegion(MmapRegionError::Mmap(err)))?;
+        }
+        let mapping = MmapRegion::build(
+            Some(FileOffset::from_arc(file_offset.arc().clone(), ext_start)),
+            new_size - old_size,
+            region.protection(),
+            region.flags(),
+        )
+        .map_err(Error::MmapRegion)?;
+        let extension = GuestRegionMmap::new(mapping, ext_base)?;
+
+        self.insert_region(extension)
+    }
+
     /// Remove a region into the `GuestMemoryMmap` object and return a new `GuestMemoryMmap`
     /// on success, together with the removed region.
     ///
@@ -479,6 +926,230 @@ impl GuestMemoryMmap {
         Err(Error::InvalidGuestRegion)
     }
 
+    /// Creates a copy-on-write clone of the guest memory, e.g. to fork a microVM.
+    ///
+    /// Every region of the clone is a private mapping of the file range backing the
+    /// corresponding region of `self`, so the clone initially shares all pages with `self` and
+    /// only gets its own copy of the pages it writes. Writes done by `self` after the clone
+    /// was created may or may not be visible through the clone, so `self` should not be
+    /// modified while clones are in use.
+    ///
+    /// All regions must be shared file mappings (e.g. memfd or hugetlbfs backed); anonymous or
+    /// private regions can't be cloned and yield `Error::InvalidGuestRegion`.
+    pub fn clone_cow(&self) -> result::Result<GuestMemoryMmap, Error> {
+        let regions = self
+            .regions
+            .iter()
+            .map(|region| {
+                let file_offset = match region.file_offset() {
+                    Some(file_offset) if region.flags() & libc::MAP_SHARED != 0 => {
+                        file_offset.clone()
+                    }
+                    _ => return Err(Error::InvalidGuestRegion),
+                };
+                let mapping = MmapRegion::build(
+                    Some(file_offset),
+                    region.len() as usize,
+                    region.protection(),
+                    libc::MAP_NORESERVE | libc::MAP_PRIVATE,
+                )
+                .map_err(Error::MmapRegion)?;
+                let mut clone = GuestRegionMmap::new(mapping, region.start_addr())?;
+                if region.dirty_bitmap().is_some() {
+                    clone.enable_dirty_page_tracking();
+                }
+                Ok(clone)
+            })
+            .collect::<result::Result<Vec<_>, Error>>()?;
+        Self::from_regions(regions)
+    }
+
+    /// Returns a view over the `len` bytes of guest memory starting at `addr`.
+    ///
+    /// The view shares the mapping of the region containing the window, keeping it alive
+    /// even if the region is later removed from `self`, and only allows accesses within the
+    /// window. The window must not span multiple regions.
+    pub fn subslice(
+        &self,
+        addr: GuestAddress,
+        len: usize,
+    ) -> result::Result<GuestMemoryWindow, guest_memory::Error> {
+        let (region, start) = self
+            .regions
+            .iter()
+            .find_map(|region| region.to_region_addr(addr).map(|start| (region, start)))
+            .ok_or(guest_memory::Error::InvalidGuestAddress(addr))?;
+        GuestMemoryWindow::new(region.clone(), start, len)
+    }
+
+    /// Checks that all the `len` bytes of guest memory starting at `addr` are backed by
+    /// regions of `self`. The range may span adjacent regions. `addr` must be backed even if
+    /// `len` is 0.
+    pub fn check_range(&self, addr: GuestAddress, len: usize) -> guest_memory::Result<()> {
+        let mut addr = addr;
+        let mut remaining = len as GuestUsize;
+        loop {
+            let region = self
+                .find_region(addr)
+                .ok_or(guest_memory::Error::InvalidGuestAddress(addr))?;
+            // `addr` is within the region, so this can't underflow.
+            let available = region.len() - (addr.raw_value() - region.start_addr().raw_value());
+            if available >= remaining {
+                return Ok(());
+            }
+            remaining -= available;
+            addr = addr
+                .checked_add(available)
+                .ok_or(guest_memory::Error::InvalidGuestAddress(addr))?;
+        }
+    }
+
+    /// Checks that all the `len` bytes of guest memory starting at `addr` are backed by
+    /// regions of `self` which allow writes, for callers writing to guest memory through host
+    /// pointers rather than through the `Bytes` accessors.
+    pub fn check_writable_range(&self, addr: GuestAddress, len: usize) -> guest_memory::Result<()> {
+        self.check_range(addr, len)?;
+        // The range was validated above, so it doesn't wrap around.
+        let end = addr.raw_value() + len as GuestUsize;
+        self.regions
+            .iter()
+            .filter(|region| region.start_addr().raw_value() < end && addr <= region.last_addr())
+            .try_for_each(|region| region.check_writable())
+    }
+
+    /// Returns a host pointer to the `len` bytes of guest memory starting at `addr`, for devices
+    /// keeping pointers into buffers described by guest-provided descriptors.
+    ///
+    /// The whole range is validated here, once, so that accesses within it through the returned
+    /// pointer don't need further checks. The range must lie within a single region, since the
+    /// host mappings of adjacent regions aren't contiguous.
+    pub fn checked_host_address(
+        &self,
+        addr: GuestAddress,
+        len: usize,
+    ) -> guest_memory::Result<*mut u8> {
+        Ok(self.get_slice(addr, len)?.as_ptr())
+    }
+
+    /// Like `checked_host_address()`, but also checks that the range can be written to, since
+    /// writes through the returned pointer bypass the protection checks of the accessors.
+    pub fn checked_writable_host_address(
+        &self,
+        addr: GuestAddress,
+        len: usize,
+    ) -> guest_memory::Result<*mut u8> {
+        self.check_writable_range(addr, len)?;
+        self.checked_host_address(addr, len)
+    }
+
+    /// Scans the `len` bytes of guest memory starting at `addr` for pages holding only zeros.
+    ///
+    /// Bit `n` of the returned bitmap is set if the `n`-th `page_size` bytes of the range are
+    /// all zero, a trailing partial page being scanned like a full one. The range may span
+    /// adjacent regions. Guest memory is scanned in place, without being copied out first.
+    /// `page_size` must be a power of two; other values fail with `EINVAL`.
+    pub fn scan_zero_pages(
+        &self,
+        addr: GuestAddress,
+        len: usize,
+        page_size: usize,
+    ) -> guest_memory::Result<Bitmap> {
+        if !page_size.is_power_of_two() {
+            return Err(guest_memory::Error::IOError(io::Error::from_raw_os_error(
+                libc::EINVAL,
+            )));
+        }
+        self.check_range(addr, len)?;
+        let page_count = (len + page_size - 1) / page_size;
+        let zero_pages = Bitmap::new(page_count * page_size, page_size);
+        for page_offset in (0..len).step_by(page_size) {
+            let page_len = std::cmp::min(page_size, len - page_offset);
+            // The range was checked, so this can't overflow.
+            if self.is_zero(addr.unchecked_add(page_offset as GuestUsize), page_len)? {
+                zero_pages.set_addr_range(page_offset, 1);
+            }
+        }
+        Ok(zero_pages)
+    }
+
+    // Returns whether the `len` bytes of guest memory starting at `addr` are all zero.
+    fn is_zero(&self, mut addr: GuestAddress, len: usize) -> guest_memory::Result<bool> {
+        let mut remaining = len;
+        while remaining > 0 {
+            let region = self
+                .find_region(addr)
+                .ok_or(guest_memory::Error::InvalidGuestAddress(addr))?;
+            let offset = (addr.raw_value() - region.start_addr().raw_value()) as usize;
+            let chunk_len = std::cmp::min(remaining, region.len() as usize - offset);
+            if !region.is_zero(offset, chunk_len) {
+                return Ok(false);
+            }
+            remaining -= chunk_len;
+            addr = addr.unchecked_add(chunk_len as GuestUsize);
+        }
+        Ok(true)
+    }
+
+    /// Report how many pages of guest memory are resident in memory, across all regions.
+    pub fn resident_pages(&self) -> io::Result<PageResidency> {
+        self.regions
+            .iter()
+            .try_fold(PageResidency::default(), |acc, region| {
+                let region_residency = region.resident_pages()?;
+                Ok(PageResidency {
+                    resident: acc.resident + region_residency.resident,
+                    total: acc.total + region_residency.total,
+                })
+            })
+    }
+
+    /// Report the regions whose pages are not all resident in memory, along with their
+    /// residency.
+    ///
+    /// Meant to diagnose regions mapped with `MapOptions::populate` that the kernel could only
+    /// partially populate.
+    pub fn unpopulated_regions(&self) -> io::Result<Vec<(GuestAddress, PageResidency)>> {
+        let mut unpopulated = Vec::new();
+        for region in self.regions.iter() {
+            let residency = region.resident_pages()?;
+            if residency.resident < residency.total {
+                unpopulated.push((region.start_addr(), residency));
+            }
+        }
+        Ok(unpopulated)
+    }
+
+    /// Bind all guest memory to the NUMA `node`.
+    pub fn bind_numa_node(&self, node: u32) -> io::Result<()> {
+        self.regions
+            .iter()
+            .try_for_each(|region| region.bind_numa_node(node))
+    }
+
+    /// Lock all guest memory in RAM, see `GuestRegionMmap::lock`.
+    pub fn lock(&self, on_fault: bool) -> io::Result<()> {
+        self.regions
+            .iter()
+            .try_for_each(|region| region.lock(on_fault))
+    }
+
+    /// Discard the `len` bytes of guest memory starting at `addr`, see
+    /// `GuestRegionMmap::discard`. The range can span multiple regions.
+    pub fn discard_range(&self, mut addr: GuestAddress, len: usize) -> io::Result<()> {
+        let mut remaining = len;
+        while remaining > 0 {
+            let region = self
+                .find_region(addr)
+                .ok_or_else(|| io::Error::from_raw_os_error(libc::EFAULT))?;
+            let offset = (addr.raw_value() - region.start_addr().raw_value()) as usize;
+            let chunk_len = std::cmp::min(remaining, region.len() as usize - offset);
+            region.discard(offset, chunk_len)?;
+            remaining -= chunk_len;
+            addr = addr.unchecked_add(chunk_len as GuestUsize);
+        }
+        Ok(())
+    }
+
     /// Return true if dirty page tracking is enabled for `GuestMemoryMmap`, and else otherwise.
     pub fn is_dirty_tracking_enabled(&self) -> bool {
         self.regions.iter().all(|r| r.dirty_bitmap().is_some())
@@ -518,6 +1189,133 @@ impl GuestMemoryMmap {
         )
     }
 
+    /// Reads from `src`, starting at file offset `offset`, into the guest memory ranges
+    /// described by `ranges` as (address, length) tuples, using `preadv()` calls of at most
+    /// `IOV_MAX` segments each. Ranges may span several memory regions.
+    ///
+    /// Returns the number of bytes read, which, like for `preadv()`, can be smaller than the
+    /// total length of the ranges.
+    /// Fails without reading anything if a range falls in a read-only region.
+    pub fn read_vectored_at_addr<F: AsRawFd>(
+        &self,
+        ranges: &[(GuestAddress, usize)],
+        src: &F,
+        offset: u64,
+    ) -> result::Result<usize, guest_memory::Error> {
+        let segments = self.host_segments(ranges)?;
+        for (region, _, _) in segments.iter() {
+            region.check_writable()?;
+        }
+        let mut total = 0;
+        for chunk in segments.chunks(IOV_MAX) {
+            let iovecs = chunk.iter().map(|s| s.2).collect::<Vec<_>>();
+            // Safe because every iovec points to a range of host memory mapped for the guest,
+            // which the kernel may write to.
+            let ret = unsafe {
+                libc::preadv(
+                    src.as_raw_fd(),
+                    iovecs.as_ptr(),
+                    iovecs.len() as libc::c_int,
+                    (offset + total as u64) as libc::off_t,
+                )
+            };
+            if ret < 0 {
+                if total > 0 {
+                    break;
+                }
+                return Err(guest_memory::Error::IOError(io::Error::last_os_error()));
+            }
+
+            // Mark the pages which were written by the read as dirty.
+            let mut remaining = ret as usize;
+            for (region, region_offset, iovec) in chunk {
+                if remaining == 0 {
+                    break;
+                }
+                let len = std::cmp::min(remaining, iovec.iov_len);
+                region.mark_dirty_pages(*region_offset, len);
+                remaining -= len;
+            }
+
+            total += ret as usize;
+            if (ret as usize) < iovecs.iter().map(|iovec| iovec.iov_len).sum::<usize>() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Writes the guest memory ranges described by `ranges` as (address, length) tuples to
+    /// `dst`, starting at file offset `offset`, using `pwritev()` calls of at most `IOV_MAX`
+    /// segments each. Ranges may span several memory regions.
+    ///
+    /// Returns the number of bytes written, which, like for `pwritev()`, can be smaller than
+    /// the total length of the ranges.
+    pub fn write_vectored_at_addr<F: AsRawFd>(
+        &self,
+        ranges: &[(GuestAddress, usize)],
+        dst: &F,
+        offset: u64,
+    ) -> result::Result<usize, guest_memory::Error> {
+        let segments = self.host_segments(ranges)?;
+        let mut total = 0;
+        for chunk in segments.chunks(IOV_MAX) {
+            let iovecs = chunk.iter().map(|s| s.2).collect::<Vec<_>>();
+            // Safe because every iovec points to a range of host memory mapped for the guest,
+            // which the kernel only reads from.
+            let ret = unsafe {
+                libc::pwritev(
+                    dst.as_raw_fd(),
+                    iovecs.as_ptr(),
+                    iovecs.len() as libc::c_int,
+                    (offset + total as u64) as libc::off_t,
+                )
+            };
+            if ret < 0 {
+                if total > 0 {
+                    break;
+                }
+                return Err(guest_memory::Error::IOError(io::Error::last_os_error()));
+            }
+
+            total += ret as usize;
+            if (ret as usize) < iovecs.iter().map(|iovec| iovec.iov_len).sum::<usize>() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    // Translates guest memory ranges to host memory segments, splitting the ranges which
+    // cross region boundaries. Each segment is returned along with its region and the
+    // offset of the segment within the region.
+    fn host_segments(
+        &self,
+        ranges: &[(GuestAddress, usize)],
+    ) -> result::Result<Vec<(&GuestRegionMmap, usize, libc::iovec)>, guest_memory::Error> {
+        let mut segments = Vec::with_capacity(ranges.len());
+        for &(mut addr, mut len) in ranges {
+            while len > 0 {
+                let (region, region_addr) = self
+                    .to_region_addr(addr)
+                    .ok_or(guest_memory::Error::InvalidGuestAddress(addr))?;
+                let region_offset = region_addr.raw_value() as usize;
+                let segment_len = std::cmp::min(len, region.len() as usize - region_offset);
+                segments.push((
+                    region,
+                    region_offset,
+                    libc::iovec {
+                        iov_base: region.get_host_address(region_addr)? as *mut libc::c_void,
+                        iov_len: segment_len,
+                    },
+                ));
+                addr = addr.unchecked_add(segment_len as u64);
+                len -= segment_len;
+            }
+        }
+        Ok(segments)
+    }
+
     pub fn read_exact_from<F>(
         &self,
         addr: GuestAddress,
@@ -588,6 +1386,105 @@ impl GuestMemory for GuestMemoryMmap {
     }
 }
 
+/// Bounds-checked view over a contiguous window of a guest memory region.
+///
+/// Offsets passed to the accessors are relative to the start of the window. Writes go
+/// through the underlying region, so they are tracked in its dirty bitmap.
+#[derive(Clone, Debug)]
+pub struct GuestMemoryWindow {
+    region: Arc<GuestRegionMmap>,
+    start: MemoryRegionAddress,
+    len: usize,
+}
+
+impl GuestMemoryWindow {
+    fn new(
+        region: Arc<GuestRegionMmap>,
+        start: MemoryRegionAddress,
+        len: usize,
+    ) -> result::Result<Self, guest_memory::Error> {
+        let end = start
+            .checked_add(len as u64)
+            .ok_or_else(|| guest_memory::Error::InvalidGuestAddress(region.start_addr()))?;
+        if end.raw_value() > region.len() {
+            return Err(guest_memory::Error::InvalidGuestAddress(
+                region.start_addr().unchecked_add(end.raw_value()),
+            ));
+        }
+        Ok(GuestMemoryWindow { region, start, len })
+    }
+
+    /// Guest physical address of the first byte of the window.
+    pub fn start_addr(&self) -> GuestAddress {
+        self.region
+            .start_addr()
+            .unchecked_add(self.start.raw_value())
+    }
+
+    /// Size of the window in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if the window is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a narrower view over `len` bytes starting at `offset` within this window.
+    pub fn subslice(
+        &self,
+        offset: usize,
+        len: usize,
+    ) -> result::Result<GuestMemoryWindow, guest_memory::Error> {
+        let start = self.region_addr(offset, len)?;
+        GuestMemoryWindow::new(self.region.clone(), start, len)
+    }
+
+    /// Returns a volatile slice covering the whole window.
+    pub fn as_volatile_slice(&self) -> guest_memory::Result<VolatileSlice> {
+        self.region.get_slice(self.start, self.len)
+    }
+
+    /// Reads `buf.len()` bytes starting at `offset` within the window.
+    pub fn read_slice(&self, buf: &mut [u8], offset: usize) -> guest_memory::Result<()> {
+        let addr = self.region_addr(offset, buf.len())?;
+        self.region.read_slice(buf, addr)
+    }
+
+    /// Writes `buf` starting at `offset` within the window.
+    pub fn write_slice(&self, buf: &[u8], offset: usize) -> guest_memory::Result<()> {
+        let addr = self.region_addr(offset, buf.len())?;
+        self.region.write_slice(buf, addr)
+    }
+
+    /// Reads an object starting at `offset` within the window.
+    pub fn read_obj<T: ByteValued>(&self, offset: usize) -> guest_memory::Result<T> {
+        let addr = self.region_addr(offset, std::mem::size_of::<T>())?;
+        self.region.read_obj(addr)
+    }
+
+    /// Writes an object starting at `offset` within the window.
+    pub fn write_obj<T: ByteValued>(&self, val: T, offset: usize) -> guest_memory::Result<()> {
+        let addr = self.region_addr(offset, std::mem::size_of::<T>())?;
+        self.region.write_obj(val, addr)
+    }
+
+    // Translates `offset` into a region address, checking that `count` bytes fit in the window.
+    fn region_addr(
+        &self,
+        offset: usize,
+        count: usize,
+    ) -> guest_memory::Result<MemoryRegionAddress> {
+        match offset.checked_add(count) {
+            Some(end) if end <= self.len => Ok(self.start.unchecked_add(offset as u64)),
+            _ => Err(guest_memory::Error::InvalidGuestAddress(
+                self.start_addr().unchecked_add(offset as u64),
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate vmm_sys_util;
@@ -595,7 +1492,7 @@ mod tests {
     use super::*;
     use vm_memory_upstream::GuestAddressSpace;
 
-    use std::fs::File;
+    use std::io::{Seek, SeekFrom};
     use std::mem;
     use std::path::Path;
     use vmm_sys_util::tempfile::TempFile;
@@ -626,6 +1523,67 @@ mod tests {
         assert!(mmap.dirty_bitmap().unwrap().is_addr_set(128));
     }
 
+    #[test]
+    fn test_set_protection() {
+        let region =
+            GuestRegionMmap::new(MmapRegion::new(0x1000).unwrap(), GuestAddress(0x0)).unwrap();
+        assert_eq!(region.protection(), libc::PROT_READ | libc::PROT_WRITE);
+
+        region.set_protection(libc::PROT_READ).unwrap();
+        assert_eq!(region.protection(), libc::PROT_READ);
+        assert_eq!(region.read_obj::<u8>(MemoryRegionAddress(0)).unwrap(), 0);
+
+        // Every write accessor fails on a read-only region, instead of faulting.
+        let is_eacces = |err: guest_memory::Error| match err {
+            guest_memory::Error::IOError(e) => e.raw_os_error() == Some(libc::EACCES),
+            _ => false,
+        };
+        assert!(is_eacces(
+            region.write(&[1u8], MemoryRegionAddress(0)).unwrap_err()
+        ));
+        assert!(is_eacces(
+            region
+                .write_slice(&[1u8], MemoryRegionAddress(0))
+                .unwrap_err()
+        ));
+        assert!(is_eacces(
+            region.write_obj(1u8, MemoryRegionAddress(0)).unwrap_err()
+        ));
+        assert!(is_eacces(
+            region
+                .read_from(MemoryRegionAddress(0), &mut &[1u8][..], 1)
+                .unwrap_err()
+        ));
+        assert!(is_eacces(
+            region
+                .read_exact_from(MemoryRegionAddress(0), &mut &[1u8][..], 1)
+                .unwrap_err()
+        ));
+        assert_eq!(region.read_obj::<u8>(MemoryRegionAddress(0)).unwrap(), 0);
+
+        region
+            .set_protection(libc::PROT_READ | libc::PROT_WRITE)
+            .unwrap();
+        assert_eq!(region.protection(), libc::PROT_READ | libc::PROT_WRITE);
+        region.write_obj(1u8, MemoryRegionAddress(0)).unwrap();
+        assert_eq!(region.read_obj::<u8>(MemoryRegionAddress(0)).unwrap(), 1);
+
+        // Invalid protection flags are rejected, and the protection is left unchanged.
+        for prot in [
+            -1,
+            libc::PROT_NONE,
+            libc::PROT_WRITE,
+            libc::PROT_READ | libc::PROT_EXEC,
+            libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+        ]
+        .iter()
+        {
+            let err = region.set_protection(*prot).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+            assert_eq!(region.protection(), libc::PROT_READ | libc::PROT_WRITE);
+        }
+    }
+
     #[test]
     fn test_bitmap_update_on_write() {
         let page_size = 4096 as usize;
@@ -1443,6 +2401,265 @@ mod tests {
         assert_eq!(region.file_offset().unwrap().start(), offset);
     }
 
+    #[test]
+    fn test_from_ranges_with_backing_file() {
+        let region_size = 0x1000;
+        let regions = vec![
+            (GuestAddress(0x0), region_size, MapOptions::default()),
+            (GuestAddress(0x10_0000), region_size, MapOptions::default()),
+        ];
+        let mut f = TempFile::new().unwrap().into_file();
+        f.set_len(2 * region_size as u64).unwrap();
+        f.write_all(&[0xAAu8; 0x1000]).unwrap();
+        f.write_all(&[0xBBu8; 0x1000]).unwrap();
+        let f = Arc::new(f);
+
+        // Private mappings don't carry guest writes through to the file.
+        let gm = GuestMemoryMmap::from_ranges_with_backing_file(&regions, f.clone(), false, true)
+            .unwrap();
+        assert_eq!(gm.num_regions(), 2);
+        assert!(gm.is_dirty_tracking_enabled());
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x0)).unwrap(), 0xAA);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x10_0000)).unwrap(), 0xBB);
+        let region = gm.find_region(GuestAddress(0x10_0000)).unwrap();
+        assert_eq!(region.file_offset().unwrap().start(), region_size as u64);
+        gm.write_obj(0xCCu8, GuestAddress(0x0)).unwrap();
+        let mut buf = [0u8; 1];
+        (&*f).seek(SeekFrom::Start(0)).unwrap();
+        (&*f).read_exact(&mut buf).unwrap();
+        assert_eq!(buf[0], 0xAA);
+
+        // Shared mappings do.
+        let gm = GuestMemoryMmap::from_ranges_with_backing_file(&regions, f.clone(), true, false)
+            .unwrap();
+        assert!(!gm.is_dirty_tracking_enabled());
+        gm.write_obj(0xCCu8, GuestAddress(0x0)).unwrap();
+        (&*f).seek(SeekFrom::Start(0)).unwrap();
+        (&*f).read_exact(&mut buf).unwrap();
+        assert_eq!(buf[0], 0xCC);
+    }
+
+    #[test]
+    fn test_resize_region() {
+        let region_size = 0x1000;
+        let regions = vec![
+            (GuestAddress(0x0), region_size, MapOptions::default()),
+            (GuestAddress(0x10_0000), region_size, MapOptions::default()),
+        ];
+        let f = Arc::new(TempFile::new().unwrap().into_file());
+        f.set_len(2 * region_size as u64).unwrap();
+        let gm = GuestMemoryMmap::from_ranges_with_backing_file(&regions, f.clone(), true, true)
+            .unwrap();
+
+        // Only existing regions can be resized, and only grown.
+        assert!(gm.resize_region(GuestAddress(0x800), 0x2000).is_err());
+        assert!(gm.resize_region(GuestAddress(0x0), 0x800).is_err());
+        assert_eq!(
+            gm.resize_region(GuestAddress(0x0), 0x1000)
+                .unwrap()
+                .num_regions(),
+            2
+        );
+        // The first region can't grow into the file range backing the second one.
+        match gm.resize_region(GuestAddress(0x0), 0x2000) {
+            Err(Error::MemoryRegionOverlap) => (),
+            _ => panic!("Expected MemoryRegionOverlap"),
+        }
+        // Nor into the guest range of another region.
+        let adjacent = GuestMemoryMmap::from_ranges_with_files(
+            &[
+                (
+                    GuestAddress(0x0),
+                    region_size,
+                    Some(FileOffset::new(TempFile::new().unwrap().into_file(), 0)),
+                ),
+                (
+                    GuestAddress(0x1000),
+                    region_size,
+                    Some(FileOffset::new(TempFile::new().unwrap().into_file(), 0)),
+                ),
+            ],
+            false,
+        )
+        .unwrap();
+        match adjacent.resize_region(GuestAddress(0x0), 0x2000) {
+            Err(Error::MemoryRegionOverlap) => (),
+            _ => panic!("Expected MemoryRegionOverlap"),
+        }
+        // Anonymous regions can't be resized.
+        let anon = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), region_size)]).unwrap();
+        assert!(anon.resize_region(GuestAddress(0x0), 0x2000).is_err());
+
+        // Growing the last region extends the backing file.
+        gm.write_obj(0xAAu8, GuestAddress(0x10_0000)).unwrap();
+        let grown = gm.resize_region(GuestAddress(0x10_0000), 0x3000).unwrap();
+        assert_eq!(f.metadata().unwrap().len(), 0x4000);
+        assert_eq!(grown.num_regions(), 3);
+        assert!(grown.is_dirty_tracking_enabled());
+        assert!(grown.address_in_range(GuestAddress(0x10_2fff)));
+        assert!(!grown.address_in_range(GuestAddress(0x10_3000)));
+        assert_eq!(grown.read_obj::<u8>(GuestAddress(0x10_0000)).unwrap(), 0xAA);
+        // Accesses can span the original region and its extension.
+        grown
+            .write_obj(0xBBBB_BBBBu32, GuestAddress(0x10_0ffe))
+            .unwrap();
+        assert_eq!(
+            grown.read_obj::<u32>(GuestAddress(0x10_0ffe)).unwrap(),
+            0xBBBB_BBBB
+        );
+
+        // A grown region keeps growing from the end of its last extension.
+        assert!(grown
+            .resize_region(GuestAddress(0x10_0000), 0x2000)
+            .is_err());
+        assert_eq!(
+            grown
+                .resize_region(GuestAddress(0x10_0000), 0x3000)
+                .unwrap()
+                .num_regions(),
+            3
+        );
+        let regrown = grown
+            .resize_region(GuestAddress(0x10_0000), 0x5000)
+            .unwrap();
+        assert_eq!(f.metadata().unwrap().len(), 0x6000);
+        assert_eq!(regrown.num_regions(), 4);
+        assert!(regrown.address_in_range(GuestAddress(0x10_4fff)));
+        assert!(!regrown.address_in_range(GuestAddress(0x10_5000)));
+        assert_eq!(
+            regrown.read_obj::<u32>(GuestAddress(0x10_0ffe)).unwrap(),
+            0xBBBB_BBBB
+        );
+        regrown
+            .write_obj(0xCCCC_CCCCu32, GuestAddress(0x10_2ffe))
+            .unwrap();
+        assert_eq!(
+            regrown.read_obj::<u32>(GuestAddress(0x10_2ffe)).unwrap(),
+            0xCCCC_CCCC
+        );
+    }
+
+    #[test]
+    fn test_clone_cow() {
+        let region_size = 0x1000;
+        let regions = vec![
+            (GuestAddress(0x0), region_size, MapOptions::default()),
+            (GuestAddress(0x10_0000), region_size, MapOptions::default()),
+        ];
+        let f = TempFile::new().unwrap().into_file();
+        f.set_len(2 * region_size as u64).unwrap();
+        let f = Arc::new(f);
+
+        let parent =
+            GuestMemoryMmap::from_ranges_with_backing_file(&regions, f.clone(), true, true)
+                .unwrap();
+        parent.write_obj(0xAAu8, GuestAddress(0x0)).unwrap();
+        parent.write_obj(0xBBu8, GuestAddress(0x10_0000)).unwrap();
+
+        let child1 = parent.clone_cow().unwrap();
+        let child2 = parent.clone_cow().unwrap();
+        assert_eq!(child1.num_regions(), 2);
+        assert!(child1.is_dirty_tracking_enabled());
+
+        // Children see the parent's memory.
+        assert_eq!(child1.read_obj::<u8>(GuestAddress(0x0)).unwrap(), 0xAA);
+        assert_eq!(
+            child2.read_obj::<u8>(GuestAddress(0x10_0000)).unwrap(),
+            0xBB
+        );
+
+        // Writes in a child are private to it.
+        child1.write_obj(0xCCu8, GuestAddress(0x0)).unwrap();
+        assert_eq!(child1.read_obj::<u8>(GuestAddress(0x0)).unwrap(), 0xCC);
+        assert_eq!(child2.read_obj::<u8>(GuestAddress(0x0)).unwrap(), 0xAA);
+        assert_eq!(parent.read_obj::<u8>(GuestAddress(0x0)).unwrap(), 0xAA);
+        let mut buf = [0u8; 1];
+        (&*f).seek(SeekFrom::Start(0)).unwrap();
+        (&*f).read_exact(&mut buf).unwrap();
+        assert_eq!(buf[0], 0xAA);
+
+        // Anonymous and private file mappings can't be cloned.
+        let anon = GuestMemoryMmap::from_ranges_with_options(&regions, false).unwrap();
+        assert_eq!(
+            format!("{:?}", anon.clone_cow().err().unwrap()),
+            format!("{:?}", Error::InvalidGuestRegion)
+        );
+        let private =
+            GuestMemoryMmap::from_ranges_with_backing_file(&regions, f, false, false).unwrap();
+        assert!(private.clone_cow().is_err());
+    }
+
+    #[test]
+    fn test_vectored_at_addr() {
+        let region_size = 0x1000;
+        // Two adjacent regions, so that a range can span both of them.
+        let gm = GuestMemoryMmap::from_ranges_with_tracking(&[
+            (GuestAddress(0x0), region_size),
+            (GuestAddress(0x1000), region_size),
+        ])
+        .unwrap();
+
+        let mut f = TempFile::new().unwrap().into_file();
+        let data = (0..0x2000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        f.write_all(&data).unwrap();
+
+        let ranges = [(GuestAddress(0x800), 0x1000), (GuestAddress(0x1c00), 0x100)];
+        assert_eq!(gm.read_vectored_at_addr(&ranges, &f, 0x10).unwrap(), 0x1100);
+
+        let mut buf = vec![0u8; 0x1000];
+        gm.read_slice(&mut buf, GuestAddress(0x800)).unwrap();
+        assert_eq!(buf[..], data[0x10..0x1010]);
+        let mut buf = vec![0u8; 0x100];
+        gm.read_slice(&mut buf, GuestAddress(0x1c00)).unwrap();
+        assert_eq!(buf[..], data[0x1010..0x1110]);
+
+        // The pages written by the read are dirty.
+        let first = gm.find_region(GuestAddress(0x0)).unwrap();
+        assert!(!first.dirty_bitmap().unwrap().is_addr_set(0x0));
+        assert!(first.dirty_bitmap().unwrap().is_addr_set(0x800));
+        let second = gm.find_region(GuestAddress(0x1000)).unwrap();
+        assert!(second.dirty_bitmap().unwrap().is_addr_set(0x0));
+        assert!(second.dirty_bitmap().unwrap().is_addr_set(0xc00));
+
+        // Write the same ranges back to another file.
+        let dst = TempFile::new().unwrap().into_file();
+        assert_eq!(gm.write_vectored_at_addr(&ranges, &dst, 0).unwrap(), 0x1100);
+        let mut written = Vec::new();
+        (&dst).read_to_end(&mut written).unwrap();
+        assert_eq!(written[..], data[0x10..0x1110]);
+
+        // Ranges outside of guest memory are rejected.
+        assert!(gm
+            .read_vectored_at_addr(&[(GuestAddress(0x1800), 0x1000)], &f, 0)
+            .is_err());
+        assert!(gm
+            .write_vectored_at_addr(&[(GuestAddress(0x3000), 0x10)], &dst, 0)
+            .is_err());
+
+        // More ranges than fit in a single call are transferred across several calls.
+        let ranges = (0..0x2000)
+            .step_by(2)
+            .map(|addr| (GuestAddress(addr), 1))
+            .collect::<Vec<_>>();
+        assert!(ranges.len() > IOV_MAX);
+        assert_eq!(
+            gm.read_vectored_at_addr(&ranges, &f, 0).unwrap(),
+            ranges.len()
+        );
+        assert_eq!(
+            gm.read_obj::<u8>(GuestAddress(0x1ffe)).unwrap(),
+            data[0xfff]
+        );
+        let dst = TempFile::new().unwrap().into_file();
+        assert_eq!(
+            gm.write_vectored_at_addr(&ranges, &dst, 0).unwrap(),
+            ranges.len()
+        );
+        let mut written = Vec::new();
+        (&dst).read_to_end(&mut written).unwrap();
+        assert_eq!(written[..], data[..ranges.len()]);
+    }
+
     #[test]
     fn test_mmap_insert_region() {
         let region_size = 0x1000;
@@ -1523,4 +2740,387 @@ mod tests {
         gm.regions.append(&mut dirty_tracking_gm.regions);
         assert!(!gm.is_dirty_tracking_enabled());
     }
+
+    #[test]
+    fn test_check_range() {
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x1000),
+            (GuestAddress(0x1000), 0x1000),
+            (GuestAddress(0x3000), 0x1000),
+        ])
+        .unwrap();
+
+        // Ranges can span adjacent regions, but not holes or the end of memory.
+        assert!(gm.check_range(GuestAddress(0x800), 0x1000).is_ok());
+        assert!(gm.check_range(GuestAddress(0x1800), 0x1000).is_err());
+        assert!(gm.check_range(GuestAddress(0x3800), 0x801).is_err());
+        assert!(gm.check_range(GuestAddress(0x3fff), usize::MAX).is_err());
+
+        // Empty ranges must still start in guest memory.
+        assert!(gm.check_range(GuestAddress(0x3fff), 0).is_ok());
+        assert!(gm.check_range(GuestAddress(0x2000), 0).is_err());
+        assert!(gm.check_range(GuestAddress(0x4000), 0).is_err());
+
+        // Host pointers can only cover a single region.
+        assert_eq!(
+            gm.checked_host_address(GuestAddress(0x1800), 0x800)
+                .unwrap(),
+            gm.get_host_address(GuestAddress(0x1800)).unwrap()
+        );
+        assert!(gm
+            .checked_host_address(GuestAddress(0x800), 0x1000)
+            .is_err());
+        assert!(gm
+            .checked_host_address(GuestAddress(0x3800), 0x801)
+            .is_err());
+        assert!(gm.checked_host_address(GuestAddress(0x2000), 0x10).is_err());
+    }
+
+    #[test]
+    fn test_scan_zero_pages() {
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x2000),
+            (GuestAddress(0x2000), 0x1000),
+            (GuestAddress(0x4000), 0x1000),
+        ])
+        .unwrap();
+        gm.write_obj(1u8, GuestAddress(0x1fff)).unwrap();
+        gm.write_obj(1u8, GuestAddress(0x2400)).unwrap();
+
+        let zero_pages = gm.scan_zero_pages(GuestAddress(0), 0x3000, 0x1000).unwrap();
+        assert!(zero_pages.is_bit_set(0));
+        assert!(!zero_pages.is_bit_set(1));
+        assert!(!zero_pages.is_bit_set(2));
+
+        // Pages can span adjacent regions and the last one can be partial.
+        let zero_pages = gm
+            .scan_zero_pages(GuestAddress(0x800), 0x1900, 0x1000)
+            .unwrap();
+        assert!(zero_pages.is_bit_set(0));
+        assert!(!zero_pages.is_bit_set(1));
+        let zero_pages = gm
+            .scan_zero_pages(GuestAddress(0x2800), 0x800, 0x1000)
+            .unwrap();
+        assert!(zero_pages.is_bit_set(0));
+
+        // Holes and the end of memory can't be scanned.
+        assert!(gm
+            .scan_zero_pages(GuestAddress(0x2000), 0x2000, 0x1000)
+            .is_err());
+        assert!(gm
+            .scan_zero_pages(GuestAddress(0x4800), 0x1000, 0x1000)
+            .is_err());
+
+        // Page sizes must be powers of two.
+        for &page_size in [0, 0x1800].iter() {
+            match gm.scan_zero_pages(GuestAddress(0), 0x2000, page_size) {
+                Err(guest_memory::Error::IOError(err)) => {
+                    assert_eq!(err.raw_os_error(), Some(libc::EINVAL))
+                }
+                _ => panic!("Expected EINVAL"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_subslice() {
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x1000),
+            (GuestAddress(0x1000), 0x1000),
+        ])
+        .unwrap();
+
+        // Windows can't span regions or go past the end of memory.
+        assert!(gm.subslice(GuestAddress(0x800), 0x1000).is_err());
+        assert!(gm.subslice(GuestAddress(0x2000), 0x10).is_err());
+
+        let window = gm.subslice(GuestAddress(0x1100), 0x100).unwrap();
+        assert_eq!(window.start_addr(), GuestAddress(0x1100));
+        assert_eq!(window.len(), 0x100);
+        assert_eq!(window.as_volatile_slice().unwrap().len(), 0x100);
+
+        // Accesses are relative to the window and visible through the whole memory.
+        window.write_obj(0xdead_beefu32, 0x10).unwrap();
+        assert_eq!(
+            gm.read_obj::<u32>(GuestAddress(0x1110)).unwrap(),
+            0xdead_beef
+        );
+        let mut buf = [0u8; 4];
+        window.read_slice(&mut buf, 0x10).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), 0xdead_beef);
+
+        // Accesses outside the window are rejected.
+        assert!(window.write_slice(&[0u8; 2], 0xff).is_err());
+        assert!(window.read_obj::<u64>(0xfc).is_err());
+        assert!(window.read_slice(&mut buf, usize::MAX).is_err());
+
+        // Nested windows are bounded by their parent.
+        let inner = window.subslice(0x10, 0x4).unwrap();
+        assert_eq!(inner.start_addr(), GuestAddress(0x1110));
+        assert_eq!(inner.read_obj::<u32>(0).unwrap(), 0xdead_beef);
+        assert!(inner.read_obj::<u64>(0).is_err());
+        assert!(window.subslice(0xf0, 0x20).is_err());
+
+        // The window keeps the mapping alive after the region is removed.
+        let (smaller_gm, region) = gm.remove_region(GuestAddress(0x1000), 0x1000).unwrap();
+        assert_eq!(smaller_gm.num_regions(), 1);
+        drop(region);
+        drop(gm);
+        assert_eq!(window.read_obj::<u32>(0x10).unwrap(), 0xdead_beef);
+    }
+
+    #[test]
+    fn test_bind_numa_node() {
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x1000),
+            (GuestAddress(0x10_0000), 0x1000),
+        ])
+        .unwrap();
+        // Binding to a node the host doesn't have fails.
+        assert!(gm.bind_numa_node(4095).is_err());
+        if Path::new("/sys/devices/system/node/node0").exists() {
+            gm.bind_numa_node(0).unwrap();
+            gm.write_obj(1u8, GuestAddress(0)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_lock() {
+        let page_size = 0x1000;
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(0x10_0000), page_size),
+        ])
+        .unwrap();
+
+        // Locking on fault leaves the memory unpopulated.
+        gm.lock(true).unwrap();
+        assert_eq!(gm.resident_pages().unwrap().resident, 0);
+
+        // Otherwise, all pages get populated.
+        gm.lock(false).unwrap();
+        assert_eq!(gm.resident_pages().unwrap().resident, 3);
+    }
+
+    #[test]
+    fn test_map_options() {
+        let page_size = 0x1000;
+
+        assert_eq!(MapOptions::default().flags(), 0);
+        assert_eq!(
+            MapOptions::default().prot(),
+            libc::PROT_READ | libc::PROT_WRITE
+        );
+        let options = MapOptions {
+            populate: true,
+            read_only: true,
+        };
+        assert_eq!(options.flags(), libc::MAP_POPULATE);
+        assert_eq!(options.prot(), libc::PROT_READ);
+
+        // Lazily mapped memory is reported as unpopulated.
+        let ranges = [
+            (GuestAddress(0), page_size * 2, MapOptions::default()),
+            (GuestAddress(0x10_0000), page_size, MapOptions::default()),
+        ];
+        let gm = GuestMemoryMmap::from_ranges_with_options(&ranges, true).unwrap();
+        assert!(gm.is_dirty_tracking_enabled());
+        assert_eq!(
+            gm.unpopulated_regions().unwrap(),
+            vec![
+                (
+                    GuestAddress(0),
+                    PageResidency {
+                        resident: 0,
+                        total: 2
+                    }
+                ),
+                (
+                    GuestAddress(0x10_0000),
+                    PageResidency {
+                        resident: 0,
+                        total: 1
+                    }
+                ),
+            ]
+        );
+
+        // Populated memory is resident right away.
+        let options = MapOptions {
+            populate: true,
+            ..Default::default()
+        };
+        let ranges = [
+            (GuestAddress(0), page_size * 2, options),
+            (GuestAddress(0x10_0000), page_size, options),
+        ];
+        let gm = GuestMemoryMmap::from_ranges_with_options(&ranges, false).unwrap();
+        assert!(gm.unpopulated_regions().unwrap().is_empty());
+
+        let f = Arc::new(TempFile::new().unwrap().into_file());
+        f.set_len(3 * page_size as u64).unwrap();
+        let gm = GuestMemoryMmap::from_ranges_with_backing_file(&ranges, f.clone(), true, false)
+            .unwrap();
+        assert!(gm.unpopulated_regions().unwrap().is_empty());
+
+        // Options apply per region: writes to read-only regions fail instead of faulting.
+        let ranges = [
+            (
+                GuestAddress(0),
+                page_size,
+                MapOptions {
+                    read_only: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                GuestAddress(page_size as u64),
+                page_size,
+                MapOptions::default(),
+            ),
+        ];
+        let gm = GuestMemoryMmap::from_ranges_with_backing_file(&ranges, f.clone(), true, false)
+            .unwrap();
+        let rom = gm.find_region(GuestAddress(0)).unwrap();
+        assert_eq!(rom.protection(), libc::PROT_READ);
+        assert_eq!(
+            gm.find_region(GuestAddress(page_size as u64))
+                .unwrap()
+                .protection(),
+            libc::PROT_READ | libc::PROT_WRITE
+        );
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0)).unwrap(), 0);
+        gm.write_obj(1u8, GuestAddress(page_size as u64)).unwrap();
+        gm.write_obj(1u8, GuestAddress(0)).unwrap_err();
+        gm.read_vectored_at_addr(&[(GuestAddress(0), 1)], &*f, 0)
+            .unwrap_err();
+        gm.check_writable_range(GuestAddress(page_size as u64), page_size)
+            .unwrap();
+        gm.check_writable_range(GuestAddress(page_size as u64 - 1), 2)
+            .unwrap_err();
+        gm.checked_writable_host_address(GuestAddress(page_size as u64), page_size)
+            .unwrap();
+        gm.checked_writable_host_address(GuestAddress(0), 1)
+            .unwrap_err();
+        gm.checked_host_address(GuestAddress(0), 1).unwrap();
+    }
+
+    #[test]
+    fn test_discard_range() {
+        let page_size = 0x1000;
+
+        // Anonymous memory.
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), page_size),
+            (GuestAddress(page_size as u64), page_size),
+        ])
+        .unwrap();
+        gm.write_slice(&[0xAAu8; 0x2000], GuestAddress(0)).unwrap();
+        gm.discard_range(GuestAddress(0), 2 * page_size).unwrap();
+        assert_eq!(gm.read_obj::<u64>(GuestAddress(0)).unwrap(), 0);
+        assert_eq!(gm.read_obj::<u64>(GuestAddress(0x1ff8)).unwrap(), 0);
+        assert_eq!(gm.resident_pages().unwrap().resident, 0);
+        assert_eq!(
+            gm.discard_range(GuestAddress(0x1000), 2 * page_size)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EFAULT)
+        );
+        // Only whole pages can be discarded.
+        for &(addr, len) in [(0x800, page_size), (0, 0x800)].iter() {
+            assert_eq!(
+                gm.discard_range(GuestAddress(addr), len)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+
+        // File-backed memory gets holes punched in the file, whether it is mapped shared or
+        // copy-on-write.
+        for &shared in [true, false].iter() {
+            let mut f = TempFile::new().unwrap().into_file();
+            f.write_all(&[0xAAu8; 0x2000]).unwrap();
+            let f = Arc::new(f);
+            let gm = GuestMemoryMmap::from_ranges_with_backing_file(
+                &[(GuestAddress(0), 2 * page_size, MapOptions::default())],
+                f.clone(),
+                shared,
+                false,
+            )
+            .unwrap();
+            gm.write_obj(0xCCu8, GuestAddress(0x1000)).unwrap();
+
+            gm.discard_range(GuestAddress(0x1000), page_size).unwrap();
+            assert_eq!(gm.read_obj::<u8>(GuestAddress(0x0)).unwrap(), 0xAA);
+            assert_eq!(gm.read_obj::<u8>(GuestAddress(0x1000)).unwrap(), 0);
+            let mut buf = [0xFFu8; 1];
+            (&*f).seek(SeekFrom::Start(0x1000)).unwrap();
+            (&*f).read_exact(&mut buf).unwrap();
+            assert_eq!(buf[0], 0);
+        }
+
+        // A file opened read-only can't have holes punched in it, and the guest memory is left
+        // untouched.
+        let tmp = TempFile::new().unwrap();
+        tmp.as_file().write_all(&[0xAAu8; 0x2000]).unwrap();
+        let f = Arc::new(File::open(tmp.as_path()).unwrap());
+        let gm = GuestMemoryMmap::from_ranges_with_backing_file(
+            &[(GuestAddress(0), 2 * page_size, MapOptions::default())],
+            f,
+            false,
+            false,
+        )
+        .unwrap();
+        gm.write_obj(0xCCu8, GuestAddress(0x1000)).unwrap();
+        assert_eq!(
+            gm.discard_range(GuestAddress(0x1000), page_size)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EBADF)
+        );
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x1000)).unwrap(), 0xCC);
+    }
+
+    #[test]
+    fn test_resident_pages() {
+        let page_size = 0x1000;
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0x0), page_size * 4),
+            (GuestAddress(0x10_0000), page_size * 2),
+        ])
+        .unwrap();
+
+        // Freshly mapped anonymous memory has no resident pages.
+        assert_eq!(
+            gm.resident_pages().unwrap(),
+            PageResidency {
+                resident: 0,
+                total: 6
+            }
+        );
+
+        // Writing to guest memory faults pages in.
+        gm.write_obj(1u8, GuestAddress(0x0)).unwrap();
+        gm.write_obj(1u8, GuestAddress(0x2000)).unwrap();
+        gm.write_obj(1u8, GuestAddress(0x10_0000)).unwrap();
+
+        assert_eq!(
+            gm.find_region(GuestAddress(0x0))
+                .unwrap()
+                .resident_pages()
+                .unwrap(),
+            PageResidency {
+                resident: 2,
+                total: 4
+            }
+        );
+        assert_eq!(
+            gm.resident_pages().unwrap(),
+            PageResidency {
+                resident: 3,
+                total: 6
+            }
+        );
+    }
 }
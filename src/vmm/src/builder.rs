@@ -16,6 +16,8 @@ use crate::device_manager::{legacy::PortIODeviceManager, persist::MMIODevManager
 #[cfg(target_arch = "x86_64")]
 use crate::persist::{MicrovmState, MicrovmStateError};
 use crate::vmm_config::boot_source::BootConfig;
+use crate::vmm_config::memory_backend::{MemoryBackendConfig, MemoryBackendError};
+use crate::vmm_config::snapshot::SnapshotStatus;
 use crate::vstate::{
     system::KvmContext,
     vcpu::{Vcpu, VcpuConfig},
@@ -48,6 +50,8 @@ pub enum StartMicrovmError {
     CreateNetDevice(devices::virtio::net::Error),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
+    /// Cannot set up the guest memory backend.
+    GuestMemoryBackend(MemoryBackendError),
     /// Memory regions are overlapping or mmap fails.
     GuestMemoryMmap(vm_memory::Error),
     /// Cannot load initrd due to an invalid memory configuration.
@@ -102,6 +106,7 @@ impl Display for StartMicrovmError {
 
                 write!(f, "Cannot create network device. {}", err_msg)
             }
+            GuestMemoryBackend(err) => write!(f, "Invalid memory backend: {}", err),
             GuestMemoryMmap(err) => {
                 // Remove imbricated quotes from error message.
                 let mut err_msg = format!("{:?}", err);
@@ -270,6 +275,7 @@ fn create_vmm_and_vcpus(
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
+        snapshot_status: SnapshotStatus::default(),
     };
 
     Ok((vmm, vcpus))
@@ -297,6 +303,11 @@ pub fn build_microvm_for_boot(
             .mem_size_mib
             .ok_or(MissingMemSizeConfig)?,
         track_dirty_pages,
+        &vm_resources
+            .vm_config()
+            .mem_backend
+            .clone()
+            .unwrap_or_default(),
     )?;
     let vcpu_config = vm_resources.vcpu_config();
     let entry_addr = load_kernel(boot_config, &guest_memory)?;
@@ -401,6 +412,14 @@ pub fn build_microvm_from_snapshot(
         vcpu_count,
     )?;
 
+    // Refuse to restore a guest that relies on CPU features this host lacks.
+    for vcpu_state in microvm_state.vcpu_states.iter() {
+        vcpu_state
+            .check_cpuid_support(vmm.vm.supported_cpuid())
+            .map_err(MicrovmStateError::IncompatibleCpuFeatures)
+            .map_err(RestoreMicrovmState)?;
+    }
+
     // Restore kvm vm state.
     vmm.vm
         .restore_state(&microvm_state.vm_state)
@@ -444,19 +463,17 @@ pub fn build_microvm_from_snapshot(
 pub fn create_guest_memory(
     mem_size_mib: usize,
     track_dirty_pages: bool,
+    mem_backend: &MemoryBackendConfig,
 ) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
     let mem_size = mem_size_mib << 20;
     let arch_mem_regions = arch::arch_memory_regions(mem_size);
 
-    if !track_dirty_pages {
-        Ok(GuestMemoryMmap::from_ranges(&arch_mem_regions)
-            .map_err(StartMicrovmError::GuestMemoryMmap)?)
-    } else {
-        Ok(
-            GuestMemoryMmap::from_ranges_with_tracking(&arch_mem_regions)
-                .map_err(StartMicrovmError::GuestMemoryMmap)?,
-        )
-    }
+    mem_backend
+        .create_guest_memory(&arch_mem_regions, track_dirty_pages)
+        .map_err(|err| match err {
+            MemoryBackendError::GuestMemoryMmap(err) => StartMicrovmError::GuestMemoryMmap(err),
+            err => StartMicrovmError::GuestMemoryBackend(err),
+        })
 }
 
 fn load_kernel(
@@ -876,7 +893,8 @@ pub mod tests {
     }
 
     pub(crate) fn default_vmm() -> Vmm {
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory =
+            create_guest_memory(128, false, &MemoryBackendConfig::default()).unwrap();
 
         let exit_evt = EventFd::new(libc::EFD_NONBLOCK)
             .map_err(Error::EventFd)
@@ -897,6 +915,7 @@ pub mod tests {
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
+            snapshot_status: SnapshotStatus::default(),
         };
 
         #[cfg(target_arch = "x86_64")]
@@ -1060,13 +1079,15 @@ pub mod tests {
 
         // Case 1: create guest memory without dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, false).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, false, &MemoryBackendConfig::default()).unwrap();
             assert!(!guest_memory.is_dirty_tracking_enabled());
         }
 
         // Case 2: create guest memory with dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, true).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, true, &MemoryBackendConfig::default()).unwrap();
             assert!(guest_memory.is_dirty_tracking_enabled());
         }
     }
@@ -1074,7 +1095,8 @@ pub mod tests {
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory =
+            create_guest_memory(128, false, &MemoryBackendConfig::default()).unwrap();
 
         #[allow(unused_mut)]
         let mut vm = setup_kvm_vm(&guest_memory, false).unwrap();
@@ -47,16 +47,35 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_epoll_wait),
             allow_syscall(libc::SYS_exit),
             allow_syscall(libc::SYS_exit_group),
+            // Used for punching holes in the files backing discarded guest memory
+            allow_syscall_if(
+                libc::SYS_fallocate,
+                or![and![Cond::new(
+                    1,
+                    ArgLen::DWORD,
+                    Eq,
+                    (libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE) as u64
+                )?],],
+            ),
             // Used by snapshotting, drive patching and rescanning
             allow_syscall_if(
                 libc::SYS_fcntl,
-                or![and![
-                    Cond::new(1, ArgLen::DWORD, Eq, super::FCNTL_F_SETFD)?,
-                    Cond::new(2, ArgLen::DWORD, Eq, super::FCNTL_FD_CLOEXEC)?,
-                ],],
+                or![
+                    and![
+                        Cond::new(1, ArgLen::DWORD, Eq, super::FCNTL_F_SETFD)?,
+                        Cond::new(2, ArgLen::DWORD, Eq, super::FCNTL_FD_CLOEXEC)?,
+                    ],
+                    // Used for checking that files backing discarded guest memory are writable
+                    and![Cond::new(1, ArgLen::DWORD, Eq, super::FCNTL_F_GETFL)?],
+                ],
             ),
             // Used for drive patching & rescanning, for reading the local timezone
             allow_syscall(libc::SYS_fstat),
+            // Used for finding the page size of hugetlbfs-backed guest memory
+            allow_syscall(libc::SYS_fstatfs),
+            // Used for atomic snapshot writes
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_fsync),
             // Used for snapshotting
             #[cfg(target_arch = "x86_64")]
             allow_syscall(libc::SYS_ftruncate),
@@ -92,6 +111,8 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     libc::MADV_DONTNEED as u64
                 )?],],
             ),
+            // Used for sampling the guest memory residency metrics
+            allow_syscall(libc::SYS_mincore),
             // Used for re-allocating large memory regions, for example vectors
             allow_syscall(libc::SYS_mremap),
             // Used for freeing memory
@@ -109,29 +130,56 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     )?],
                 ],
             ),
+            // Used for throttling snapshot writes, via thread::sleep
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_nanosleep),
+            #[cfg(all(target_env = "gnu", target_arch = "x86_64"))]
+            allow_syscall(libc::SYS_clock_nanosleep),
             #[cfg(target_arch = "x86_64")]
             allow_syscall(libc::SYS_open),
             #[cfg(target_arch = "aarch64")]
             allow_syscall(libc::SYS_openat),
+            // Used for moving guest memory ranges to and from files at a given offset
+            allow_syscall(libc::SYS_pread64),
+            allow_syscall(libc::SYS_preadv),
+            allow_syscall(libc::SYS_pwrite64),
+            allow_syscall(libc::SYS_pwritev),
             allow_syscall(libc::SYS_read),
             // Used by the API thread and vsock
             allow_syscall(libc::SYS_recvfrom),
+            // Used for atomic snapshot writes. Seccomp cannot filter on the path, so this allows
+            // renaming any file the process can reach; see docs/prod-host-setup.md.
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_rename),
             // SYS_rt_sigreturn is needed in case a fault does occur, so that the signal handler
             // can return. Otherwise we get stuck in a fault loop.
             allow_syscall(libc::SYS_rt_sigreturn),
             // Used by the API thread and vsock
             allow_syscall_if(
                 libc::SYS_socket,
-                or![and![
-                    Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
-                    Cond::new(
-                        1,
-                        ArgLen::DWORD,
-                        Eq,
-                        (libc::SOCK_STREAM as u64) | (libc::SOCK_CLOEXEC as u64)
-                    )?,
-                    Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
-                ],],
+                or![
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_STREAM as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                    // Used by vsock for SOCK_SEQPACKET connections
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_SEQPACKET as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                ],
             ),
             // Used to kick vcpus
             allow_syscall_if(
@@ -164,6 +212,10 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                 libc::SYS_timerfd_settime,
                 or![and![Cond::new(1, ArgLen::DWORD, Eq, 0u64)?],],
             ),
+            // Used for atomic snapshot writes, to remove uncommitted files. Seccomp cannot filter
+            // on the path, so this allows removing any file the process can reach.
+            #[cfg(target_arch = "x86_64")]
+            allow_syscall(libc::SYS_unlink),
             allow_syscall(libc::SYS_write),
         ]
         .into_iter()
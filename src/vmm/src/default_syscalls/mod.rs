@@ -16,6 +16,7 @@ pub use self::filters::get_seccomp_filter;
 // See include/uapi/asm-generic/fcntl.h in the kernel code.
 const FCNTL_FD_CLOEXEC: u64 = 1;
 const FCNTL_F_SETFD: u64 = 2;
+const FCNTL_F_GETFL: u64 = 3;
 
 // See include/uapi/linux/futex.h in the kernel code.
 const FUTEX_WAIT: u64 = 0;
@@ -26,6 +26,19 @@ use utils::eventfd::EventFd;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 
+/// Points of the snapshot lifecycle at which virtio devices are notified.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotHook {
+    /// Before the device states are saved.
+    PreSave,
+    /// After the snapshot was created, successfully or not.
+    PostSave,
+    /// After a device was restored, before it is attached to the MMIO bus.
+    PreRestore,
+    /// After all devices were restored.
+    PostRestore,
+}
+
 /// Errors for MMIO device manager.
 #[derive(Debug)]
 pub enum Error {
@@ -421,7 +434,8 @@ impl MMIODeviceManager {
                     TYPE_VSOCK => {
                         // Vsock has complicated protocol that isn't resilient to any packet loss,
                         // so for Vsock we don't support connection persistence through snapshot.
-                        // Any in-flight packets or events are simply lost.
+                        // The backend is drained before saving (see `Vsock::pre_save()`), and
+                        // the guest driver is sent a transport reset event upon restore.
                         // Vsock is restored 'empty'.
                     }
                     _ => (),
@@ -430,6 +444,28 @@ impl MMIODeviceManager {
             Ok(())
         });
     }
+
+    /// Runs the snapshot `hook` of every virtio device, letting devices quiesce and resume
+    /// around snapshot operations.
+    pub fn run_snapshot_hook(&self, hook: SnapshotHook) -> Result<()> {
+        self.for_each_device(|devtype, id, _, bus_dev| {
+            if let DeviceType::Virtio(_) = *devtype {
+                info!("{:?} hook of {}.", hook, id);
+                let bus_dev = bus_dev.lock().expect("Poisoned lock");
+                // Virtio devices are guaranteed MmioTransport.
+                let mmio_dev = bus_dev.as_any().downcast_ref::<MmioTransport>().unwrap();
+                let mut virtio = mmio_dev.locked_device();
+                match hook {
+                    SnapshotHook::PreSave => virtio.pre_save(),
+                    SnapshotHook::PostSave => virtio.post_save(),
+                    SnapshotHook::PreRestore => virtio.pre_restore(),
+                    SnapshotHook::PostRestore => virtio.post_restore(),
+                }
+                .map_err(Error::InternalDeviceError)?;
+            }
+            Ok(())
+        })
+    }
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -550,6 +586,15 @@ mod tests {
         fn is_activated(&self) -> bool {
             false
         }
+
+        fn pre_save(&mut self) -> std::result::Result<(), String> {
+            self.dummy += 1;
+            Ok(())
+        }
+
+        fn post_save(&mut self) -> std::result::Result<(), String> {
+            Err(String::from("cannot resume"))
+        }
     }
 
     #[test]
@@ -574,6 +619,39 @@ mod tests {
             .is_ok());
     }
 
+    #[test]
+    fn test_run_snapshot_hook() {
+        let guest_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let mut vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        let mut device_manager =
+            MMIODeviceManager::new(0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+        let mut cmdline = kernel_cmdline::Cmdline::new(4096);
+        let dummy = Arc::new(Mutex::new(DummyDevice::new()));
+        #[cfg(target_arch = "x86_64")]
+        assert!(builder::setup_interrupt_controller(&mut vm).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(builder::setup_interrupt_controller(&mut vm, 1).is_ok());
+        device_manager
+            .register_virtio_test_device(vm.fd(), guest_mem, dummy.clone(), &mut cmdline, "dummy")
+            .unwrap();
+
+        device_manager
+            .run_snapshot_hook(SnapshotHook::PreSave)
+            .unwrap();
+        assert_eq!(dummy.lock().unwrap().dummy, 1);
+
+        // Devices that don't implement a hook are left untouched.
+        device_manager
+            .run_snapshot_hook(SnapshotHook::PostRestore)
+            .unwrap();
+        assert_eq!(dummy.lock().unwrap().dummy, 1);
+
+        match device_manager.run_snapshot_hook(SnapshotHook::PostSave) {
+            Err(Error::InternalDeviceError(msg)) => assert_eq!(msg, "cannot resume"),
+            other => panic!("{:?}", other),
+        }
+    }
+
     #[test]
     fn test_register_too_many_devices() {
         let start_addr1 = GuestAddress(0x0);
@@ -214,6 +214,13 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             Ok(())
         });
+        // `for_each_device()` walks a `HashMap`, whose iteration order changes from one process
+        // to the next. Sort the devices by MMIO address, so that identical state is always
+        // saved as identical bytes.
+        states
+            .block_devices
+            .sort_by_key(|block| block.mmio_slot.addr);
+        states.net_devices.sort_by_key(|net| net.mmio_slot.addr);
         states
     }
 
@@ -236,6 +243,13 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             dev_manager
                 .slot_sanity_check(slot)
                 .map_err(Error::DeviceManager)?;
+            device
+                .lock()
+                .expect("Poisoned lock")
+                .pre_restore()
+                .map_err(|err| {
+                    Error::DeviceManager(super::mmio::Error::InternalDeviceError(err))
+                })?;
 
             let restore_args = MmioTransportConstructorArgs {
                 mem: mem.clone(),
@@ -313,16 +327,15 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             };
             let backend = VsockUnixBackend::restore(ctor_args, &vsock_state.device_state.backend)
                 .map_err(Error::VsockUnixBackend)?;
-            let device = Arc::new(Mutex::new(
-                Vsock::restore(
-                    VsockConstructorArgs {
-                        mem: mem.clone(),
-                        backend,
-                    },
-                    &vsock_state.device_state.frontend,
-                )
-                .map_err(Error::Vsock)?,
-            ));
+            let vsock = Vsock::restore(
+                VsockConstructorArgs {
+                    mem: mem.clone(),
+                    backend,
+                },
+                &vsock_state.device_state.frontend,
+            )
+            .map_err(Error::Vsock)?;
+            let device = Arc::new(Mutex::new(vsock));
 
             restore_helper(
                 device.clone(),
@@ -334,6 +347,10 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
+        dev_manager
+            .run_snapshot_hook(SnapshotHook::PostRestore)
+            .map_err(Error::DeviceManager)?;
+
         Ok(dev_manager)
     }
 }
@@ -515,6 +532,7 @@ mod tests {
                 vsock_id: vsock_dev_id.to_string(),
                 guest_cid: 3,
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                queue_size: Some(512),
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
 
@@ -553,5 +571,69 @@ mod tests {
             MMIODeviceManager::restore(restore_args, &device_states).unwrap();
 
         assert_eq!(restored_dev_manager, original_mmio_device_manager);
+
+        // The restored vsock device keeps its configured queue size.
+        let bus_dev = restored_dev_manager
+            .get_device(arch::DeviceType::Virtio(TYPE_VSOCK), "vsock")
+            .unwrap()
+            .lock()
+            .expect("Poisoned lock");
+        let locked_device = bus_dev
+            .as_any()
+            .downcast_ref::<MmioTransport>()
+            .unwrap()
+            .locked_device();
+        assert_eq!(
+            locked_device
+                .as_any()
+                .downcast_ref::<Vsock<VsockUnixBackend>>()
+                .unwrap()
+                .queue_size(),
+            512
+        );
+    }
+
+    #[test]
+    fn test_device_states_order() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+
+        // Attach several devices of each type, so that their order in the device map likely differs
+        // from the order they are attached in.
+        let block_configs = (0..4)
+            .map(|i| CustomBlockConfig::new(format!("drive{}", i), false, None, true))
+            .collect();
+        let _block_files =
+            insert_block_devices(&mut vmm, &mut cmdline, &mut event_manager, block_configs);
+        for i in 0..3 {
+            let network_interface = NetworkInterfaceConfig {
+                iface_id: format!("netif{}", i),
+                host_dev_name: format!("hostname{}", i),
+                guest_mac: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                allow_mmds_requests: false,
+            };
+            insert_net_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                network_interface,
+            );
+        }
+
+        // Devices are saved in the order of their MMIO slots, whatever the map order.
+        let states = vmm.mmio_device_manager.save();
+        assert_eq!(states.block_devices.len(), 4);
+        assert!(states
+            .block_devices
+            .windows(2)
+            .all(|pair| pair[0].mmio_slot.addr < pair[1].mmio_slot.addr));
+        assert_eq!(states.net_devices.len(), 3);
+        assert!(states
+            .net_devices
+            .windows(2)
+            .all(|pair| pair[0].mmio_slot.addr < pair[1].mmio_slot.addr));
     }
 }
@@ -24,6 +24,8 @@ pub mod resources;
 pub mod rpc_interface;
 /// Signal handling utilities.
 pub mod signal_handler;
+/// Tracking and compaction of snapshot chains.
+pub mod snapshot_catalog;
 /// microVM state versions.
 pub mod version_map;
 /// Wrappers over structures used to configure the VMM.
@@ -46,6 +48,7 @@ use crate::device_manager::mmio::MMIODeviceManager;
 use crate::memory_snapshot::SnapshotMemory;
 #[cfg(target_arch = "x86_64")]
 use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
+use crate::vmm_config::snapshot::SnapshotStatus;
 #[cfg(target_arch = "x86_64")]
 use crate::vstate::vcpu::VcpuState;
 use crate::vstate::{
@@ -59,7 +62,7 @@ use devices::virtio::{
     TYPE_BLOCK, TYPE_NET,
 };
 use devices::BusDevice;
-use logger::{error, info, warn, LoggerError, MetricsError, METRICS};
+use logger::{error, info, warn, IncMetric, LoggerError, MetricsError, StoreMetric, METRICS};
 use polly::event_manager::{EventManager, Subscriber};
 use rate_limiter::BucketUpdate;
 use seccomp::BpfProgramRef;
@@ -240,6 +243,9 @@ pub struct Vmm {
     mmio_device_manager: MMIODeviceManager,
     #[cfg(target_arch = "x86_64")]
     pio_device_manager: PortIODeviceManager,
+
+    // Outcome of the most recent snapshot operation.
+    snapshot_status: SnapshotStatus,
 }
 
 impl Vmm {
@@ -326,6 +332,34 @@ impl Vmm {
         &self.guest_memory
     }
 
+    /// Returns the number of vCPUs of the microVM.
+    pub fn vcpu_count(&self) -> usize {
+        self.vcpus_handles.len()
+    }
+
+    /// Returns the size of guest memory, in MiB.
+    pub fn mem_size_mib(&self) -> u64 {
+        mem_size_mib(&self.guest_memory)
+    }
+
+    /// Samples how many guest memory pages are resident and stores the result in the
+    /// guest memory metrics, e.g. to track the warm-up of a restored microVM.
+    pub fn update_memory_metrics(&self) {
+        match self.guest_memory.resident_pages() {
+            Ok(residency) => {
+                METRICS
+                    .guest_memory
+                    .resident_pages
+                    .store(residency.resident);
+                METRICS.guest_memory.total_pages.store(residency.total);
+            }
+            Err(e) => {
+                METRICS.guest_memory.residency_fails.inc();
+                warn!("Failed to sample guest memory residency: {}", e);
+            }
+        }
+    }
+
     /// Injects CTRL+ALT+DEL keystroke combo in the i8042 device.
     #[cfg(target_arch = "x86_64")]
     pub fn send_ctrl_alt_del(&mut self) -> Result<()> {
@@ -362,7 +396,13 @@ impl Vmm {
     /// Saves the state of a paused Microvm.
     #[cfg(target_arch = "x86_64")]
     pub fn save_state(&mut self) -> std::result::Result<MicrovmState, MicrovmStateError> {
-        use self::MicrovmStateError::SaveVmState;
+        use self::MicrovmStateError::{QuiesceDevices, SaveVmState};
+        // Quiesce devices first: the interrupts they raise while doing so must be captured
+        // in the vCPU and VM (irqchip) state.
+        self.mmio_device_manager
+            .run_snapshot_hook(device_manager::mmio::SnapshotHook::PreSave)
+            .map_err(QuiesceDevices)?;
+
         let vcpu_states = self.save_vcpu_states()?;
 
         let vm_state = self.vm.save_state().map_err(SaveVmState)?;
@@ -484,13 +524,20 @@ impl Vmm {
     pub fn get_dirty_bitmap(&self) -> Result<DirtyBitmap> {
         let mut bitmap: DirtyBitmap = HashMap::new();
         self.guest_memory.with_regions_mut(
-            |slot: usize, region: &GuestRegionMmap| -> Result<()> {
+            |index: usize, region: &GuestRegionMmap| -> Result<()> {
+                // Regions added or removed at runtime don't keep slot numbers in step with
+                // region indices, so look the slot up.
+                let slot = self
+                    .vm
+                    .memslots()
+                    .slot(region.start_addr())
+                    .map_err(|err| Error::Vm(vstate::vm::Error::MemSlots(err)))?;
                 let bitmap_region = self
                     .vm
                     .fd()
-                    .get_dirty_log(slot as u32, region.len() as usize)
+                    .get_dirty_log(slot, region.len() as usize)
                     .map_err(Error::DirtyBitmap)?;
-                bitmap.insert(slot, bitmap_region);
+                bitmap.insert(index, bitmap_region);
                 Ok(())
             },
         )?;
@@ -504,9 +551,7 @@ impl Vmm {
         // The VMM's consumer will need to cache the dirty tracking setting internally. For
         // example, if this function were to be exposed through the VMM controller, the VMM
         // resources should cache the flag.
-        self.vm
-            .set_kvm_memory_regions(&self.guest_memory, enable)
-            .map_err(Error::Vm)
+        self.vm.set_dirty_page_tracking(enable).map_err(Error::Vm)
     }
 
     /// Updates the path of the host file backing the emulated block device with id `drive_id`.
@@ -565,6 +610,16 @@ impl Vmm {
         }
     }
 
+    /// Returns the status of the most recent snapshot operation.
+    pub fn snapshot_status(&self) -> SnapshotStatus {
+        self.snapshot_status.clone()
+    }
+
+    /// Records the status of the most recent snapshot operation.
+    pub fn set_snapshot_status(&mut self, status: SnapshotStatus) {
+        self.snapshot_status = status;
+    }
+
     /// Returns the latest balloon statistics if they are enabled.
     pub fn latest_balloon_stats(&self) -> std::result::Result<BalloonStats, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -17,6 +17,7 @@ use vm_memory::{
     GuestMemoryRegion, GuestRegionMmap, MemoryRegionAddress, MmapRegion,
 };
 
+use crate::vmm_config::memory_backend::{account_guest_memory_error, account_mmap_region_error};
 use crate::DirtyBitmap;
 
 /// State of a guest memory region saved to file/buffer.
@@ -52,6 +53,13 @@ where
         writer: &mut T,
         dirty_bitmap: &DirtyBitmap,
     ) -> std::result::Result<(), Error>;
+    /// Dumps all non-zero pages of GuestMemoryMmap to a writer, seeking over the zero ones.
+    /// The writer must read back zeros where nothing is written, e.g. a freshly truncated file,
+    /// which then stays sparse.
+    fn dump_sparse<T: std::io::Write + std::io::Seek>(
+        &self,
+        writer: &mut T,
+    ) -> std::result::Result<(), Error>;
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -166,6 +174,53 @@ impl SnapshotMemory for GuestMemoryMmap {
         .map_err(Error::WriteMemory)
     }
 
+    /// Dumps all non-zero pages of GuestMemoryMmap to a writer, seeking over the zero ones.
+    fn dump_sparse<T: std::io::Write + std::io::Seek>(
+        &self,
+        writer: &mut T,
+    ) -> std::result::Result<(), Error> {
+        let page_size = sysconf::page::pagesize();
+        let mut writer_offset = 0;
+
+        self.with_regions_mut(|_, region| {
+            let region_len = region.len() as usize;
+            let zero_pages = self.scan_zero_pages(region.start_addr(), region_len, page_size)?;
+            let mut write_size = 0;
+            let mut data_batch_start = 0;
+
+            for page_offset in (0..region_len).step_by(page_size) {
+                let len = std::cmp::min(page_size, region_len - page_offset);
+                if !zero_pages.is_addr_set(page_offset) {
+                    // We are at the start of a new batch of non-zero pages.
+                    if write_size == 0 {
+                        // Seek forward over the zero pages.
+                        writer
+                            .seek(SeekFrom::Start(writer_offset + page_offset as u64))
+                            .map_err(GuestMemoryError::IOError)?;
+                        data_batch_start = page_offset as u64;
+                    }
+                    write_size += len;
+                } else if write_size > 0 {
+                    // We are at the end of a batch of non-zero pages.
+                    region.write_all_to(
+                        MemoryRegionAddress(data_batch_start),
+                        writer,
+                        write_size,
+                    )?;
+                    write_size = 0;
+                }
+            }
+
+            if write_size > 0 {
+                region.write_all_to(MemoryRegionAddress(data_batch_start), writer, write_size)?;
+            }
+
+            writer_offset += region.len();
+            Ok(())
+        })
+        .map_err(Error::WriteMemory)
+    }
+
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -191,13 +246,19 @@ impl SnapshotMemory for GuestMemoryMmap {
                 }
                 Ok(region)
             })
-            .map_err(Error::CreateRegion)?
+            .map_err(|err| {
+                account_mmap_region_error(&err);
+                Error::CreateRegion(err)
+            })?
             .map_err(Error::CreateMemory)?;
 
             mmap_regions.push(mmap_region);
         }
 
-        Ok(Self::from_regions(mmap_regions).map_err(Error::CreateMemory)?)
+        Ok(Self::from_regions(mmap_regions).map_err(|err| {
+            account_guest_memory_error(&err);
+            Error::CreateMemory(err)
+        })?)
     }
 }
 
@@ -207,6 +268,7 @@ mod tests {
 
     use super::*;
     use std::io::{Read, Seek};
+    use std::os::unix::fs::MetadataExt;
     use utils::tempfile::TempFile;
     use vm_memory::GuestAddress;
 
@@ -319,7 +381,46 @@ mod tests {
             assert_eq!(second_region, actual_region);
         }
 
-        // Case 2: dump only the dirty pages.
+        // Case 2: dump only the non-zero pages.
+        {
+            let zeros = vec![0u8; page_size];
+            guest_memory
+                .write(&zeros[..], GuestAddress(page_size as u64))
+                .unwrap();
+
+            let memory_file = TempFile::new().unwrap();
+            let file = memory_file.as_file();
+            file.set_len(page_size as u64 * 4).unwrap();
+            guest_memory
+                .dump_sparse(&mut memory_file.as_file())
+                .unwrap();
+            // Only the first page of each region holds data.
+            assert!(file.metadata().unwrap().blocks() * 512 < page_size as u64 * 4);
+
+            let restored_guest_memory =
+                GuestMemoryMmap::restore(file, &memory_state, false).unwrap();
+            let mut actual_region = vec![0u8; page_size * 2];
+            restored_guest_memory
+                .read(&mut actual_region.as_mut_slice(), GuestAddress(0))
+                .unwrap();
+            assert_eq!(
+                [&first_region[..page_size], &zeros[..]].concat(),
+                actual_region
+            );
+            restored_guest_memory
+                .read(
+                    &mut actual_region.as_mut_slice(),
+                    GuestAddress(page_size as u64 * 3),
+                )
+                .unwrap();
+            assert_eq!(second_region, actual_region);
+
+            guest_memory
+                .write(&first_region[page_size..], GuestAddress(page_size as u64))
+                .unwrap();
+        }
+
+        // Case 3: dump only the dirty pages.
         {
             // KVM Bitmap
             // First region pages: [dirty, clean]
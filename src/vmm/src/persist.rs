@@ -6,13 +6,18 @@
 // Currently only supports x86_64.
 #![cfg(target_arch = "x86_64")]
 
+use std::ffi::{OsStr, OsString};
 use std::fmt::{Display, Formatter};
-use std::fs::{File, OpenOptions};
-use std::io;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use crate::builder::{self, StartMicrovmError};
+use crate::device_manager::mmio::{Error as MmioError, SnapshotHook};
 use crate::device_manager::persist::Error as DevicePersistError;
 use crate::mem_size_mib;
 use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
@@ -22,9 +27,14 @@ use crate::device_manager::persist::DeviceStates;
 use crate::memory_snapshot;
 use crate::memory_snapshot::{GuestMemoryState, SnapshotMemory};
 use crate::version_map::FC_VERSION_TO_SNAP_VERSION;
+use logger::{debug, update_metric_with_elapsed_time, SharedStoreMetric, METRICS};
 use polly::event_manager::EventManager;
+use rate_limiter::{BucketReduction, TokenBucket};
 use seccomp::BpfProgramRef;
+use serde::{Deserialize, Serialize};
 use snapshot::Snapshot;
+use utils::time::{get_time_us, ClockType};
+use versionize::crc::CRC64Writer;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::GuestMemoryMmap;
@@ -53,13 +63,46 @@ pub struct MicrovmState {
     pub device_states: DeviceStates,
 }
 
+/// Version of the snapshot manifest format written by this Firecracker build.
+pub const SNAPSHOT_MANIFEST_VERSION: u16 = 1;
+
+/// Metadata written as JSON next to the microVM state file, at the same path with an added
+/// `.json` extension, so that it can be inspected without parsing the binary snapshot.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct SnapshotManifest {
+    /// Version of the manifest format.
+    pub manifest_version: u16,
+    /// Id of the snapshot, derived from the checksum of the microVM state file.
+    pub snapshot_id: String,
+    /// Type of the snapshot.
+    pub snapshot_type: SnapshotType,
+    /// Data version of the microVM state file.
+    pub data_version: u16,
+    /// Wall clock time at which the snapshot was created, in microseconds.
+    pub created_at_us: u64,
+    /// Version of the Firecracker build that created the snapshot.
+    pub vmm_version: String,
+    /// Checksum of the guest configuration: memory size and layout, and vCPU count.
+    pub guest_config_hash: String,
+    /// File name of the guest memory file.
+    pub mem_file_name: String,
+    /// Size of the guest memory file, in bytes.
+    pub mem_file_len: u64,
+    /// CRC64 of the whole microVM state file.
+    pub state_file_crc64: String,
+}
+
 /// Errors related to saving and restoring Microvm state.
 #[derive(Debug)]
 pub enum MicrovmStateError {
+    /// The host CPU lacks features the snapshotted guest relies on.
+    IncompatibleCpuFeatures(vstate::vcpu::VcpuError),
     /// Provided MicroVM state is invalid.
     InvalidInput,
     /// Operation not allowed.
     NotAllowed(String),
+    /// Failed to quiesce devices before saving their state.
+    QuiesceDevices(MmioError),
     /// Failed to restore devices.
     RestoreDevices(DevicePersistError),
     /// Failed to restore Vcpu state.
@@ -80,8 +123,12 @@ impl Display for MicrovmStateError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         use self::MicrovmStateError::*;
         match self {
+            IncompatibleCpuFeatures(err) => {
+                write!(f, "Snapshot is incompatible with the host CPU: {}", err)
+            }
             InvalidInput => write!(f, "Provided MicroVM state is invalid."),
             NotAllowed(msg) => write!(f, "Operation not allowed: {}", msg),
+            QuiesceDevices(err) => write!(f, "Cannot quiesce devices. Error: {}", err),
             RestoreDevices(err) => write!(f, "Cannot restore devices. Error: {:?}", err),
             RestoreVcpuState(err) => write!(f, "Cannot restore Vcpu state. Error: {:?}", err),
             RestoreVmState(err) => write!(f, "Cannot restore Vm state. Error: {:?}", err),
@@ -106,8 +153,14 @@ pub enum CreateSnapshotError {
     Memory(memory_snapshot::Error),
     /// Failed to open memory backing file.
     MemoryBackingFile(io::Error),
+    /// Failed to write the snapshot manifest.
+    ManifestFile(io::Error),
     /// Failed to save MicrovmState.
     MicrovmState(MicrovmStateError),
+    /// Failed to resume devices after saving their state.
+    ResumeDevices(MmioError),
+    /// Failed to serialize the snapshot manifest.
+    SerializeManifest(serde_json::Error),
     /// Failed to serialize microVM state.
     SerializeMicrovmState(snapshot::Error),
     /// Failed to open the snapshot backing file.
@@ -126,7 +179,10 @@ impl Display for CreateSnapshotError {
             InvalidVmState(err) => write!(f, "Cannot save Vm state. Error: {:?}", err),
             Memory(err) => write!(f, "Cannot write memory file: {:?}", err),
             MemoryBackingFile(err) => write!(f, "Cannot open memory file: {:?}", err),
+            ManifestFile(err) => write!(f, "Cannot write snapshot manifest: {:?}", err),
             MicrovmState(err) => write!(f, "Cannot save microvm state: {}", err),
+            ResumeDevices(err) => write!(f, "Cannot resume devices. Error: {}", err),
+            SerializeManifest(err) => write!(f, "Cannot serialize snapshot manifest: {}", err),
             SerializeMicrovmState(err) => write!(f, "Cannot serialize MicrovmState: {:?}", err),
             SnapshotBackingFile(err) => write!(f, "Cannot open snapshot file: {:?}", err),
         }
@@ -142,12 +198,22 @@ pub enum LoadSnapshotError {
     DeserializeMemory(memory_snapshot::Error),
     /// Failed to deserialize microVM state.
     DeserializeMicrovmState(snapshot::Error),
+    /// The snapshot manifest does not match the snapshot files.
+    InvalidManifest(String),
+    /// Failed to read the snapshot manifest.
+    ManifestFile(io::Error),
     /// Failed to open memory backing file.
     MemoryBackingFile(io::Error),
+    /// The memory file is smaller than the guest memory saved in the snapshot.
+    MemoryFileTooSmall(u64, u64),
+    /// The size of the guest memory does not match the saved machine configuration.
+    MemorySizeMismatch(u64, u64),
     /// Failed to open the snapshot backing file.
     SnapshotBackingFile(io::Error),
     /// Failed to retrieve the metadata of the snapshot backing file.
     SnapshotBackingFileMetadata(io::Error),
+    /// The snapshot was created by a newer, unsupported, version of Firecracker.
+    UnsupportedVersion(String),
 }
 
 impl Display for LoadSnapshotError {
@@ -157,33 +223,283 @@ impl Display for LoadSnapshotError {
             BuildMicroVm(err) => write!(f, "Cannot build a microVM from snapshot: {}", err),
             DeserializeMemory(err) => write!(f, "Cannot deserialize memory: {}", err),
             DeserializeMicrovmState(err) => write!(f, "Cannot deserialize MicrovmState: {:?}", err),
+            InvalidManifest(msg) => write!(f, "Invalid snapshot manifest: {}", msg),
+            ManifestFile(err) => write!(f, "Cannot read snapshot manifest: {}", err),
             MemoryBackingFile(err) => write!(f, "Cannot open memory file: {}", err),
+            MemoryFileTooSmall(file_size, mem_size) => write!(
+                f,
+                "The memory file size ({} bytes) is smaller than the saved guest memory size \
+                 ({} bytes)",
+                file_size, mem_size
+            ),
+            MemorySizeMismatch(saved, restored) => write!(
+                f,
+                "The snapshot was taken with {} MiB of guest memory, but {} MiB were restored",
+                saved, restored
+            ),
             SnapshotBackingFile(err) => write!(f, "Cannot open snapshot file: {}", err),
             SnapshotBackingFileMetadata(err) => write!(f, "Cannot retrieve file metadata: {}", err),
+            UnsupportedVersion(msg) => write!(f, "Unsupported snapshot version: {}", msg),
         }
     }
 }
 
-/// Creates a Microvm snapshot.
+/// Creates a Microvm snapshot, returning the combined size of the microVM state and memory
+/// files.
 pub fn create_snapshot(
     vmm: &mut Vmm,
     params: &CreateSnapshotParams,
     version_map: VersionMap,
-) -> std::result::Result<(), CreateSnapshotError> {
+) -> std::result::Result<u64, CreateSnapshotError> {
+    let result = write_snapshot(vmm, params, version_map);
+    // Devices quiesced while saving their state get to resume even if the snapshot failed.
+    let post_save_result = vmm
+        .mmio_device_manager
+        .run_snapshot_hook(SnapshotHook::PostSave)
+        .map_err(CreateSnapshotError::ResumeDevices);
+    result.and_then(|files_size| post_save_result.map(|()| files_size))
+}
+
+fn write_snapshot(
+    vmm: &mut Vmm,
+    params: &CreateSnapshotParams,
+    version_map: VersionMap,
+) -> std::result::Result<u64, CreateSnapshotError> {
+    let phase_start_us = get_time_us(ClockType::Monotonic);
     let microvm_state = vmm
         .save_state()
         .map_err(CreateSnapshotError::MicrovmState)?;
-
-    snapshot_memory_to_file(vmm, &params.mem_file_path, &params.snapshot_type)?;
-
-    snapshot_state_to_file(
+    record_phase(
+        "save state",
+        &METRICS.latencies_us.snapshot_save_state,
+        phase_start_us,
+        0,
+    );
+
+    let phase_start_us = get_time_us(ClockType::Monotonic);
+    let mem_file_len = snapshot_memory_to_file(vmm, params)?;
+    record_phase(
+        "write memory",
+        &METRICS.latencies_us.snapshot_write_memory,
+        phase_start_us,
+        mem_file_len,
+    );
+
+    let phase_start_us = get_time_us(ClockType::Monotonic);
+    let (data_version, state_len) = snapshot_state_to_file(
         &microvm_state,
         &params.snapshot_path,
         &params.version,
         version_map,
+        params.atomic_write,
+        params.bandwidth_limit,
     )?;
+    record_phase(
+        "write state",
+        &METRICS.latencies_us.snapshot_write_state,
+        phase_start_us,
+        state_len,
+    );
+
+    snapshot_manifest_to_file(&microvm_state, params, data_version, mem_file_len)?;
+    Ok(state_len + mem_file_len)
+}
 
-    Ok(())
+// Returns the path of the manifest describing the snapshot at `snapshot_path`.
+fn manifest_path(snapshot_path: &Path) -> PathBuf {
+    let mut path = snapshot_path.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+fn file_crc64(path: &Path) -> io::Result<String> {
+    let mut crc_writer = CRC64Writer::new(io::sink());
+    io::copy(&mut File::open(path)?, &mut crc_writer)?;
+    Ok(format!("{:016x}", crc_writer.checksum()))
+}
+
+fn guest_config_hash(microvm_state: &MicrovmState) -> String {
+    let mut crc_writer = CRC64Writer::new(io::sink());
+    // Writing to a sink can't fail.
+    let _ = write!(
+        crc_writer,
+        "mem_size_mib={};vcpus={}",
+        microvm_state.vm_info.mem_size_mib,
+        microvm_state.vcpu_states.len()
+    );
+    for region in microvm_state.memory_state.regions.iter() {
+        let _ = write!(crc_writer, ";{:x}+{:x}", region.base_address, region.size);
+    }
+    format!("{:016x}", crc_writer.checksum())
+}
+
+fn snapshot_manifest_to_file(
+    microvm_state: &MicrovmState,
+    params: &CreateSnapshotParams,
+    data_version: u16,
+    mem_file_len: u64,
+) -> std::result::Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    let state_file_crc64 = file_crc64(&params.snapshot_path).map_err(ManifestFile)?;
+    let manifest = SnapshotManifest {
+        manifest_version: SNAPSHOT_MANIFEST_VERSION,
+        snapshot_id: state_file_crc64.clone(),
+        snapshot_type: params.snapshot_type.clone(),
+        data_version,
+        created_at_us: get_time_us(ClockType::Real),
+        vmm_version: env!("FIRECRACKER_VERSION").to_string(),
+        guest_config_hash: guest_config_hash(microvm_state),
+        mem_file_name: params
+            .mem_file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        mem_file_len,
+        state_file_crc64,
+    };
+    let data = serde_json::to_vec_pretty(&manifest).map_err(SerializeManifest)?;
+
+    let mut manifest_file = SnapshotFile::open(
+        &manifest_path(&params.snapshot_path),
+        OpenOptions::new().create(true).write(true).truncate(true),
+        params.atomic_write,
+    )
+    .map_err(ManifestFile)?;
+    manifest_file.file.write_all(&data).map_err(ManifestFile)?;
+    manifest_file.commit().map_err(ManifestFile)
+}
+
+// Stores the duration of a snapshot phase in `metric` and logs it together with the number of
+// bytes the phase went through, so restore latency can be attributed to each phase.
+fn record_phase(name: &str, metric: &SharedStoreMetric, start_us: u64, bytes: u64) {
+    let elapsed_us = update_metric_with_elapsed_time(metric, start_us);
+    debug!(
+        "Snapshot phase '{}' took {} us ({} bytes)",
+        name, elapsed_us, bytes
+    );
+}
+
+// A snapshot file being written. Atomic writes go to a temporary file in the same directory,
+// which only replaces the target file once it is synced to disk, so a host crash can't leave
+// a truncated snapshot behind. The temporary file is removed if the snapshot is not committed.
+struct SnapshotFile {
+    file: File,
+    path: PathBuf,
+    temp_path: Option<PathBuf>,
+}
+
+impl SnapshotFile {
+    fn open(path: &Path, options: &OpenOptions, atomic: bool) -> io::Result<Self> {
+        if !atomic {
+            return Ok(SnapshotFile {
+                file: options.open(path)?,
+                path: path.to_path_buf(),
+                temp_path: None,
+            });
+        }
+
+        let temp_path = Self::temp_path(path);
+        let file = options.clone().truncate(true).open(&temp_path)?;
+        Ok(SnapshotFile {
+            file,
+            path: path.to_path_buf(),
+            temp_path: Some(temp_path),
+        })
+    }
+
+    fn temp_path(path: &Path) -> PathBuf {
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_else(|| OsStr::new("snapshot")));
+        name.push(".tmp");
+        path.with_file_name(name)
+    }
+
+    // Makes an atomic write visible: syncs the temporary file, renames it over the target
+    // file and syncs the directory, so that the rename itself is persisted.
+    fn commit(mut self) -> io::Result<()> {
+        if let Some(temp_path) = self.temp_path.as_ref() {
+            self.file.sync_all()?;
+            fs::rename(temp_path, &self.path)?;
+            self.temp_path = None;
+
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SnapshotFile {
+    fn drop(&mut self) {
+        if let Some(temp_path) = self.temp_path.as_ref() {
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
+
+// Caps the rate at which a snapshot file is written, so that dumping guest memory to shared
+// storage doesn't starve other users of it. Writes are split into chunks of at most
+// `THROTTLE_CHUNK_SIZE` bytes, each of them waiting for enough tokens in a bucket refilled
+// with `bytes_per_sec` tokens every second.
+struct ThrottledWriter<'a> {
+    file: &'a mut File,
+    bucket: Option<TokenBucket>,
+}
+
+// A multiple of the page size, so writes of page aligned buffers stay page aligned.
+const THROTTLE_CHUNK_SIZE: usize = 1 << 20;
+
+impl<'a> ThrottledWriter<'a> {
+    fn new(file: &'a mut File, bytes_per_sec: Option<u64>) -> Self {
+        ThrottledWriter {
+            file,
+            // A zero limit yields no bucket, which disables throttling.
+            bucket: bytes_per_sec.and_then(|rate| TokenBucket::new(rate, 0, 1000)),
+        }
+    }
+
+    // Blocks until `bytes` tokens were taken out of the bucket.
+    fn acquire(&mut self, mut bytes: u64) {
+        let bucket = match self.bucket.as_mut() {
+            Some(bucket) => bucket,
+            None => return,
+        };
+        while bytes > 0 {
+            // The bucket can't hand out more than its capacity at once.
+            let tokens = bytes.min(bucket.capacity());
+            match bucket.reduce(tokens) {
+                BucketReduction::Failure => {
+                    // Sleep until the missing tokens were refilled.
+                    let missing = tokens - bucket.budget();
+                    let wait_ms = missing * bucket.refill_time_ms() / bucket.capacity();
+                    thread::sleep(Duration::from_millis(wait_ms.max(1)));
+                }
+                _ => bytes -= tokens,
+            }
+        }
+    }
+}
+
+impl<'a> Write for ThrottledWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(THROTTLE_CHUNK_SIZE);
+        self.acquire(len as u64);
+        self.file.write_all(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl<'a> Seek for ThrottledWriter<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
 }
 
 fn snapshot_state_to_file(
@@ -191,13 +507,16 @@ fn snapshot_state_to_file(
     snapshot_path: &PathBuf,
     version: &Option<String>,
     version_map: VersionMap,
-) -> std::result::Result<(), CreateSnapshotError> {
+    atomic: bool,
+    bandwidth_limit: Option<u64>,
+) -> std::result::Result<(u16, u64), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
-    let mut snapshot_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(snapshot_path)
-        .map_err(SnapshotBackingFile)?;
+    let mut snapshot_file = SnapshotFile::open(
+        snapshot_path,
+        OpenOptions::new().create(true).write(true),
+        atomic,
+    )
+    .map_err(SnapshotBackingFile)?;
 
     // Translate the microVM version to its corresponding snapshot data format.
     let snapshot_data_version = match version {
@@ -210,39 +529,60 @@ fn snapshot_state_to_file(
 
     let mut snapshot = Snapshot::new(version_map, snapshot_data_version);
     snapshot
-        .save(&mut snapshot_file, microvm_state)
+        .save(
+            &mut ThrottledWriter::new(&mut snapshot_file.file, bandwidth_limit),
+            microvm_state,
+        )
         .map_err(SerializeMicrovmState)?;
+    // The state is written from the start of the file, so the bytes written are the current
+    // position. Unlike `stat`, `lseek` is allowed by the seccomp filter of the VMM thread.
+    let snapshot_len = snapshot_file
+        .file
+        .seek(SeekFrom::Current(0))
+        .map_err(SnapshotBackingFile)?;
 
-    Ok(())
+    snapshot_file.commit().map_err(SnapshotBackingFile)?;
+    Ok((snapshot_data_version, snapshot_len))
 }
 
+// Returns the size of the memory file.
 fn snapshot_memory_to_file(
     vmm: &Vmm,
-    mem_file_path: &PathBuf,
-    snapshot_type: &SnapshotType,
-) -> std::result::Result<(), CreateSnapshotError> {
+    params: &CreateSnapshotParams,
+) -> std::result::Result<u64, CreateSnapshotError> {
     use self::CreateSnapshotError::*;
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(mem_file_path)
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Guest memory is page aligned and is written in multiples of pages, as O_DIRECT requires.
+    if params.direct_io_mem_file {
+        options.custom_flags(libc::O_DIRECT);
+    }
+    let mut mem_file = SnapshotFile::open(&params.mem_file_path, &options, params.atomic_write)
         .map_err(MemoryBackingFile)?;
-
     // Set the length of the file to the full size of the memory area.
-    let mem_size_mib = mem_size_mib(vmm.guest_memory());
-    file.set_len((mem_size_mib * 1024 * 1024) as u64)
+    let mem_file_len = mem_size_mib(vmm.guest_memory()) << 20;
+    mem_file
+        .file
+        .set_len(mem_file_len)
         .map_err(MemoryBackingFile)?;
+    let file = &mut ThrottledWriter::new(&mut mem_file.file, params.bandwidth_limit);
 
-    match snapshot_type {
+    match params.snapshot_type {
         SnapshotType::Diff => {
             let dirty_bitmap = vmm.get_dirty_bitmap().map_err(|_| DirtyBitmap)?;
             vmm.guest_memory()
-                .dump_dirty(&mut file, &dirty_bitmap)
+                .dump_dirty(file, &dirty_bitmap)
                 .map_err(Memory)
         }
-        SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
-    }
+        // The file was just truncated, so the pages that are not written read back as zeros.
+        SnapshotType::Full if params.sparse_mem_file => {
+            vmm.guest_memory().dump_sparse(file).map_err(Memory)
+        }
+        SnapshotType::Full => vmm.guest_memory().dump(file).map_err(Memory),
+    }?;
+
+    mem_file.commit().map_err(MemoryBackingFile)?;
+    Ok(mem_file_len)
 }
 
 /// Loads a Microvm snapshot producing a 'paused' Microvm.
@@ -254,33 +594,117 @@ pub fn load_snapshot(
 ) -> std::result::Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
     use self::LoadSnapshotError::*;
     let track_dirty_pages = params.enable_diff_snapshots;
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+
+    validate_snapshot_manifest(params, &version_map)?;
+
+    let phase_start_us = get_time_us(ClockType::Monotonic);
+    let (microvm_state, state_len) = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    record_phase(
+        "read state",
+        &METRICS.latencies_us.snapshot_read_state,
+        phase_start_us,
+        state_len,
+    );
+
+    let phase_start_us = get_time_us(ClockType::Monotonic);
     let guest_memory = guest_memory_from_file(
         &params.mem_file_path,
         &microvm_state.memory_state,
         track_dirty_pages,
     )?;
-    builder::build_microvm_from_snapshot(
+    record_phase(
+        "map memory",
+        &METRICS.latencies_us.snapshot_map_memory,
+        phase_start_us,
+        mem_size_mib(&guest_memory) << 20,
+    );
+    // The guest expects the memory size it was running with when the snapshot was taken.
+    if mem_size_mib(&guest_memory) != microvm_state.vm_info.mem_size_mib {
+        return Err(MemorySizeMismatch(
+            microvm_state.vm_info.mem_size_mib,
+            mem_size_mib(&guest_memory),
+        ));
+    }
+
+    let phase_start_us = get_time_us(ClockType::Monotonic);
+    let vmm = builder::build_microvm_from_snapshot(
         event_manager,
         microvm_state,
         guest_memory,
         track_dirty_pages,
         seccomp_filter,
     )
-    .map_err(BuildMicroVm)
+    .map_err(BuildMicroVm)?;
+    record_phase(
+        "restore microvm",
+        &METRICS.latencies_us.snapshot_restore_microvm,
+        phase_start_us,
+        0,
+    );
+
+    Ok(vmm)
+}
+
+// Checks the snapshot files against their manifest, if there is one. Snapshots created by
+// builds that did not write manifests are loaded as they are.
+fn validate_snapshot_manifest(
+    params: &LoadSnapshotParams,
+    version_map: &VersionMap,
+) -> std::result::Result<(), LoadSnapshotError> {
+    use self::LoadSnapshotError::{InvalidManifest, ManifestFile, UnsupportedVersion};
+    let data = match fs::read(manifest_path(&params.snapshot_path)) {
+        Ok(data) => data,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(ManifestFile(err)),
+    };
+    let manifest: SnapshotManifest =
+        serde_json::from_slice(&data).map_err(|err| InvalidManifest(err.to_string()))?;
+
+    if manifest.manifest_version > SNAPSHOT_MANIFEST_VERSION {
+        return Err(UnsupportedVersion(format!(
+            "manifest version {} is not supported",
+            manifest.manifest_version
+        )));
+    }
+    if manifest.data_version > version_map.latest_version() {
+        return Err(UnsupportedVersion(format!(
+            "snapshot data version {} was created by Firecracker {} and is not supported",
+            manifest.data_version, manifest.vmm_version
+        )));
+    }
+    let mem_file_len = fs::metadata(&params.mem_file_path)
+        .map_err(LoadSnapshotError::MemoryBackingFile)?
+        .len();
+    if mem_file_len != manifest.mem_file_len {
+        return Err(InvalidManifest(format!(
+            "memory file is {} bytes long instead of {}",
+            mem_file_len, manifest.mem_file_len
+        )));
+    }
+    let state_file_crc64 =
+        file_crc64(&params.snapshot_path).map_err(LoadSnapshotError::SnapshotBackingFile)?;
+    if state_file_crc64 != manifest.state_file_crc64 {
+        return Err(InvalidManifest(format!(
+            "state file checksum is {} instead of {}",
+            state_file_crc64, manifest.state_file_crc64
+        )));
+    }
+    Ok(())
 }
 
 fn snapshot_state_from_file(
     snapshot_path: &PathBuf,
     version_map: VersionMap,
-) -> std::result::Result<MicrovmState, LoadSnapshotError> {
+) -> std::result::Result<(MicrovmState, u64), LoadSnapshotError> {
     use self::LoadSnapshotError::{
         DeserializeMicrovmState, SnapshotBackingFile, SnapshotBackingFileMetadata,
     };
     let mut snapshot_reader = File::open(snapshot_path).map_err(SnapshotBackingFile)?;
     let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotBackingFileMetadata)?;
-    let snapshot_len = metadata.len() as usize;
-    Snapshot::load(&mut snapshot_reader, snapshot_len, version_map).map_err(DeserializeMicrovmState)
+    let snapshot_len = metadata.len();
+    let microvm_state = Snapshot::load(&mut snapshot_reader, snapshot_len as usize, version_map)
+        .map_err(DeserializeMicrovmState)?;
+    Ok((microvm_state, snapshot_len))
 }
 
 fn guest_memory_from_file(
@@ -288,8 +712,20 @@ fn guest_memory_from_file(
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
 ) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
-    use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile};
+    use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile, MemoryFileTooSmall};
     let mem_file = File::open(mem_file_path).map_err(MemoryBackingFile)?;
+    // A short memory file would still map fine, and the guest would only fault when touching
+    // the missing pages.
+    let file_size = mem_file.metadata().map_err(MemoryBackingFile)?.len();
+    let mem_size = mem_state
+        .regions
+        .iter()
+        .map(|region| region.offset + region.size as u64)
+        .max()
+        .unwrap_or(0);
+    if file_size < mem_size {
+        return Err(MemoryFileTooSmall(file_size, mem_size));
+    }
     GuestMemoryMmap::restore(&mem_file, mem_state, track_dirty_pages).map_err(DeserializeMemory)
 }
 
@@ -395,6 +831,204 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_atomic_snapshot_file() {
+        use utils::tempdir::TempDir;
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.as_path().join("snapshot");
+        let temp_path = dir.as_path().join(".snapshot.tmp");
+        let mut options = OpenOptions::new();
+        options.create(true).write(true);
+
+        // The target file is only replaced once committed.
+        fs::write(&path, b"old").unwrap();
+        let mut file = SnapshotFile::open(&path, &options, true).unwrap();
+        file.file.write_all(b"new").unwrap();
+        assert!(temp_path.exists());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        file.commit().unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+
+        // Uncommitted writes are discarded.
+        let mut file = SnapshotFile::open(&path, &options, true).unwrap();
+        file.file.write_all(b"partial").unwrap();
+        drop(file);
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+
+        // Non-atomic writes go straight to the target file.
+        let mut file = SnapshotFile::open(&path, &options, false).unwrap();
+        file.file.write_all(b"abc").unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read(&path).unwrap(), b"abc");
+        file.commit().unwrap();
+    }
+
+    #[test]
+    fn test_throttled_writer() {
+        use std::io::Read;
+        use std::time::Instant;
+
+        let data: Vec<u8> = (0..3 << 19).map(|i| i as u8).collect();
+        let mut file = TempFile::new().unwrap().into_file();
+
+        // Without a limit the data goes straight to the file.
+        ThrottledWriter::new(&mut file, None)
+            .write_all(&data)
+            .unwrap();
+
+        // The bucket starts off full with a second worth of tokens, so writing 1.5 seconds
+        // worth of data has to wait for the remaining half.
+        let start = Instant::now();
+        let mut writer = ThrottledWriter::new(&mut file, Some(1 << 20));
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        writer.write_all(&data).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        let mut written = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut written).unwrap();
+        assert_eq!(written, data);
+    }
+
+    // The filter allows the syscalls musl makes, since Firecracker is built with it. glibc
+    // e.g. implements `open()` on top of `openat`, which only aarch64 builds allow.
+    #[cfg(target_env = "musl")]
+    #[test]
+    fn test_create_snapshot_seccomp() {
+        use crate::default_syscalls::default_filter;
+        use crate::version_map::VERSION_MAP;
+        use seccomp::{SeccompAction, SeccompFilter, SeccompRule};
+        use std::convert::TryInto;
+        use utils::tempdir::TempDir;
+
+        // Syscalls the test thread itself needs, e.g. when exiting.
+        const TEST_SYSCALLS: [i64; 2] = [libc::SYS_rt_sigprocmask, libc::SYS_sigaltstack];
+
+        let dir = TempDir::new().unwrap();
+        let snapshot_path = dir.as_path().join("snapshot");
+        let mem_file_path = dir.as_path().join("mem");
+        let mut params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: snapshot_path.clone(),
+            mem_file_path: mem_file_path.clone(),
+            version: None,
+            sparse_mem_file: false,
+            atomic_write: true,
+            direct_io_mem_file: false,
+            // Less than the guest memory size, so that writing it gets throttled.
+            bandwidth_limit: Some(96 << 20),
+        };
+
+        // Spawn a new thread, so that the filter isn't installed for the other tests. Any syscall
+        // the filter doesn't allow kills the test process.
+        thread::spawn(move || {
+            let mut vmm = default_vmm();
+            let mut filter = default_filter().unwrap();
+            for syscall in TEST_SYSCALLS.iter() {
+                filter
+                    .add_rules(
+                        *syscall,
+                        vec![SeccompRule::new(vec![], SeccompAction::Allow)],
+                    )
+                    .unwrap();
+            }
+            SeccompFilter::apply(filter.try_into().unwrap()).unwrap();
+
+            create_snapshot(&mut vmm, &params, VERSION_MAP.clone()).unwrap();
+
+            // Failing to write the state removes its temporary file.
+            params.version = Some(String::from("0.0.0"));
+            assert!(create_snapshot(&mut vmm, &params, VERSION_MAP.clone()).is_err());
+        })
+        .join()
+        .unwrap();
+
+        let manifest: SnapshotManifest =
+            serde_json::from_slice(&fs::read(manifest_path(&snapshot_path)).unwrap()).unwrap();
+        assert_eq!(
+            manifest.mem_file_len,
+            fs::metadata(&mem_file_path).unwrap().len()
+        );
+        assert!(!dir.as_path().join(".snapshot.tmp").exists());
+    }
+
+    #[test]
+    fn test_validate_snapshot_manifest() {
+        use utils::tempdir::TempDir;
+
+        let dir = TempDir::new().unwrap();
+        let params = LoadSnapshotParams {
+            snapshot_path: dir.as_path().join("vm.snap"),
+            mem_file_path: dir.as_path().join("vm.mem"),
+            enable_diff_snapshots: false,
+        };
+        let version_map = VersionMap::new();
+        fs::write(&params.snapshot_path, b"state").unwrap();
+        fs::write(&params.mem_file_path, vec![0u8; 0x1000]).unwrap();
+
+        // Snapshots without a manifest are not validated.
+        assert!(validate_snapshot_manifest(&params, &version_map).is_ok());
+
+        let manifest = SnapshotManifest {
+            manifest_version: SNAPSHOT_MANIFEST_VERSION,
+            snapshot_id: String::from("id"),
+            snapshot_type: SnapshotType::Full,
+            data_version: 1,
+            created_at_us: 0,
+            vmm_version: String::from("0.0.0"),
+            guest_config_hash: String::from("0"),
+            mem_file_name: String::from("vm.mem"),
+            mem_file_len: 0x1000,
+            state_file_crc64: file_crc64(&params.snapshot_path).unwrap(),
+        };
+        let write_manifest = |manifest: &SnapshotManifest| {
+            fs::write(
+                manifest_path(&params.snapshot_path),
+                serde_json::to_vec(manifest).unwrap(),
+            )
+            .unwrap()
+        };
+        write_manifest(&manifest);
+        assert_eq!(
+            manifest_path(&params.snapshot_path),
+            dir.as_path().join("vm.snap.json")
+        );
+        assert!(validate_snapshot_manifest(&params, &version_map).is_ok());
+
+        // Files not matching the manifest are rejected.
+        fs::write(&params.snapshot_path, b"other state").unwrap();
+        assert!(matches!(
+            validate_snapshot_manifest(&params, &version_map),
+            Err(LoadSnapshotError::InvalidManifest(_))
+        ));
+        fs::write(&params.snapshot_path, b"state").unwrap();
+        fs::write(&params.mem_file_path, vec![0u8; 0x2000]).unwrap();
+        assert!(matches!(
+            validate_snapshot_manifest(&params, &version_map),
+            Err(LoadSnapshotError::InvalidManifest(_))
+        ));
+        fs::write(&params.mem_file_path, vec![0u8; 0x1000]).unwrap();
+
+        // So are manifests of snapshots too recent for this build.
+        write_manifest(&SnapshotManifest {
+            data_version: version_map.latest_version() + 1,
+            ..manifest
+        });
+        assert!(matches!(
+            validate_snapshot_manifest(&params, &version_map),
+            Err(LoadSnapshotError::UnsupportedVersion(_))
+        ));
+
+        fs::write(manifest_path(&params.snapshot_path), b"{").unwrap();
+        assert!(matches!(
+            validate_snapshot_manifest(&params, &version_map),
+            Err(LoadSnapshotError::InvalidManifest(_))
+        ));
+    }
+
     #[test]
     fn test_create_snapshot_error_display() {
         use crate::persist::CreateSnapshotError::*;
@@ -417,9 +1051,18 @@ mod tests {
         let err = MemoryBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = ManifestFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = MicrovmState(MicrovmStateError::UnexpectedVcpuResponse);
         let _ = format!("{}{:?}", err, err);
 
+        let err = ResumeDevices(MmioError::IncorrectDeviceType);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SerializeManifest(serde_json::from_str::<SnapshotManifest>("").unwrap_err());
+        let _ = format!("{}{:?}", err, err);
+
         let err = SerializeMicrovmState(snapshot::Error::InvalidMagic(0));
         let _ = format!("{}{:?}", err, err);
 
@@ -427,6 +1070,37 @@ mod tests {
         let _ = format!("{}{:?}", err, err);
     }
 
+    #[test]
+    fn test_guest_memory_from_file() {
+        use crate::memory_snapshot::GuestMemoryRegionState;
+
+        let mem_state = GuestMemoryState {
+            regions: vec![
+                GuestMemoryRegionState {
+                    base_address: 0,
+                    size: 0x1000,
+                    offset: 0,
+                },
+                GuestMemoryRegionState {
+                    base_address: 0x10_0000,
+                    size: 0x2000,
+                    offset: 0x1000,
+                },
+            ],
+        };
+        let mem_file = TempFile::new().unwrap();
+
+        // A memory file that doesn't hold all of the saved guest memory is rejected.
+        mem_file.as_file().set_len(0x2000).unwrap();
+        match guest_memory_from_file(&mem_file.as_path().to_path_buf(), &mem_state, false) {
+            Err(LoadSnapshotError::MemoryFileTooSmall(0x2000, 0x3000)) => (),
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        mem_file.as_file().set_len(0x3000).unwrap();
+        guest_memory_from_file(&mem_file.as_path().to_path_buf(), &mem_state, false).unwrap();
+    }
+
     #[test]
     fn test_load_snapshot_error_display() {
         use crate::persist::LoadSnapshotError::*;
@@ -442,26 +1116,51 @@ mod tests {
         let err = DeserializeMicrovmState(snapshot::Error::Io(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = InvalidManifest("foo".to_string());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = ManifestFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = MemoryBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = MemoryFileTooSmall(128, 256);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MemorySizeMismatch(128, 256);
+        let _ = format!("{}{:?}", err, err);
+
         let err = SnapshotBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
         let err = SnapshotBackingFileMetadata(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
+
+        let err = UnsupportedVersion("foo".to_string());
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
     fn test_microvm_state_error_display() {
         use crate::persist::MicrovmStateError::*;
 
+        let err = IncompatibleCpuFeatures(vstate::vcpu::VcpuError::MissingCpuFeatures {
+            leaf: 0x1,
+            register: "ecx",
+            bits: 1,
+        });
+        let _ = format!("{}{:?}", err, err);
+
         let err = InvalidInput;
         let _ = format!("{}{:?}", err, err);
 
         let err = NotAllowed(String::from(""));
         let _ = format!("{}{:?}", err, err);
 
+        let err = QuiesceDevices(MmioError::IncorrectDeviceType);
+        let _ = format!("{}{:?}", err, err);
+
         let err = RestoreDevices(DevicePersistError::MmioTransport);
         let _ = format!("{}{:?}", err, err);
 
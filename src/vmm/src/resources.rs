@@ -217,6 +217,22 @@ impl VmResources {
             return Err(VmConfigError::InvalidVcpuCount);
         }
 
+        // The memory backend, be it new or previously set, needs to be able to back the
+        // (potentially updated) memory size.
+        if let Some(mem_backend) = machine_config
+            .mem_backend
+            .as_ref()
+            .or_else(|| self.vm_config.mem_backend.as_ref())
+        {
+            let mem_size_mib = machine_config
+                .mem_size_mib
+                .or(self.vm_config.mem_size_mib)
+                .unwrap_or(DEFAULT_MEM_SIZE_MIB);
+            mem_backend
+                .validate(mem_size_mib << 20)
+                .map_err(|e| VmConfigError::InvalidMemoryBackend(e.to_string()))?;
+        }
+
         // Update all the fields that have a new value.
         self.vm_config.vcpu_count = Some(vcpu_count_value);
         self.vm_config.ht_enabled = Some(ht_enabled);
@@ -230,6 +246,10 @@ impl VmResources {
             self.vm_config.cpu_template = machine_config.cpu_template;
         }
 
+        if machine_config.mem_backend.is_some() {
+            self.vm_config.mem_backend = machine_config.mem_backend.clone();
+        }
+
         Ok(())
     }
 
@@ -355,6 +375,7 @@ mod tests {
     use crate::vmm_config::boot_source::{BootConfig, BootSourceConfig, DEFAULT_KERNEL_CMDLINE};
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
     use crate::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, VmConfigError};
+    use crate::vmm_config::memory_backend::{MemoryBackendConfig, MemoryBackendType};
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
@@ -787,6 +808,7 @@ mod tests {
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
             track_dirty_pages: false,
+            mem_backend: None,
         };
 
         assert_ne!(vm_resources.vm_config, aux_vm_config);
@@ -831,6 +853,37 @@ mod tests {
         // mem_size_mib compatible with balloon size.
         aux_vm_config.mem_size_mib = Some(256);
         assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+
+        // Memory backend file too small for mem_size_mib.
+        let mem_file = TempFile::new().unwrap();
+        mem_file.as_file().set_len(128 << 20).unwrap();
+        aux_vm_config.mem_backend = Some(MemoryBackendConfig {
+            backend_type: MemoryBackendType::File,
+            path: Some(mem_file.as_path().to_path_buf()),
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        });
+        match vm_resources.set_vm_config(&aux_vm_config) {
+            Err(VmConfigError::InvalidMemoryBackend(_)) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // Valid memory backend.
+        aux_vm_config.mem_size_mib = Some(128);
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(
+            vm_resources.vm_config.mem_backend,
+            aux_vm_config.mem_backend
+        );
+
+        // The previously set memory backend is validated against memory size updates.
+        aux_vm_config.mem_backend = None;
+        aux_vm_config.mem_size_mib = Some(256);
+        match vm_resources.set_vm_config(&aux_vm_config) {
+            Err(VmConfigError::InvalidMemoryBackend(_)) => (),
+            other => panic!("{:?}", other),
+        }
     }
 
     #[test]
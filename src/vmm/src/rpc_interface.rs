@@ -5,6 +5,8 @@ use std::fmt::{Display, Formatter};
 use std::result;
 use std::sync::{Arc, Mutex};
 
+use serde::Serialize;
+
 #[cfg(not(test))]
 use super::{builder::build_microvm_for_boot, resources::VmResources, Vmm};
 #[cfg(all(not(test), target_arch = "x86_64"))]
@@ -18,6 +20,8 @@ use tests::{create_snapshot, load_snapshot};
 use super::Error as VmmError;
 use crate::builder::StartMicrovmError;
 #[cfg(target_arch = "x86_64")]
+use crate::memory_snapshot;
+#[cfg(target_arch = "x86_64")]
 use crate::persist::{CreateSnapshotError, LoadSnapshotError};
 #[cfg(target_arch = "x86_64")]
 use crate::version_map::VERSION_MAP;
@@ -36,10 +40,13 @@ use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::{
     NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
 };
+use crate::vmm_config::snapshot::SnapshotStatus;
 #[cfg(target_arch = "x86_64")]
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, SnapshotOperation, SnapshotType,
+};
 use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
-use logger::{info, update_metric_with_elapsed_time, METRICS};
+use logger::{info, update_metric_with_elapsed_time, warn, METRICS};
 use polly::event_manager::EventManager;
 use seccomp::BpfProgram;
 
@@ -64,6 +71,9 @@ pub enum VmmAction {
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get the status of the most recent snapshot create or load operation.
+    #[cfg(target_arch = "x86_64")]
+    GetSnapshotStatus,
     /// Get the configuration of the microVM.
     GetVmConfiguration,
     /// Flush the metrics. This action can only be called after the logger has been configured.
@@ -198,6 +208,152 @@ impl Display for VmmActionError {
     }
 }
 
+/// Machine-readable codes for the errors surfaced through the API.
+///
+/// The codes are serialized in snake case (e.g. `load_snapshot`) alongside the fault message of
+/// API error responses. They are part of the API contract: existing codes must not be renamed
+/// or reused, and their set does not depend on the target architecture.
+///
+/// Where the cause of a failure is known, e.g. a full disk, the code identifies the cause;
+/// otherwise, it identifies the failed operation.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Invalid balloon device configuration.
+    BalloonConfig,
+    /// Invalid boot source configuration.
+    BootSource,
+    /// Creating a microVM snapshot failed.
+    CreateSnapshot,
+    /// Invalid block device configuration.
+    DriveConfig,
+    /// The snapshot was created by, or for, a Firecracker version, architecture or byte order
+    /// this build doesn't support.
+    IncompatibleSnapshotVersion,
+    /// Internal VMM error.
+    InternalVmm,
+    /// The snapshot memory file can't be opened, or doesn't match the snapshot.
+    InvalidMemoryFile,
+    /// The snapshot files can't be read, are corrupted, or don't match each other.
+    InvalidSnapshotFile,
+    /// Loading a microVM snapshot failed.
+    LoadSnapshot,
+    /// Loading a microVM snapshot is not allowed after configuring boot-specific resources.
+    LoadSnapshotNotAllowed,
+    /// Invalid logger configuration.
+    Logger,
+    /// Invalid machine configuration.
+    MachineConfig,
+    /// Invalid metrics configuration.
+    Metrics,
+    /// Invalid MMDS configuration.
+    MmdsConfig,
+    /// Invalid network interface configuration.
+    NetworkConfig,
+    /// The filesystem holding the snapshot files is full, or the disk quota was exceeded.
+    NoSpace,
+    /// The operation is not supported after starting the microVM.
+    OperationNotSupportedPostBoot,
+    /// The operation is not supported before starting the microVM.
+    OperationNotSupportedPreBoot,
+    /// Starting the microVM failed.
+    StartMicrovm,
+    /// Invalid vsock device configuration.
+    VsockConfig,
+}
+
+impl VmmActionError {
+    /// Returns the machine-readable code of this error.
+    pub fn error_code(&self) -> ErrorCode {
+        use self::VmmActionError::*;
+
+        match self {
+            BalloonConfig(_) => ErrorCode::BalloonConfig,
+            BootSource(_) => ErrorCode::BootSource,
+            #[cfg(target_arch = "x86_64")]
+            CreateSnapshot(err) => create_snapshot_error_code(err),
+            DriveConfig(_) => ErrorCode::DriveConfig,
+            InternalVmm(_) => ErrorCode::InternalVmm,
+            #[cfg(target_arch = "x86_64")]
+            LoadSnapshot(err) => load_snapshot_error_code(err),
+            #[cfg(target_arch = "x86_64")]
+            LoadSnapshotNotAllowed => ErrorCode::LoadSnapshotNotAllowed,
+            Logger(_) => ErrorCode::Logger,
+            MachineConfig(_) => ErrorCode::MachineConfig,
+            Metrics(_) => ErrorCode::Metrics,
+            MmdsConfig(_) => ErrorCode::MmdsConfig,
+            NetworkConfig(_) => ErrorCode::NetworkConfig,
+            OperationNotSupportedPostBoot => ErrorCode::OperationNotSupportedPostBoot,
+            OperationNotSupportedPreBoot => ErrorCode::OperationNotSupportedPreBoot,
+            StartMicrovm(_) => ErrorCode::StartMicrovm,
+            VsockConfig(_) => ErrorCode::VsockConfig,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn create_snapshot_error_code(err: &CreateSnapshotError) -> ErrorCode {
+    use self::CreateSnapshotError::*;
+
+    let io_err = match err {
+        InvalidVersion => return ErrorCode::IncompatibleSnapshotVersion,
+        SerializeMicrovmState(err) => {
+            return snapshot_error_code(err).unwrap_or(ErrorCode::CreateSnapshot)
+        }
+        Memory(memory_snapshot::Error::FileHandle(err))
+        | Memory(memory_snapshot::Error::WriteMemory(vm_memory::GuestMemoryError::IOError(err)))
+        | MemoryBackingFile(err)
+        | ManifestFile(err)
+        | SnapshotBackingFile(err) => err,
+        _ => return ErrorCode::CreateSnapshot,
+    };
+    match io_err.raw_os_error() {
+        Some(errno) if is_no_space(errno) => ErrorCode::NoSpace,
+        _ => ErrorCode::CreateSnapshot,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn load_snapshot_error_code(err: &LoadSnapshotError) -> ErrorCode {
+    use self::LoadSnapshotError::*;
+
+    match err {
+        BuildMicroVm(_) | ManifestFile(_) => ErrorCode::LoadSnapshot,
+        DeserializeMemory(_)
+        | MemoryBackingFile(_)
+        | MemoryFileTooSmall(_, _)
+        | MemorySizeMismatch(_, _) => ErrorCode::InvalidMemoryFile,
+        DeserializeMicrovmState(err) => {
+            snapshot_error_code(err).unwrap_or(ErrorCode::InvalidSnapshotFile)
+        }
+        InvalidManifest(_) | SnapshotBackingFile(_) | SnapshotBackingFileMetadata(_) => {
+            ErrorCode::InvalidSnapshotFile
+        }
+        UnsupportedVersion(_) => ErrorCode::IncompatibleSnapshotVersion,
+    }
+}
+
+// Returns the code of the snapshot (de)serialization errors which have a known cause.
+#[cfg(target_arch = "x86_64")]
+fn snapshot_error_code(err: &snapshot::Error) -> Option<ErrorCode> {
+    use snapshot::Error::*;
+
+    match err {
+        InvalidDataVersion(_)
+        | InvalidFormatVersion(_)
+        | NoCommonDataVersion(_)
+        | ArchMismatch { .. }
+        | EndiannessMismatch { .. } => Some(ErrorCode::IncompatibleSnapshotVersion),
+        Io(errno) if is_no_space(*errno) => Some(ErrorCode::NoSpace),
+        _ => None,
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn is_no_space(errno: i32) -> bool {
+    errno == libc::ENOSPC || errno == libc::EDQUOT
+}
+
 /// The enum represents the response sent by the VMM in case of success. The response is either
 /// empty, when no data needs to be sent, or an internal VMM structure.
 #[derive(Debug, PartialEq)]
@@ -210,6 +366,8 @@ pub enum VmmData {
     Empty,
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The status of the most recent snapshot operation.
+    SnapshotStatus(SnapshotStatus),
 }
 
 /// Shorthand result type for external VMM commands.
@@ -225,6 +383,10 @@ pub struct PrebootApiController<'a> {
     // Configuring boot specific resources will set this to true.
     // Loading from snapshot will not be allowed once this is true.
     boot_path: bool,
+    // Status of the last failed snapshot load. A successful load hands
+    // its status over to the built `Vmm`.
+    #[cfg(target_arch = "x86_64")]
+    snapshot_status: SnapshotStatus,
 }
 
 impl<'a> PrebootApiController<'a> {
@@ -242,6 +404,8 @@ impl<'a> PrebootApiController<'a> {
             event_manager,
             built_vmm: None,
             boot_path: false,
+            #[cfg(target_arch = "x86_64")]
+            snapshot_status: SnapshotStatus::default(),
         }
     }
 
@@ -300,6 +464,8 @@ impl<'a> PrebootApiController<'a> {
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
             GetBalloonConfig => self.balloon_config(),
+            #[cfg(target_arch = "x86_64")]
+            GetSnapshotStatus => Ok(VmmData::SnapshotStatus(self.snapshot_status.clone())),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
@@ -428,12 +594,40 @@ impl<'a> PrebootApiController<'a> {
             update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_load_snapshot, load_start_us);
         info!("'load snapshot' VMM action took {} us.", elapsed_time_us);
 
-        loaded_vmm
-            .map(|vmm| {
+        let status = SnapshotStatus::new(
+            SnapshotOperation::Load,
+            &loaded_vmm.as_ref().map(|_| 0),
+            elapsed_time_us,
+        );
+        match loaded_vmm {
+            Ok(vmm) => {
+                let mut locked_vmm = vmm.lock().expect("Poisoned lock");
+                // The machine configuration comes from the snapshot, so that it reflects
+                // the topology the restored guest actually runs with.
+                let vm_config = VmConfig {
+                    vcpu_count: Some(locked_vmm.vcpu_count() as u8),
+                    mem_size_mib: Some(locked_vmm.mem_size_mib() as usize),
+                    track_dirty_pages: load_params.enable_diff_snapshots,
+                    ..Default::default()
+                };
+                // The guest is already restored at this point; failing to record its
+                // configuration must not turn the load into an error.
+                if let Err(err) = self.vm_resources.set_vm_config(&vm_config) {
+                    warn!(
+                        "Cannot update the machine configuration after loading the snapshot: {}",
+                        err
+                    );
+                }
+                locked_vmm.set_snapshot_status(status);
+                drop(locked_vmm);
                 self.built_vmm = Some(vmm);
-                VmmData::Empty
-            })
-            .map_err(VmmActionError::LoadSnapshot)
+                Ok(VmmData::Empty)
+            }
+            Err(err) => {
+                self.snapshot_status = status;
+                Err(VmmActionError::LoadSnapshot(err))
+            }
+        }
     }
 }
 
@@ -466,6 +660,10 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            #[cfg(target_arch = "x86_64")]
+            GetSnapshotStatus => Ok(VmmData::SnapshotStatus(
+                self.vmm.lock().expect("Poisoned lock").snapshot_status(),
+            )),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(self.vm_config.clone())),
             Pause => self.pause(),
             Resume => self.resume(),
@@ -574,8 +772,15 @@ impl RuntimeApiController {
         let mut locked_vmm = self.vmm.lock().unwrap();
         let create_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
-        create_snapshot(&mut locked_vmm, create_params, VERSION_MAP.clone())
-            .map_err(VmmActionError::CreateSnapshot)?;
+        let outcome = create_snapshot(&mut locked_vmm, create_params, VERSION_MAP.clone());
+        let duration_us =
+            utils::time::get_time_us(utils::time::ClockType::Monotonic) - create_start_us;
+        locked_vmm.set_snapshot_status(SnapshotStatus::new(
+            SnapshotOperation::Create,
+            &outcome,
+            duration_us,
+        ));
+        outcome.map_err(VmmActionError::CreateSnapshot)?;
 
         match create_params.snapshot_type {
             SnapshotType::Full => {
@@ -784,11 +989,32 @@ mod tests {
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub snapshot_status: SnapshotStatus,
+        pub vcpu_count: usize,
+        pub mem_size_mib: u64,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
 
     impl MockVmm {
+        #[cfg(target_arch = "x86_64")]
+        pub fn snapshot_status(&self) -> SnapshotStatus {
+            self.snapshot_status.clone()
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_snapshot_status(&mut self, status: SnapshotStatus) {
+            self.snapshot_status = status;
+        }
+
+        pub fn vcpu_count(&self) -> usize {
+            self.vcpu_count
+        }
+
+        pub fn mem_size_mib(&self) -> u64 {
+            self.mem_size_mib
+        }
+
         pub fn resume_vm(&mut self) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::VcpuResume);
@@ -893,8 +1119,8 @@ mod tests {
         _: &mut Vmm,
         _: &CreateSnapshotParams,
         _: versionize::VersionMap,
-    ) -> std::result::Result<(), CreateSnapshotError> {
-        Ok(())
+    ) -> std::result::Result<u64, CreateSnapshotError> {
+        Ok(0x1000)
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -906,7 +1132,11 @@ mod tests {
         _: &LoadSnapshotParams,
         _: versionize::VersionMap,
     ) -> Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
-        Ok(Arc::new(Mutex::new(MockVmm::default())))
+        Ok(Arc::new(Mutex::new(MockVmm {
+            vcpu_count: 2,
+            mem_size_mib: 256,
+            ..Default::default()
+        })))
     }
 
     fn default_preboot<'a>(
@@ -1088,6 +1318,7 @@ mod tests {
             vsock_id: String::new(),
             guest_cid: 0,
             uds_path: String::new(),
+            queue_size: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1098,6 +1329,7 @@ mod tests {
             vsock_id: String::new(),
             guest_cid: 0,
             uds_path: String::new(),
+            queue_size: None,
         });
         check_preboot_request_err(
             req,
@@ -1169,6 +1401,10 @@ mod tests {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
                 version: None,
+                sparse_mem_file: false,
+                atomic_write: false,
+                direct_io_mem_file: false,
+                bandwidth_limit: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1280,6 +1516,39 @@ mod tests {
         check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuResume));
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_create_snapshot() {
+        use crate::vmm_config::snapshot::SnapshotOperationState;
+
+        let req = VmmAction::GetSnapshotStatus;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::SnapshotStatus(SnapshotStatus::default()))
+            );
+        });
+
+        let req = VmmAction::CreateSnapshot(CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::new(),
+            mem_file_path: PathBuf::new(),
+            version: None,
+            sparse_mem_file: false,
+            atomic_write: false,
+            direct_io_mem_file: false,
+            bandwidth_limit: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            let status = vmm.snapshot_status();
+            assert_eq!(status.operation, Some(SnapshotOperation::Create));
+            assert_eq!(status.state, SnapshotOperationState::Succeeded);
+            assert_eq!(status.bytes_written, 0x1000);
+            assert_eq!(status.error, None);
+        });
+    }
+
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_runtime_ctrl_alt_del() {
@@ -1455,6 +1724,7 @@ mod tests {
                 vsock_id: String::new(),
                 guest_cid: 0,
                 uds_path: String::new(),
+                queue_size: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1467,6 +1737,7 @@ mod tests {
                 vsock_id: String::new(),
                 guest_cid: 0,
                 uds_path: String::new(),
+                queue_size: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1546,6 +1817,7 @@ mod tests {
             vsock_id: String::new(),
             guest_cid: 0,
             uds_path: String::new(),
+            queue_size: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
 
@@ -1555,4 +1827,200 @@ mod tests {
         let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
     }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_preboot_load_snapshot_status() {
+        use crate::vmm_config::snapshot::SnapshotOperationState;
+
+        let mut vm_resources = MockVmRes::default();
+        let mut evmgr = EventManager::new().unwrap();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr);
+
+        assert_eq!(
+            preboot.handle_preboot_request(VmmAction::GetSnapshotStatus),
+            Ok(VmmData::SnapshotStatus(SnapshotStatus::default()))
+        );
+
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_file_path: PathBuf::new(),
+            enable_diff_snapshots: false,
+        });
+        assert_eq!(preboot.handle_preboot_request(req), Ok(VmmData::Empty));
+
+        // The status of a successful load is carried over by the built `Vmm`.
+        let status = preboot
+            .built_vmm
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .snapshot_status();
+        assert_eq!(status.operation, Some(SnapshotOperation::Load));
+        assert_eq!(status.state, SnapshotOperationState::Succeeded);
+        assert_eq!(status.bytes_written, 0);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_preboot_load_snapshot_vm_config() {
+        let mut vm_resources = MockVmRes::default();
+        let mut evmgr = EventManager::new().unwrap();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr);
+
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_file_path: PathBuf::new(),
+            enable_diff_snapshots: true,
+        });
+        assert_eq!(preboot.handle_preboot_request(req), Ok(VmmData::Empty));
+
+        // The machine configuration reflects the restored microVM.
+        let expected_cfg = VmConfig {
+            vcpu_count: Some(2),
+            mem_size_mib: Some(256),
+            track_dirty_pages: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            preboot.handle_preboot_request(VmmAction::GetVmConfiguration),
+            Ok(VmmData::MachineConfiguration(expected_cfg))
+        );
+
+        // Failing to record the machine configuration keeps the restored microVM.
+        let mut vm_resources = MockVmRes {
+            force_errors: true,
+            ..Default::default()
+        };
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr);
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_file_path: PathBuf::new(),
+            enable_diff_snapshots: false,
+        });
+        assert_eq!(preboot.handle_preboot_request(req), Ok(VmmData::Empty));
+        assert!(preboot.built_vmm.is_some());
+    }
+
+    #[test]
+    fn test_error_code() {
+        assert_eq!(
+            VmmActionError::OperationNotSupportedPreBoot.error_code(),
+            ErrorCode::OperationNotSupportedPreBoot
+        );
+        assert_eq!(
+            VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig).error_code(),
+            ErrorCode::StartMicrovm
+        );
+        assert_eq!(
+            VmmActionError::VsockConfig(VsockConfigError::CreateVsockDevice(
+                VsockError::BufDescMissing
+            ))
+            .error_code(),
+            ErrorCode::VsockConfig
+        );
+
+        // The codes are serialized in snake case.
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::LoadSnapshotNotAllowed).unwrap(),
+            "\"load_snapshot_not_allowed\""
+        );
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::InternalVmm).unwrap(),
+            "\"internal_vmm\""
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_snapshot_error_code() {
+        let no_space = || std::io::Error::from_raw_os_error(libc::ENOSPC);
+
+        // Snapshot errors are reported by cause where it is known.
+        assert_eq!(
+            VmmActionError::CreateSnapshot(CreateSnapshotError::InvalidVersion).error_code(),
+            ErrorCode::IncompatibleSnapshotVersion
+        );
+        assert_eq!(
+            VmmActionError::CreateSnapshot(CreateSnapshotError::Memory(
+                memory_snapshot::Error::FileHandle(no_space())
+            ))
+            .error_code(),
+            ErrorCode::NoSpace
+        );
+        assert_eq!(
+            VmmActionError::CreateSnapshot(CreateSnapshotError::SerializeMicrovmState(
+                snapshot::Error::Io(libc::EDQUOT)
+            ))
+            .error_code(),
+            ErrorCode::NoSpace
+        );
+        assert_eq!(
+            VmmActionError::LoadSnapshot(LoadSnapshotError::UnsupportedVersion(String::new()))
+                .error_code(),
+            ErrorCode::IncompatibleSnapshotVersion
+        );
+        assert_eq!(
+            VmmActionError::LoadSnapshot(LoadSnapshotError::DeserializeMicrovmState(
+                snapshot::Error::InvalidDataVersion(0)
+            ))
+            .error_code(),
+            ErrorCode::IncompatibleSnapshotVersion
+        );
+        assert_eq!(
+            VmmActionError::LoadSnapshot(LoadSnapshotError::DeserializeMicrovmState(
+                snapshot::Error::ArchMismatch {
+                    found: snapshot::SnapshotArch::Aarch64,
+                    expected: snapshot::SnapshotArch::X86_64,
+                }
+            ))
+            .error_code(),
+            ErrorCode::IncompatibleSnapshotVersion
+        );
+        assert_eq!(
+            VmmActionError::LoadSnapshot(LoadSnapshotError::DeserializeMicrovmState(
+                snapshot::Error::EndiannessMismatch {
+                    found: snapshot::SnapshotEndianness::Big,
+                    expected: snapshot::SnapshotEndianness::Little,
+                }
+            ))
+            .error_code(),
+            ErrorCode::IncompatibleSnapshotVersion
+        );
+        assert_eq!(
+            VmmActionError::LoadSnapshot(LoadSnapshotError::DeserializeMicrovmState(
+                snapshot::Error::Crc64(0)
+            ))
+            .error_code(),
+            ErrorCode::InvalidSnapshotFile
+        );
+        assert_eq!(
+            VmmActionError::LoadSnapshot(LoadSnapshotError::MemorySizeMismatch(128, 256))
+                .error_code(),
+            ErrorCode::InvalidMemoryFile
+        );
+        assert_eq!(
+            VmmActionError::LoadSnapshot(LoadSnapshotError::MemoryFileTooSmall(128, 256))
+                .error_code(),
+            ErrorCode::InvalidMemoryFile
+        );
+
+        // Otherwise, by the failed operation.
+        assert_eq!(
+            VmmActionError::CreateSnapshot(CreateSnapshotError::ManifestFile(
+                std::io::Error::from_raw_os_error(libc::EACCES)
+            ))
+            .error_code(),
+            ErrorCode::CreateSnapshot
+        );
+        assert_eq!(
+            VmmActionError::CreateSnapshot(CreateSnapshotError::DirtyBitmap).error_code(),
+            ErrorCode::CreateSnapshot
+        );
+        assert_eq!(
+            serde_json::to_string(&ErrorCode::IncompatibleSnapshotVersion).unwrap(),
+            "\"incompatible_snapshot_version\""
+        );
+    }
 }
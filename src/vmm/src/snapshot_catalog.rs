@@ -0,0 +1,411 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps track of chains of full and diff snapshots.
+//!
+//! A diff snapshot is only usable together with the full snapshot it was taken on top of and
+//! every diff snapshot in between. The `SnapshotCatalog` records these relations in a small
+//! JSON index and can compact a chain into a single full snapshot by layering the memory files
+//! of the diff snapshots on top of the memory file of their base.
+
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use utils::time::{get_time_us, ClockType};
+
+use crate::vmm_config::snapshot::SnapshotType;
+
+/// Errors associated with the snapshot catalog.
+#[derive(Debug)]
+pub enum Error {
+    /// A snapshot with the same id is already in the catalog.
+    DuplicateId(String),
+    /// The catalog index could not be parsed.
+    Deserialize(serde_json::Error),
+    /// A full snapshot can't have a parent, a diff snapshot needs one.
+    InvalidParent(String),
+    /// Failed to read or write a snapshot or index file.
+    Io(io::Error),
+    /// The catalog index could not be serialized.
+    Serialize(serde_json::Error),
+    /// There is no snapshot with this id in the catalog.
+    UnknownId(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+        match self {
+            DuplicateId(id) => write!(f, "Snapshot {} is already in the catalog", id),
+            Deserialize(err) => write!(f, "Cannot parse snapshot catalog: {}", err),
+            InvalidParent(id) => write!(f, "Invalid parent for snapshot {}", id),
+            Io(err) => write!(f, "Snapshot catalog I/O error: {}", err),
+            Serialize(err) => write!(f, "Cannot serialize snapshot catalog: {}", err),
+            UnknownId(id) => write!(f, "Snapshot {} is not in the catalog", id),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A snapshot recorded in the catalog.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SnapshotEntry {
+    /// Unique id of the snapshot.
+    pub id: String,
+    /// Id of the snapshot this diff snapshot was taken on top of. `None` for full snapshots.
+    pub parent: Option<String>,
+    /// Type of the snapshot.
+    pub snapshot_type: SnapshotType,
+    /// Path to the file containing the microVM state.
+    pub snapshot_path: PathBuf,
+    /// Path to the file containing the guest memory.
+    pub mem_file_path: PathBuf,
+    /// Wall clock time at which the snapshot was added to the catalog, in microseconds.
+    pub created_at_us: u64,
+    /// Number of pages backed by data in the memory file, which for diff snapshots are the
+    /// pages dirtied since the parent snapshot.
+    pub dirty_pages: u64,
+}
+
+/// Index of the snapshots of a microVM.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SnapshotCatalog {
+    entries: Vec<SnapshotEntry>,
+}
+
+impl SnapshotCatalog {
+    /// Loads a catalog from the index at `path`. A missing index yields an empty catalog.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(Error::Deserialize),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+
+    /// Writes the catalog to the index at `path`, replacing the previous index atomically.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(Error::Serialize)?;
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+
+        let mut file = File::create(&temp_path).map_err(Error::Io)?;
+        file.write_all(&data)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|err| {
+                let _ = fs::remove_file(&temp_path);
+                Error::Io(err)
+            })
+    }
+
+    /// Returns all snapshots in the order they were added.
+    pub fn entries(&self) -> &[SnapshotEntry] {
+        &self.entries
+    }
+
+    /// Returns the snapshot with id `id`.
+    pub fn get(&self, id: &str) -> Option<&SnapshotEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// Records a snapshot created by Firecracker.
+    ///
+    /// Diff snapshots have to name the snapshot they were taken on top of as `parent`, full
+    /// snapshots can't have a parent. The dirty page count is derived from the memory file.
+    pub fn add(
+        &mut self,
+        id: &str,
+        parent: Option<&str>,
+        snapshot_type: SnapshotType,
+        snapshot_path: &Path,
+        mem_file_path: &Path,
+    ) -> Result<&SnapshotEntry> {
+        if self.get(id).is_some() {
+            return Err(Error::DuplicateId(id.to_string()));
+        }
+        match (&snapshot_type, parent) {
+            (SnapshotType::Full, None) => (),
+            (SnapshotType::Diff, Some(parent)) if self.get(parent).is_some() => (),
+            _ => return Err(Error::InvalidParent(id.to_string())),
+        }
+
+        let mem_file = File::open(mem_file_path).map_err(Error::Io)?;
+        let dirty_pages = data_extents(&mem_file)
+            .map_err(Error::Io)?
+            .iter()
+            .map(|(start, end)| page_count(*start, *end))
+            .sum();
+
+        self.entries.push(SnapshotEntry {
+            id: id.to_string(),
+            parent: parent.map(str::to_string),
+            snapshot_type,
+            snapshot_path: snapshot_path.to_path_buf(),
+            mem_file_path: mem_file_path.to_path_buf(),
+            created_at_us: get_time_us(ClockType::Real),
+            dirty_pages,
+        });
+        Ok(self.entries.last().unwrap())
+    }
+
+    /// Returns the snapshots needed to restore snapshot `id`, starting with the full snapshot
+    /// at the base of the chain and ending with `id` itself.
+    pub fn chain(&self, id: &str) -> Result<Vec<&SnapshotEntry>> {
+        let mut chain = Vec::new();
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let entry = self
+                .get(id)
+                .ok_or_else(|| Error::UnknownId(id.to_string()))?;
+            chain.push(entry);
+            next = entry.parent.as_deref();
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Compacts the chain ending in snapshot `id` into a new full snapshot `new_id`.
+    ///
+    /// The memory file of the base snapshot is copied to `mem_file_path` and the data extents
+    /// of every diff memory file are written on top of it, in chain order. The microVM state of
+    /// snapshot `id` is copied to `snapshot_path`. Diff memory files are expected to be sparse,
+    /// with holes in place of the pages that were not dirtied, which requires a host
+    /// filesystem supporting `SEEK_DATA`/`SEEK_HOLE`.
+    pub fn compact(
+        &mut self,
+        id: &str,
+        new_id: &str,
+        snapshot_path: &Path,
+        mem_file_path: &Path,
+    ) -> Result<&SnapshotEntry> {
+        if self.get(new_id).is_some() {
+            return Err(Error::DuplicateId(new_id.to_string()));
+        }
+        let chain = self.chain(id)?;
+
+        let mut mem_file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(mem_file_path)
+            .map_err(Error::Io)?;
+        for entry in chain.iter() {
+            let mut src = File::open(&entry.mem_file_path).map_err(Error::Io)?;
+            copy_data_extents(&mut src, &mut mem_file).map_err(Error::Io)?;
+        }
+        mem_file.sync_all().map_err(Error::Io)?;
+
+        let state_path = chain.last().unwrap().snapshot_path.clone();
+        fs::copy(&state_path, snapshot_path).map_err(Error::Io)?;
+
+        self.add(
+            new_id,
+            None,
+            SnapshotType::Full,
+            snapshot_path,
+            mem_file_path,
+        )
+    }
+}
+
+fn page_count(start: u64, end: u64) -> u64 {
+    let page_size = sysconf::page::pagesize() as u64;
+    (end - start + page_size - 1) / page_size
+}
+
+fn lseek(file: &File, offset: u64, whence: libc::c_int) -> io::Result<Option<u64>> {
+    // Safe because the file descriptor is valid and the call doesn't touch memory.
+    let ret = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+    if ret >= 0 {
+        return Ok(Some(ret as u64));
+    }
+    match io::Error::last_os_error() {
+        // There is no data past `offset`.
+        ref err if err.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        err => Err(err),
+    }
+}
+
+// Returns the `[start, end)` ranges of `file` that are backed by data.
+fn data_extents(file: &File) -> io::Result<Vec<(u64, u64)>> {
+    let mut extents = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = lseek(file, offset, libc::SEEK_DATA)? {
+        // Every offset is followed by a hole, at the latest the implicit one at the end.
+        let end = lseek(file, start, libc::SEEK_HOLE)?
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENXIO))?;
+        extents.push((start, end));
+        offset = end;
+    }
+    Ok(extents)
+}
+
+// Writes the data extents of `src` at the same offsets in `dst`, growing `dst` to at least the
+// size of `src`.
+fn copy_data_extents(src: &mut File, dst: &mut File) -> io::Result<()> {
+    for (start, end) in data_extents(src)? {
+        src.seek(SeekFrom::Start(start))?;
+        dst.seek(SeekFrom::Start(start))?;
+        let copied = io::copy(&mut src.take(end - start), dst)?;
+        if copied != end - start {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+    }
+
+    let len = src.metadata()?.len();
+    if dst.metadata()?.len() < len {
+        dst.set_len(len)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempdir::TempDir;
+
+    fn write_pages(path: &Path, len: u64, pages: &[(u64, u8)]) {
+        let page_size = sysconf::page::pagesize() as u64;
+        let mut file = File::create(path).unwrap();
+        file.set_len(len * page_size).unwrap();
+        for (page, value) in pages {
+            file.seek(SeekFrom::Start(page * page_size)).unwrap();
+            file.write_all(&vec![*value; page_size as usize]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_catalog() {
+        let dir = TempDir::new().unwrap();
+        let path = |name: &str| dir.as_path().join(name);
+        let page_size = sysconf::page::pagesize();
+
+        write_pages(&path("base.mem"), 4, &[(0, 1), (1, 1), (2, 1), (3, 1)]);
+        write_pages(&path("diff1.mem"), 4, &[(1, 2)]);
+        write_pages(&path("diff2.mem"), 4, &[(1, 3), (3, 3)]);
+        for name in &["base.state", "diff1.state", "diff2.state"] {
+            fs::write(path(name), name.as_bytes()).unwrap();
+        }
+
+        let mut catalog = SnapshotCatalog::default();
+        catalog
+            .add(
+                "base",
+                None,
+                SnapshotType::Full,
+                &path("base.state"),
+                &path("base.mem"),
+            )
+            .unwrap();
+        let entry = catalog
+            .add(
+                "diff1",
+                Some("base"),
+                SnapshotType::Diff,
+                &path("diff1.state"),
+                &path("diff1.mem"),
+            )
+            .unwrap();
+        assert_eq!(entry.parent.as_deref(), Some("base"));
+        // Filesystems without hole support report the whole file as data.
+        assert!(entry.dirty_pages >= 1);
+        catalog
+            .add(
+                "diff2",
+                Some("diff1"),
+                SnapshotType::Diff,
+                &path("diff2.state"),
+                &path("diff2.mem"),
+            )
+            .unwrap();
+
+        // Invalid additions.
+        assert!(matches!(
+            catalog.add(
+                "diff1",
+                Some("base"),
+                SnapshotType::Diff,
+                &path("diff1.state"),
+                &path("diff1.mem")
+            ),
+            Err(Error::DuplicateId(_))
+        ));
+        assert!(matches!(
+            catalog.add(
+                "orphan",
+                None,
+                SnapshotType::Diff,
+                &path("diff1.state"),
+                &path("diff1.mem")
+            ),
+            Err(Error::InvalidParent(_))
+        ));
+        assert!(matches!(
+            catalog.add(
+                "orphan",
+                Some("missing"),
+                SnapshotType::Diff,
+                &path("diff1.state"),
+                &path("diff1.mem")
+            ),
+            Err(Error::InvalidParent(_))
+        ));
+        assert!(matches!(catalog.chain("missing"), Err(Error::UnknownId(_))));
+
+        let chain: Vec<&str> = catalog
+            .chain("diff2")
+            .unwrap()
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect();
+        assert_eq!(chain, vec!["base", "diff1", "diff2"]);
+
+        // The index survives a round trip.
+        catalog.save(&path("catalog.json")).unwrap();
+        let mut catalog = SnapshotCatalog::load(&path("catalog.json")).unwrap();
+        assert_eq!(catalog.entries().len(), 3);
+        assert!(SnapshotCatalog::load(&path("missing.json"))
+            .unwrap()
+            .entries()
+            .is_empty());
+
+        // Compaction only yields the expected memory contents if the diff files are sparse.
+        let holes_supported = catalog.get("diff1").unwrap().dirty_pages == 1;
+        let entry = catalog
+            .compact("diff2", "full", &path("full.state"), &path("full.mem"))
+            .unwrap()
+            .clone();
+        assert_eq!(entry.snapshot_type, SnapshotType::Full);
+        assert_eq!(entry.parent, None);
+        assert_eq!(fs::read(path("full.state")).unwrap(), b"diff2.state");
+        assert_eq!(catalog.chain("full").unwrap().len(), 1);
+
+        let mem = fs::read(path("full.mem")).unwrap();
+        assert_eq!(mem.len(), 4 * page_size);
+        if holes_supported {
+            let pages: Vec<u8> = mem.chunks(page_size).map(|page| page[0]).collect();
+            assert_eq!(pages, vec![1, 3, 1, 3]);
+        }
+    }
+
+    #[test]
+    fn test_error_display() {
+        let errors = vec![
+            Error::DuplicateId("id".to_string()),
+            Error::Deserialize(serde_json::from_str::<SnapshotCatalog>("").unwrap_err()),
+            Error::InvalidParent("id".to_string()),
+            Error::Io(io::Error::from_raw_os_error(0)),
+            Error::UnknownId("id".to_string()),
+        ];
+        for err in errors {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
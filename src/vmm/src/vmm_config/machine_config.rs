@@ -4,6 +4,8 @@
 use serde::{de, Deserialize, Serialize};
 use std::fmt;
 
+use super::memory_backend::MemoryBackendConfig;
+
 /// The default memory size of the VM, in MiB.
 pub const DEFAULT_MEM_SIZE_MIB: usize = 128;
 /// Firecracker aims to support small scale workloads only, so limit the maximum
@@ -15,6 +17,8 @@ pub const MAX_SUPPORTED_VCPUS: u8 = 32;
 pub enum VmConfigError {
     /// The memory size is smaller than the target size set in the balloon device configuration.
     IncompatibleBalloonSize,
+    /// The memory backend configuration is invalid.
+    InvalidMemoryBackend(String),
     /// The memory size is invalid. The memory can only be an unsigned integer.
     InvalidMemorySize,
     /// The vcpu count is invalid. When hyperthreading is enabled, the `cpu_count` must be either
@@ -28,12 +32,13 @@ pub enum VmConfigError {
 impl fmt::Display for VmConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::VmConfigError::*;
-        match *self {
+        match self {
             IncompatibleBalloonSize => write!(
                 f,
                 "The memory size (MiB) is smaller than the previously \
                  set balloon device target size.",
             ),
+            InvalidMemoryBackend(err) => write!(f, "The memory backend is invalid: {}", err),
             InvalidMemorySize => write!(f, "The memory size (MiB) is invalid.",),
             InvalidVcpuCount => write!(
                 f,
@@ -73,6 +78,9 @@ pub struct VmConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// The guest memory backend. Anonymous memory is used if not specified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_backend: Option<MemoryBackendConfig>,
 }
 
 impl Default for VmConfig {
@@ -83,6 +91,7 @@ impl Default for VmConfig {
             ht_enabled: Some(false),
             cpu_template: None,
             track_dirty_pages: false,
+            mem_backend: None,
         }
     }
 }
@@ -98,9 +107,17 @@ impl fmt::Display for VmConfig {
         write!(
             f,
             "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"ht_enabled\": {:?}, \
-             \"cpu_template\": {:?}, \"track_dirty_pages\": {:?} }}",
+             \"cpu_template\": {:?}, \"track_dirty_pages\": {:?}",
             vcpu_count, mem_size, ht_enabled, cpu_template, self.track_dirty_pages
-        )
+        )?;
+        if let Some(mem_backend) = &self.mem_backend {
+            write!(
+                f,
+                ", \"mem_backend\": {}",
+                serde_json::to_string(mem_backend).map_err(|_| fmt::Error)?
+            )?;
+        }
+        write!(f, " }}")
     }
 }
 
@@ -157,5 +174,29 @@ mod tests {
 
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
+
+        let expected_str = "The memory backend is invalid: foo";
+        assert_eq!(
+            VmConfigError::InvalidMemoryBackend("foo".to_string()).to_string(),
+            expected_str
+        );
+    }
+
+    #[test]
+    fn test_display_vm_config() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(
+            vm_config.to_string(),
+            "{ \"vcpu_count\": 1, \"mem_size_mib\": 128, \"ht_enabled\": false, \
+             \"cpu_template\": \"Uninitialized\", \"track_dirty_pages\": false }"
+        );
+
+        vm_config.mem_backend = Some(MemoryBackendConfig::default());
+        assert_eq!(
+            vm_config.to_string(),
+            "{ \"vcpu_count\": 1, \"mem_size_mib\": 128, \"ht_enabled\": false, \
+             \"cpu_template\": \"Uninitialized\", \"track_dirty_pages\": false, \
+             \"mem_backend\": {\"backend_type\":\"Anonymous\"} }"
+        );
     }
 }
@@ -0,0 +1,574 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configurations used for selecting the guest memory backend.
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use logger::{warn, IncMetric, StoreMetric, METRICS};
+use serde::{Deserialize, Serialize};
+use vm_memory::mmap::MmapRegionError;
+use vm_memory::{GuestAddress, GuestMemoryMmap, MapOptions};
+
+/// Accounts a failure to create guest memory in the `guest_memory` metrics.
+pub(crate) fn account_guest_memory_error(err: &vm_memory::Error) {
+    match err {
+        vm_memory::Error::MmapRegion(err) => account_mmap_region_error(err),
+        vm_memory::Error::MemoryRegionOverlap => METRICS.guest_memory.region_overlap_fails.inc(),
+        _ => (),
+    }
+}
+
+/// Accounts a failure to map a guest memory region in the `guest_memory` metrics.
+pub(crate) fn account_mmap_region_error(err: &MmapRegionError) {
+    if let MmapRegionError::Mmap(_) = err {
+        METRICS.guest_memory.mmap_fails.inc();
+    }
+}
+
+/// Magic number identifying a hugetlbfs mount, as reported by `statfs`.
+/// Defined in `include/uapi/linux/magic.h`.
+const HUGETLBFS_MAGIC: i64 = 0x9584_58f6;
+
+/// Errors associated with the guest memory backend configuration.
+#[derive(Debug)]
+pub enum MemoryBackendError {
+    /// Failed to create the memory backing file.
+    CreateFile(io::Error),
+    /// The backing file is smaller than the guest memory.
+    FileTooSmall(u64, usize),
+    /// Failed to map guest memory over the backing file.
+    GuestMemoryMmap(vm_memory::Error),
+    /// The guest memory size is not a multiple of the hugetlbfs page size.
+    InvalidHugePageAlignment(usize, usize),
+    /// The host has no such NUMA node.
+    InvalidNumaNode(u32),
+    /// The path is invalid for the requested memory backend type.
+    InvalidPath(MemoryBackendType),
+    /// Failed to inspect the backing path.
+    Metadata(PathBuf, io::Error),
+    /// Failed to lock guest memory in RAM.
+    Mlock(io::Error),
+    /// The given path is not a hugetlbfs mount.
+    NotHugetlbfs(PathBuf),
+    /// The given path is not a regular file.
+    NotRegularFile(PathBuf),
+    /// Failed to bind guest memory to the NUMA node.
+    NumaBind(io::Error),
+    /// Failed to open the memory backing file.
+    OpenFile(io::Error),
+    /// Failed to seal the memfd.
+    SealMemfd(io::Error),
+    /// Failed to set the size of the backing file.
+    SetLen(io::Error),
+}
+
+impl fmt::Display for MemoryBackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MemoryBackendError::*;
+        match self {
+            CreateFile(err) => write!(f, "Cannot create memory backing file: {}", err),
+            FileTooSmall(file_size, mem_size) => write!(
+                f,
+                "The memory backing file size ({} bytes) is smaller than the guest memory \
+                 size ({} bytes).",
+                file_size, mem_size
+            ),
+            GuestMemoryMmap(err) => write!(f, "Cannot map guest memory: {:?}", err),
+            InvalidHugePageAlignment(mem_size, page_size) => write!(
+                f,
+                "The guest memory size ({} bytes) is not a multiple of the huge page size \
+                 ({} bytes).",
+                mem_size, page_size
+            ),
+            InvalidNumaNode(node) => write!(f, "The host has no NUMA node {}.", node),
+            InvalidPath(backend_type) => match backend_type {
+                MemoryBackendType::Anonymous | MemoryBackendType::Memfd => write!(
+                    f,
+                    "The {:?} memory backend does not take a path.",
+                    backend_type
+                ),
+                MemoryBackendType::Hugetlbfs | MemoryBackendType::File => {
+                    write!(f, "The {:?} memory backend requires a path.", backend_type)
+                }
+            },
+            Metadata(path, err) => write!(f, "Cannot access {}: {}", path.display(), err),
+            Mlock(err) => write!(f, "Cannot lock guest memory: {}", err),
+            NotHugetlbfs(path) => write!(f, "{} is not a hugetlbfs mount.", path.display()),
+            NotRegularFile(path) => write!(f, "{} is not a regular file.", path.display()),
+            NumaBind(err) => write!(f, "Cannot bind guest memory to the NUMA node: {}", err),
+            OpenFile(err) => write!(f, "Cannot open memory backing file: {}", err),
+            SealMemfd(err) => write!(f, "Cannot seal memfd: {}", err),
+            SetLen(err) => write!(f, "Cannot set memory backing file size: {}", err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, MemoryBackendError>;
+
+/// The guest memory backend types.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MemoryBackendType {
+    /// Private anonymous memory.
+    Anonymous,
+    /// Shared memory backed by a sealed `memfd_create` file.
+    Memfd,
+    /// Shared memory backed by a file created in the hugetlbfs mount at `path`.
+    Hugetlbfs,
+    /// Private, copy-on-write memory backed by the existing file at `path` (e.g. a snapshot
+    /// memory file).
+    File,
+}
+
+/// Policies for locking guest memory in host RAM.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MemoryLockPolicy {
+    /// Populate all of guest memory and lock it upfront.
+    Populate,
+    /// Lock guest memory pages as they are faulted in.
+    OnFault,
+}
+
+impl Default for MemoryBackendType {
+    fn default() -> Self {
+        MemoryBackendType::Anonymous
+    }
+}
+
+/// Strongly typed structure that represents the guest memory backend configuration.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryBackendConfig {
+    /// The memory backend type.
+    pub backend_type: MemoryBackendType,
+    /// The hugetlbfs mount, or the backing file path, depending on `backend_type`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// The host NUMA node guest memory is allocated from. By default, the host's memory
+    /// policy applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub numa_node: Option<u32>,
+    /// Whether and how guest memory is locked in host RAM, so that it can't be swapped out.
+    /// By default, guest memory is not locked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mlock: Option<MemoryLockPolicy>,
+    /// Whether the kernel pre-faults all of guest memory when mapping it (`MAP_POPULATE`),
+    /// e.g. to restore the contents of a `File` backend eagerly rather than on first access.
+    #[serde(default)]
+    pub populate: bool,
+}
+
+impl MemoryBackendConfig {
+    /// Checks that the configuration can back `mem_size` bytes of guest memory.
+    pub fn validate(&self, mem_size: usize) -> Result<()> {
+        if let Some(node) = self.numa_node {
+            if !Path::new(&format!("/sys/devices/system/node/node{}", node)).exists() {
+                return Err(MemoryBackendError::InvalidNumaNode(node));
+            }
+        }
+
+        match (self.backend_type, self.path.as_ref()) {
+            (MemoryBackendType::Anonymous, None) | (MemoryBackendType::Memfd, None) => Ok(()),
+            (MemoryBackendType::Hugetlbfs, Some(mount_path)) => {
+                let page_size = hugetlbfs_page_size(mount_path)?;
+                if mem_size % page_size != 0 {
+                    return Err(MemoryBackendError::InvalidHugePageAlignment(
+                        mem_size, page_size,
+                    ));
+                }
+                Ok(())
+            }
+            (MemoryBackendType::File, Some(path)) => {
+                let metadata = fs::metadata(path)
+                    .map_err(|e| MemoryBackendError::Metadata(path.clone(), e))?;
+                if !metadata.is_file() {
+                    return Err(MemoryBackendError::NotRegularFile(path.clone()));
+                }
+                if metadata.len() < mem_size as u64 {
+                    return Err(MemoryBackendError::FileTooSmall(metadata.len(), mem_size));
+                }
+                Ok(())
+            }
+            (backend_type, _) => Err(MemoryBackendError::InvalidPath(backend_type)),
+        }
+    }
+
+    /// Creates the guest memory for `ranges`, backed as described by this configuration.
+    pub fn create_guest_memory(
+        &self,
+        ranges: &[(GuestAddress, usize)],
+        track_dirty_pages: bool,
+    ) -> Result<GuestMemoryMmap> {
+        let mem_size: usize = ranges.iter().map(|r| r.1).sum();
+        self.validate(mem_size)?;
+
+        let backing_file = match (self.backend_type, self.path.as_ref()) {
+            (MemoryBackendType::Memfd, _) => Some((create_memfd(mem_size)?, true)),
+            (MemoryBackendType::Hugetlbfs, Some(mount_path)) => {
+                Some((create_hugetlbfs_file(mount_path, mem_size)?, true))
+            }
+            (MemoryBackendType::File, Some(path)) => Some((
+                File::open(path).map_err(MemoryBackendError::OpenFile)?,
+                false,
+            )),
+            // Validation guarantees the only remaining case is anonymous memory.
+            _ => None,
+        };
+
+        let options = MapOptions {
+            populate: self.populate,
+            ..Default::default()
+        };
+        let ranges = ranges
+            .iter()
+            .map(|&(addr, size)| (addr, size, options))
+            .collect::<Vec<_>>();
+        let guest_memory = match backing_file {
+            Some((file, shared)) => GuestMemoryMmap::from_ranges_with_backing_file(
+                &ranges,
+                Arc::new(file),
+                shared,
+                track_dirty_pages,
+            ),
+            None => GuestMemoryMmap::from_ranges_with_options(&ranges, track_dirty_pages),
+        }
+        .map_err(|err| {
+            account_guest_memory_error(&err);
+            MemoryBackendError::GuestMemoryMmap(err)
+        })?;
+
+        // Bind before the guest touches its memory, so that no page gets allocated elsewhere.
+        // Pages populated while mapping guest memory are moved to the node.
+        if let Some(node) = self.numa_node {
+            guest_memory
+                .bind_numa_node(node)
+                .map_err(MemoryBackendError::NumaBind)?;
+        }
+        if let Some(policy) = self.mlock {
+            guest_memory
+                .lock(policy == MemoryLockPolicy::OnFault)
+                .map_err(MemoryBackendError::Mlock)?;
+            METRICS.guest_memory.locked_bytes.store(mem_size);
+        }
+        // The kernel doesn't report population failures, e.g. under memory pressure, so check
+        // what was left to fault in on first access.
+        if self.populate {
+            match guest_memory.unpopulated_regions() {
+                Ok(regions) => {
+                    for (addr, residency) in regions {
+                        warn!(
+                            "Only {} of the {} pages of the guest memory region at {:#x} were \
+                             populated.",
+                            residency.resident, residency.total, addr.0
+                        );
+                    }
+                }
+                Err(err) => warn!("Cannot check guest memory population: {}", err),
+            }
+        }
+        Ok(guest_memory)
+    }
+}
+
+/// Returns the huge page size of the hugetlbfs mount at `mount_path`.
+fn hugetlbfs_page_size(mount_path: &Path) -> Result<usize> {
+    let c_path = CString::new(mount_path.as_os_str().as_bytes())
+        .map_err(|_| MemoryBackendError::NotHugetlbfs(mount_path.to_path_buf()))?;
+    // Safe because we zero-initialize a plain C struct.
+    let mut stat: libc::statfs = unsafe { mem::zeroed() };
+    // Safe because `c_path` is a valid C string and `stat` is a valid `statfs` struct, and we
+    // check the return value.
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } < 0 {
+        return Err(MemoryBackendError::Metadata(
+            mount_path.to_path_buf(),
+            io::Error::last_os_error(),
+        ));
+    }
+    if stat.f_type as i64 != HUGETLBFS_MAGIC {
+        return Err(MemoryBackendError::NotHugetlbfs(mount_path.to_path_buf()));
+    }
+    Ok(stat.f_bsize as usize)
+}
+
+/// Creates a `mem_size` bytes memfd, sealed against any further resizing.
+fn create_memfd(mem_size: usize) -> Result<File> {
+    let name = CString::new("guest_mem").unwrap();
+    // Safe because `name` is a valid C string, and we check the return value.
+    let fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        return Err(MemoryBackendError::CreateFile(io::Error::last_os_error()));
+    }
+    // Safe because we have just created `fd`, and nothing else owns it.
+    let file = unsafe { File::from_raw_fd(fd) };
+    file.set_len(mem_size as u64)
+        .map_err(MemoryBackendError::SetLen)?;
+
+    // Safe because `file` is a valid memfd, and we check the return value.
+    let ret = unsafe {
+        libc::fcntl(
+            fd,
+            libc::F_ADD_SEALS,
+            libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL,
+        )
+    };
+    if ret < 0 {
+        return Err(MemoryBackendError::SealMemfd(io::Error::last_os_error()));
+    }
+    Ok(file)
+}
+
+/// Creates a `mem_size` bytes unlinked file in the hugetlbfs mount at `mount_path`.
+fn create_hugetlbfs_file(mount_path: &Path, mem_size: usize) -> Result<File> {
+    let path = mount_path.join(format!("firecracker_guest_mem_{}", std::process::id()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(MemoryBackendError::CreateFile)?;
+    // The file only needs to live as long as the mapping does.
+    fs::remove_file(&path).map_err(MemoryBackendError::CreateFile)?;
+    file.set_len(mem_size as u64)
+        .map_err(MemoryBackendError::SetLen)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_validate() {
+        let mem_size = 0x4000;
+
+        let cfg = MemoryBackendConfig::default();
+        assert_eq!(cfg.backend_type, MemoryBackendType::Anonymous);
+        cfg.validate(mem_size).unwrap();
+
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::Memfd,
+            path: None,
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        cfg.validate(mem_size).unwrap();
+
+        // Paths are only valid for the Hugetlbfs and File backends.
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::Memfd,
+            path: Some(PathBuf::from("/tmp")),
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        match cfg.validate(mem_size) {
+            Err(MemoryBackendError::InvalidPath(MemoryBackendType::Memfd)) => (),
+            other => panic!("{:?}", other),
+        }
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::Hugetlbfs,
+            path: None,
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        match cfg.validate(mem_size) {
+            Err(MemoryBackendError::InvalidPath(MemoryBackendType::Hugetlbfs)) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // /tmp is not a hugetlbfs mount.
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::Hugetlbfs,
+            path: Some(PathBuf::from("/tmp")),
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        match cfg.validate(mem_size) {
+            Err(MemoryBackendError::NotHugetlbfs(_)) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // The backing file needs to be large enough.
+        let tmp_file = TempFile::new().unwrap();
+        tmp_file.as_file().set_len(mem_size as u64 / 2).unwrap();
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::File,
+            path: Some(tmp_file.as_path().to_path_buf()),
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        match cfg.validate(mem_size) {
+            Err(MemoryBackendError::FileTooSmall(_, _)) => (),
+            other => panic!("{:?}", other),
+        }
+        tmp_file.as_file().set_len(mem_size as u64).unwrap();
+        cfg.validate(mem_size).unwrap();
+
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::File,
+            path: Some(PathBuf::from("/tmp")),
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        match cfg.validate(mem_size) {
+            Err(MemoryBackendError::NotRegularFile(_)) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_guest_memory() {
+        let ranges = [(GuestAddress(0), 0x2000), (GuestAddress(0x10_0000), 0x2000)];
+
+        let cfg = MemoryBackendConfig::default();
+        let mem = cfg.create_guest_memory(&ranges, true).unwrap();
+        assert!(mem.is_dirty_tracking_enabled());
+
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::Memfd,
+            path: None,
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        let mem = cfg.create_guest_memory(&ranges, false).unwrap();
+        assert!(!mem.is_dirty_tracking_enabled());
+
+        let tmp_file = TempFile::new().unwrap();
+        tmp_file.as_file().set_len(0x4000).unwrap();
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::File,
+            path: Some(tmp_file.as_path().to_path_buf()),
+            numa_node: None,
+            mlock: None,
+            populate: false,
+        };
+        cfg.create_guest_memory(&ranges, false).unwrap();
+
+        // The host needs to have the requested NUMA node.
+        let cfg = MemoryBackendConfig {
+            numa_node: Some(4095),
+            ..Default::default()
+        };
+        match cfg.create_guest_memory(&ranges, false) {
+            Err(MemoryBackendError::InvalidNumaNode(4095)) => (),
+            other => panic!("{:?}", other),
+        }
+        if Path::new("/sys/devices/system/node/node0").exists() {
+            let cfg = MemoryBackendConfig {
+                backend_type: MemoryBackendType::Memfd,
+                path: None,
+                numa_node: Some(0),
+                mlock: None,
+                populate: false,
+            };
+            cfg.create_guest_memory(&ranges, false).unwrap();
+        }
+
+        let cfg = MemoryBackendConfig {
+            mlock: Some(MemoryLockPolicy::OnFault),
+            ..Default::default()
+        };
+        cfg.create_guest_memory(&ranges, false).unwrap();
+        assert_eq!(METRICS.guest_memory.locked_bytes.fetch(), 0x4000);
+
+        // Populated memory is resident right away, whatever its backend.
+        let cfg = MemoryBackendConfig {
+            populate: true,
+            ..Default::default()
+        };
+        let mem = cfg.create_guest_memory(&ranges, true).unwrap();
+        assert!(mem.is_dirty_tracking_enabled());
+        assert!(mem.unpopulated_regions().unwrap().is_empty());
+        let cfg = MemoryBackendConfig {
+            backend_type: MemoryBackendType::File,
+            path: Some(tmp_file.as_path().to_path_buf()),
+            numa_node: None,
+            mlock: None,
+            populate: true,
+        };
+        let mem = cfg.create_guest_memory(&ranges, false).unwrap();
+        assert!(mem.unpopulated_regions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_guest_memory_error_metrics() {
+        let mmap_fails = METRICS.guest_memory.mmap_fails.count();
+        let overlap_fails = METRICS.guest_memory.region_overlap_fails.count();
+
+        // Mapping this much memory is bound to fail.
+        let ranges = [(GuestAddress(0), usize::MAX & !0xfff)];
+        assert!(MemoryBackendConfig::default()
+            .create_guest_memory(&ranges, false)
+            .is_err());
+        assert!(METRICS.guest_memory.mmap_fails.count() > mmap_fails);
+
+        account_guest_memory_error(&vm_memory::Error::MemoryRegionOverlap);
+        assert!(METRICS.guest_memory.region_overlap_fails.count() > overlap_fails);
+    }
+
+    #[test]
+    fn test_error_display() {
+        use self::MemoryBackendError::*;
+
+        let err = CreateFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = FileTooSmall(0, 0);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = GuestMemoryMmap(vm_memory::Error::NoMemoryRegion);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidHugePageAlignment(0, 0);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidPath(MemoryBackendType::Anonymous);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidPath(MemoryBackendType::File);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = Metadata(PathBuf::from("/tmp"), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = NotHugetlbfs(PathBuf::from("/tmp"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = NotRegularFile(PathBuf::from("/tmp"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidNumaNode(0);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = Mlock(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = NumaBind(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = OpenFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SealMemfd(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SetLen(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -24,6 +24,8 @@ pub mod instance_info;
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
+/// Wrapper for configuring the guest memory backend.
+pub mod memory_backend;
 /// Wrapper for configuring the metrics.
 pub mod metrics;
 /// Wrapper for configuring the MMDS.
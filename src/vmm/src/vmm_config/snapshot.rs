@@ -9,7 +9,7 @@ use serde::{Deserialize, Serialize};
 
 /// The snapshot type options that are available when
 /// creating a new snapshot.
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum SnapshotType {
     /// Diff snapshot.
     Diff,
@@ -38,6 +38,21 @@ pub struct CreateSnapshotParams {
     /// Optional field for the microVM version. The default
     /// value is the current version.
     pub version: Option<String>,
+    /// Setting this flag will skip writing all-zero pages of guest memory to
+    /// the memory file of full snapshots, leaving holes in the file instead.
+    #[serde(default)]
+    pub sparse_mem_file: bool,
+    /// Setting this flag writes each snapshot file to a temporary file that is synced to disk
+    /// and then atomically renamed into place, so a host crash can't leave a truncated
+    /// snapshot file behind.
+    #[serde(default)]
+    pub atomic_write: bool,
+    /// Setting this flag writes the memory file with `O_DIRECT`, bypassing the host page cache.
+    #[serde(default)]
+    pub direct_io_mem_file: bool,
+    /// Optional limit, in bytes per second, of the rate at which the snapshot files are
+    /// written. The default is to write them as fast as possible.
+    pub bandwidth_limit: Option<u64>,
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
@@ -70,3 +85,102 @@ pub struct Vm {
     /// The microVM state, which can be `paused` or `resumed`.
     pub state: VmState,
 }
+
+/// The snapshot operations whose outcome is reported through `SnapshotStatus`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum SnapshotOperation {
+    /// A snapshot was created from a running microVM.
+    Create,
+    /// A microVM was loaded from a snapshot.
+    Load,
+}
+
+/// The outcome of the most recent snapshot operation.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum SnapshotOperationState {
+    /// No snapshot operation was performed yet.
+    NotStarted,
+    /// The operation completed successfully.
+    Succeeded,
+    /// The operation failed; see `SnapshotStatus::error` for details.
+    Failed,
+}
+
+impl Default for SnapshotOperationState {
+    fn default() -> SnapshotOperationState {
+        SnapshotOperationState::NotStarted
+    }
+}
+
+/// Describes the status of the most recent snapshot create or load operation.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SnapshotStatus {
+    /// The type of the most recent operation, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation: Option<SnapshotOperation>,
+    /// The outcome of the most recent operation.
+    pub state: SnapshotOperationState,
+    /// Number of bytes written to the snapshot files. Always 0 for `Load`.
+    pub bytes_written: u64,
+    /// Duration of the operation in microseconds.
+    pub duration_us: u64,
+    /// Description of the error that made the operation fail.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SnapshotStatus {
+    /// Builds the status of an operation from its outcome.
+    pub fn new<E: std::fmt::Display>(
+        operation: SnapshotOperation,
+        outcome: &std::result::Result<u64, E>,
+        duration_us: u64,
+    ) -> SnapshotStatus {
+        match outcome {
+            Ok(bytes_written) => SnapshotStatus {
+                operation: Some(operation),
+                state: SnapshotOperationState::Succeeded,
+                bytes_written: *bytes_written,
+                duration_us,
+                error: None,
+            },
+            Err(err) => SnapshotStatus {
+                operation: Some(operation),
+                state: SnapshotOperationState::Failed,
+                bytes_written: 0,
+                duration_us,
+                error: Some(err.to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_status() {
+        let status = SnapshotStatus::default();
+        assert_eq!(status.operation, None);
+        assert_eq!(status.state, SnapshotOperationState::NotStarted);
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"state":"NotStarted","bytes_written":0,"duration_us":0}"#
+        );
+
+        let status = SnapshotStatus::new::<String>(SnapshotOperation::Create, &Ok(4096), 10);
+        assert_eq!(status.state, SnapshotOperationState::Succeeded);
+        assert_eq!(status.bytes_written, 4096);
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"operation":"Create","state":"Succeeded","bytes_written":4096,"duration_us":10}"#
+        );
+
+        let status =
+            SnapshotStatus::new(SnapshotOperation::Load, &Err("bad snapshot".to_string()), 5);
+        assert_eq!(status.state, SnapshotOperationState::Failed);
+        assert_eq!(status.bytes_written, 0);
+        assert_eq!(status.error, Some("bad snapshot".to_string()));
+    }
+}
@@ -4,7 +4,9 @@
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
-use devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use devices::virtio::{
+    validate_queue_size, Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -44,6 +46,10 @@ pub struct VsockDeviceConfig {
     pub guest_cid: u32,
     /// Path to local unix socket.
     pub uds_path: String,
+    /// Size of the virtio queues, i.e. the max number of buffers the guest driver can make
+    /// available to the device on each queue. Must be a power of 2, up to 32768.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_size: Option<u16>,
 }
 
 struct VsockAndUnixPath {
@@ -86,11 +92,21 @@ impl VsockBuilder {
 
     /// Creates a Vsock device from a VsockDeviceConfig.
     pub fn create_unixsock_vsock(cfg: VsockDeviceConfig) -> Result<Vsock<VsockUnixBackend>> {
+        // Validate before binding the Unix socket, so that an invalid configuration doesn't
+        // leave a socket file behind.
+        if let Some(queue_size) = cfg.queue_size {
+            validate_queue_size(queue_size).map_err(VsockConfigError::CreateVsockDevice)?;
+        }
         let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)
             .map_err(VsockConfigError::CreateVsockBackend)?;
 
-        Ok(Vsock::new(u64::from(cfg.guest_cid), backend)
-            .map_err(VsockConfigError::CreateVsockDevice)?)
+        match cfg.queue_size {
+            Some(queue_size) => {
+                Vsock::with_queue_size(u64::from(cfg.guest_cid), backend, queue_size)
+            }
+            None => Vsock::new(u64::from(cfg.guest_cid), backend),
+        }
+        .map_err(VsockConfigError::CreateVsockDevice)
     }
 }
 
@@ -104,6 +120,7 @@ pub(crate) mod tests {
             vsock_id: "vsock".to_string(),
             guest_cid: 3,
             uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+            queue_size: None,
         }
     }
 
@@ -115,6 +132,25 @@ pub(crate) mod tests {
         VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
     }
 
+    #[test]
+    fn test_vsock_queue_size() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+
+        vsock_config.queue_size = Some(100);
+        match VsockBuilder::create_unixsock_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::CreateVsockDevice(VsockError::InvalidQueueSize(100))) => (),
+            _ => panic!("Expected an invalid queue size error"),
+        }
+        // The Unix socket wasn't bound, so the configuration can be fixed and retried.
+        assert!(!tmp_sock_file.as_path().exists());
+
+        vsock_config.queue_size = Some(1024);
+        let vsock = VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
+        assert_eq!(vsock.queue_size(), 1024);
+    }
+
     #[test]
     fn test_vsock_insert() {
         let mut store = VsockBuilder::new();
@@ -0,0 +1,223 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bookkeeping of the KVM memory slots that map guest memory.
+
+use std::fmt::{Display, Formatter};
+use std::result;
+
+use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_LOG_DIRTY_PAGES, KVM_MEM_READONLY};
+use vm_memory::{Address, GuestAddress, GuestMemoryRegion, GuestRegionMmap};
+
+/// Errors associated with memory slot bookkeeping.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// All the memory slots reported by KVM are in use.
+    NoFreeSlot,
+    /// The guest range starting at the given address overlaps an existing slot.
+    Overlap(u64),
+    /// No slot starts at the given guest address.
+    UnknownSlot(u64),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+
+        match self {
+            NoFreeSlot => write!(f, "All the KVM memory slots are in use"),
+            Overlap(addr) => write!(
+                f,
+                "The guest memory region at {:#x} overlaps an existing memory slot",
+                addr
+            ),
+            UnknownSlot(addr) => write!(f, "No memory slot starts at {:#x}", addr),
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Tracks which KVM memory slot maps which guest memory region.
+///
+/// `MemSlots` only updates its own bookkeeping: each method returns the
+/// `kvm_userspace_memory_region` entries that need to be passed to KVM for the change to take
+/// effect. A `memory_size` of 0 deletes a slot.
+///
+/// KVM can't resize a slot in place, and `GuestMemoryMmap::resize_region()` maps the added
+/// range as a separate region anyway, so growing memory translates to `add()`-ing a slot for
+/// that region.
+#[derive(Debug)]
+pub struct MemSlots {
+    /// The slots, indexed by slot number. `None` marks a free slot.
+    slots: Vec<Option<kvm_userspace_memory_region>>,
+    /// Whether KVM should log the pages dirtied through the slots.
+    track_dirty_pages: bool,
+}
+
+impl MemSlots {
+    /// Creates the bookkeeping for `max_slots` free slots.
+    pub fn new(max_slots: usize) -> Self {
+        MemSlots {
+            slots: vec![None; max_slots],
+            track_dirty_pages: false,
+        }
+    }
+
+    /// Assigns the lowest free slot to `region`.
+    pub fn add(&mut self, region: &GuestRegionMmap) -> Result<kvm_userspace_memory_region> {
+        let start = region.start_addr().raw_value();
+        let end = start + region.len();
+        if self.slots.iter().flatten().any(|slot| {
+            start < slot.guest_phys_addr + slot.memory_size && slot.guest_phys_addr < end
+        }) {
+            return Err(Error::Overlap(start));
+        }
+
+        let index = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::NoFreeSlot)?;
+        let entry = self.entry(index as u32, region);
+        self.slots[index] = Some(entry);
+        Ok(entry)
+    }
+
+    /// Frees the slot mapping the region that starts at `guest_addr`.
+    pub fn remove(&mut self, guest_addr: GuestAddress) -> Result<kvm_userspace_memory_region> {
+        let index = self.index(guest_addr)?;
+        // Safe to unwrap since `index()` only returns used slots.
+        let mut entry = self.slots[index].take().unwrap();
+        entry.memory_size = 0;
+        Ok(entry)
+    }
+
+    /// Turns dirty page logging on or off, for the current slots as well as future ones.
+    pub fn set_dirty_page_tracking(&mut self, enable: bool) -> Vec<kvm_userspace_memory_region> {
+        self.track_dirty_pages = enable;
+        self.slots
+            .iter_mut()
+            .flatten()
+            .map(|slot| {
+                if enable {
+                    slot.flags |= KVM_MEM_LOG_DIRTY_PAGES;
+                } else {
+                    slot.flags &= !KVM_MEM_LOG_DIRTY_PAGES;
+                }
+                *slot
+            })
+            .collect()
+    }
+
+    /// Returns the number of the slot mapping the region that starts at `guest_addr`.
+    pub fn slot(&self, guest_addr: GuestAddress) -> Result<u32> {
+        self.index(guest_addr).map(|index| index as u32)
+    }
+
+    fn index(&self, guest_addr: GuestAddress) -> Result<usize> {
+        self.slots
+            .iter()
+            .position(|slot| {
+                slot.map_or(false, |slot| slot.guest_phys_addr == guest_addr.raw_value())
+            })
+            .ok_or_else(|| Error::UnknownSlot(guest_addr.raw_value()))
+    }
+
+    fn entry(&self, slot: u32, region: &GuestRegionMmap) -> kvm_userspace_memory_region {
+        let mut flags = 0u32;
+        if self.track_dirty_pages {
+            flags |= KVM_MEM_LOG_DIRTY_PAGES;
+        }
+        // Let the guest see regions the VMM can't write to (e.g. ROM) as read-only.
+        if region.protection() & libc::PROT_WRITE == 0 {
+            flags |= KVM_MEM_READONLY;
+        }
+        kvm_userspace_memory_region {
+            slot,
+            guest_phys_addr: region.start_addr().raw_value(),
+            memory_size: region.len(),
+            userspace_addr: region.as_ptr() as u64,
+            flags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::MmapRegion;
+
+    fn region(addr: u64, len: usize) -> GuestRegionMmap {
+        GuestRegionMmap::new(MmapRegion::new(len).unwrap(), GuestAddress(addr)).unwrap()
+    }
+
+    #[test]
+    fn test_memslots() {
+        let mut slots = MemSlots::new(2);
+        let low = region(0, 0x1000);
+        let high = region(0x2000, 0x1000);
+
+        let entry = slots.add(&low).unwrap();
+        assert_eq!(entry.slot, 0);
+        assert_eq!(entry.guest_phys_addr, 0);
+        assert_eq!(entry.memory_size, 0x1000);
+        assert_eq!(entry.userspace_addr, low.as_ptr() as u64);
+        assert_eq!(entry.flags, 0);
+
+        // Overlapping regions are rejected.
+        assert_eq!(
+            slots.add(&region(0x800, 0x1000)).unwrap_err(),
+            Error::Overlap(0x800)
+        );
+
+        // New slots follow the dirty page tracking setting.
+        let updates = slots.set_dirty_page_tracking(true);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].flags, KVM_MEM_LOG_DIRTY_PAGES);
+        let entry = slots.add(&high).unwrap();
+        assert_eq!(entry.slot, 1);
+        assert_eq!(entry.flags, KVM_MEM_LOG_DIRTY_PAGES);
+        assert_eq!(slots.slot(GuestAddress(0x2000)).unwrap(), 1);
+        assert_eq!(
+            slots.add(&region(0x4000, 0x1000)).unwrap_err(),
+            Error::NoFreeSlot
+        );
+
+        // Removing a region frees its slot, which gets reused.
+        let entry = slots.remove(GuestAddress(0)).unwrap();
+        assert_eq!(entry.slot, 0);
+        assert_eq!(entry.memory_size, 0);
+        assert_eq!(
+            slots.remove(GuestAddress(0)).unwrap_err(),
+            Error::UnknownSlot(0)
+        );
+        assert_eq!(slots.slot(GuestAddress(0x2000)).unwrap(), 1);
+        assert_eq!(slots.add(&region(0x4000, 0x1000)).unwrap().slot, 0);
+
+        let updates = slots.set_dirty_page_tracking(false);
+        assert_eq!(updates.len(), 2);
+        assert!(updates.iter().all(|entry| entry.flags == 0));
+    }
+
+    #[test]
+    fn test_readonly_slot() {
+        let mut slots = MemSlots::new(1);
+        let rom = region(0, 0x1000);
+        rom.set_protection(libc::PROT_READ).unwrap();
+        assert_eq!(slots.add(&rom).unwrap().flags, KVM_MEM_READONLY);
+    }
+
+    #[test]
+    fn test_error_display() {
+        for err in [
+            Error::NoFreeSlot,
+            Error::Overlap(0x1000),
+            Error::UnknownSlot(0),
+        ]
+        .iter()
+        {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
@@ -37,6 +37,15 @@ pub enum Error {
     LocalIntConfiguration(arch::x86_64::interrupts::Error),
     /// Error configuring the MSR registers
     MSRSConfiguration(arch::x86_64::msr::Error),
+    /// The saved vcpu CPUID advertises features that the host doesn't support.
+    MissingCpuFeatures {
+        /// The CPUID leaf.
+        leaf: u32,
+        /// The CPUID register of the leaf.
+        register: &'static str,
+        /// The unsupported feature bits.
+        bits: u32,
+    },
     /// Error configuring the general purpose registers
     REGSConfiguration(arch::x86_64::regs::Error),
     /// Error configuring the special registers
@@ -100,6 +109,15 @@ impl Display for Error {
             ),
             VcpuFd(e) => write!(f, "Cannot open the VCPU file descriptor: {}", e),
             MSRSConfiguration(e) => write!(f, "Error configuring the MSR registers: {:?}", e),
+            MissingCpuFeatures {
+                leaf,
+                register,
+                bits,
+            } => write!(
+                f,
+                "The host CPU does not support the features {:#x} of CPUID leaf {:#x} {}",
+                bits, leaf, register
+            ),
             REGSConfiguration(e) => write!(
                 f,
                 "Error configuring the general purpose registers: {:?}",
@@ -138,6 +156,35 @@ impl Display for Error {
 
 type Result<T> = result::Result<T, Error>;
 
+// The CPUID registers which enumerate CPU features, as (leaf, register, ignored bits).
+// Ignored bits are either dynamic, reflecting guest control register state, or set by
+// Firecracker regardless of what KVM reports as supported.
+const CPUID_FEATURE_REGISTERS: [(u32, &str, u32); 7] = [
+    // OSXSAVE (27), TSC_DEADLINE_TIMER (24) and HYPERVISOR (31).
+    (0x1, "ecx", (1 << 27) | (1 << 24) | (1 << 31)),
+    // HTT (28).
+    (0x1, "edx", 1 << 28),
+    (0x7, "ebx", 0),
+    // OSPKE (4).
+    (0x7, "ecx", 1 << 4),
+    (0x7, "edx", 0),
+    (0x8000_0001, "ecx", 0),
+    (0x8000_0001, "edx", 0),
+];
+
+// Returns the value of `register` from the first subleaf of `leaf`, or 0 if the leaf is absent.
+fn cpuid_register(cpuid: &CpuId, leaf: u32, register: &str) -> u32 {
+    cpuid
+        .as_slice()
+        .iter()
+        .find(|entry| entry.function == leaf && entry.index == 0)
+        .map_or(0, |entry| match register {
+            "ebx" => entry.ebx,
+            "ecx" => entry.ecx,
+            _ => entry.edx,
+        })
+}
+
 /// A wrapper around creating and using a kvm x86_64 vcpu.
 pub struct KvmVcpu {
     pub index: u8,
@@ -390,6 +437,27 @@ pub struct VcpuState {
     xsave: kvm_xsave,
 }
 
+impl VcpuState {
+    /// Checks that the host supports every CPU feature advertised to the guest through the
+    /// saved CPUID, given the host's `supported_cpuid`. Restoring a guest on a host lacking
+    /// some of those features would make it crash as soon as it uses one of them.
+    pub fn check_cpuid_support(&self, supported_cpuid: &CpuId) -> Result<()> {
+        for &(leaf, register, ignored_bits) in CPUID_FEATURE_REGISTERS.iter() {
+            let bits = cpuid_register(&self.cpuid, leaf, register)
+                & !cpuid_register(supported_cpuid, leaf, register)
+                & !ignored_bits;
+            if bits != 0 {
+                return Err(Error::MissingCpuFeatures {
+                    leaf,
+                    register,
+                    bits,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate cpuid;
@@ -473,6 +541,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_check_cpuid_support() {
+        let (vm, _) = setup_vm(0x1000);
+        let mut state = VcpuState {
+            cpuid: vm.supported_cpuid().clone(),
+            ..Default::default()
+        };
+        assert!(state.check_cpuid_support(vm.supported_cpuid()).is_ok());
+
+        // Ignored bits don't make the check fail.
+        let leaf_0x1_index = state
+            .cpuid
+            .as_slice()
+            .iter()
+            .position(|entry| entry.function == 0x1)
+            .unwrap();
+        state.cpuid.as_mut_slice()[leaf_0x1_index].ecx |= 1 << 31;
+        assert!(state.check_cpuid_support(vm.supported_cpuid()).is_ok());
+
+        // Bit 10 of leaf 0x1 EDX is reserved, so no host supports it.
+        state.cpuid.as_mut_slice()[leaf_0x1_index].edx |= 1 << 10;
+        match state.check_cpuid_support(vm.supported_cpuid()) {
+            Err(Error::MissingCpuFeatures {
+                leaf: 0x1,
+                register: "edx",
+                bits,
+            }) => assert_eq!(bits, 1 << 10),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_vcpu_cpuid_restore() {
         let (_vm, vcpu, _) = setup_vcpu(0x1000);
@@ -7,24 +7,26 @@
 
 use std::{
     fmt::{Display, Formatter},
-    result,
+    io, result,
 };
 
 #[cfg(target_arch = "aarch64")]
 use arch::aarch64::gic::GICDevice;
+use kvm_bindings::kvm_userspace_memory_region;
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
     kvm_clock_data, kvm_irqchip, kvm_pit_config, kvm_pit_state2, CpuId, MsrList,
     KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
     KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
 };
-use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_LOG_DIRTY_PAGES};
 use kvm_ioctls::{Kvm, VmFd};
 #[cfg(target_arch = "x86_64")]
 use versionize::{VersionMap, Versionize, VersionizeResult};
 #[cfg(target_arch = "x86_64")]
 use versionize_derive::Versionize;
-use vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestRegionMmap};
+
+use super::memslots::{self, MemSlots};
 
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
@@ -32,8 +34,12 @@ pub enum Error {
     #[cfg(target_arch = "x86_64")]
     /// Retrieving supported guest MSRs fails.
     GuestMSRs(arch::x86_64::msr::Error),
+    /// Cannot update the memory slot bookkeeping.
+    MemSlots(memslots::Error),
     /// The number of configured slots is bigger than the maximum reported by KVM.
     NotEnoughMemorySlots,
+    /// Cannot change the protection of a memory region.
+    SetMemoryProtection(io::Error),
     /// Cannot set the memory regions.
     SetUserMemoryRegion(kvm_ioctls::Error),
     #[cfg(target_arch = "aarch64")]
@@ -72,12 +78,16 @@ impl Display for Error {
             GuestMSRs(e) => write!(f, "Retrieving supported guest MSRs fails: {:?}", e),
             #[cfg(target_arch = "aarch64")]
             VmCreateGIC(e) => write!(f, "Error creating the global interrupt controller: {:?}", e),
+            MemSlots(e) => write!(f, "Cannot update the memory slots: {}", e),
             VmFd(e) => write!(f, "Cannot open the VM file descriptor: {}", e),
             VmSetup(e) => write!(f, "Cannot configure the microvm: {}", e),
             NotEnoughMemorySlots => write!(
                 f,
                 "The number of configured slots is bigger than the maximum reported by KVM"
             ),
+            SetMemoryProtection(e) => {
+                write!(f, "Cannot change the protection of a memory region: {}", e)
+            }
             SetUserMemoryRegion(e) => write!(f, "Cannot set the memory regions: {}", e),
             #[cfg(target_arch = "x86_64")]
             VmGetPit2(e) => write!(f, "Failed to get KVM vm pit state: {}", e),
@@ -100,6 +110,7 @@ pub type Result<T> = result::Result<T, Error>;
 /// A wrapper around creating and using a VM.
 pub struct Vm {
     fd: VmFd,
+    memslots: MemSlots,
 
     // X86 specific fields.
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
@@ -129,6 +140,7 @@ impl Vm {
 
         Ok(Vm {
             fd: vm_fd,
+            memslots: MemSlots::new(kvm.get_nr_memslots()),
             #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
             supported_cpuid,
             #[cfg(target_arch = "x86_64")]
@@ -259,32 +271,71 @@ impl Vm {
         Ok(())
     }
 
+    /// Gets the memory slots bookkeeping of this VM.
+    pub fn memslots(&self) -> &MemSlots {
+        &self.memslots
+    }
+
+    /// Maps all the regions of `guest_mem` into the guest, each in its own memory slot.
     pub(crate) fn set_kvm_memory_regions(
-        &self,
+        &mut self,
         guest_mem: &GuestMemoryMmap,
         track_dirty_pages: bool,
     ) -> Result<()> {
-        let mut flags = 0u32;
-        if track_dirty_pages {
-            flags |= KVM_MEM_LOG_DIRTY_PAGES;
+        self.set_dirty_page_tracking(track_dirty_pages)?;
+        guest_mem.with_regions_mut(|_, region| self.add_memory_region(region))
+    }
+
+    /// Maps `region` into the guest, using the lowest free memory slot.
+    pub fn add_memory_region(&mut self, region: &GuestRegionMmap) -> Result<()> {
+        let memory_region = self.memslots.add(region).map_err(Error::MemSlots)?;
+        self.set_user_memory_region(memory_region).map_err(|err| {
+            // Don't keep track of a slot that KVM refused.
+            let _ = self.memslots.remove(region.start_addr());
+            err
+        })
+    }
+
+    /// Unmaps the region starting at `guest_addr` from the guest, and frees its memory slot.
+    pub fn remove_memory_region(&mut self, guest_addr: GuestAddress) -> Result<()> {
+        let memory_region = self.memslots.remove(guest_addr).map_err(Error::MemSlots)?;
+        self.set_user_memory_region(memory_region)
+    }
+
+    /// Changes the protection of the mapped `region` to `prot` (`PROT_READ`, optionally with
+    /// `PROT_WRITE`), and remaps it into the guest so that its memory slot is read-only exactly
+    /// when `prot` lacks `PROT_WRITE`.
+    ///
+    /// KVM can't change the flags of an existing slot, so the slot is deleted and recreated.
+    /// The pages KVM logged as dirty in the deleted slot are lost, so the whole region is
+    /// marked dirty in its own bitmap instead.
+    ///
+    /// The default seccomp filter allows neither `mprotect` nor `KVM_SET_USER_MEMORY_REGION`,
+    /// so this can't be called from the VMM thread.
+    pub fn set_region_protection(&mut self, region: &GuestRegionMmap, prot: i32) -> Result<()> {
+        self.remove_memory_region(region.start_addr())?;
+        if let Err(err) = region.set_protection(prot) {
+            // Keep the region mapped into the guest, with its previous protection.
+            self.add_memory_region(region)?;
+            return Err(Error::SetMemoryProtection(err));
+        }
+        region.mark_dirty_pages(0, region.len() as usize);
+        self.add_memory_region(region)
+    }
+
+    /// Enables or disables KVM dirty page tracking, for all current and future memory slots.
+    pub fn set_dirty_page_tracking(&mut self, enable: bool) -> Result<()> {
+        for memory_region in self.memslots.set_dirty_page_tracking(enable) {
+            self.set_user_memory_region(memory_region)?;
         }
-        guest_mem
-            .with_regions(|index, region| {
-                let memory_region = kvm_userspace_memory_region {
-                    slot: index as u32,
-                    guest_phys_addr: region.start_addr().raw_value() as u64,
-                    memory_size: region.len() as u64,
-                    // It's safe to unwrap because the guest address is valid.
-                    userspace_addr: guest_mem.get_host_address(region.start_addr()).unwrap() as u64,
-                    flags,
-                };
-
-                // Safe because the fd is a valid KVM file descriptor.
-                unsafe { self.fd.set_user_memory_region(memory_region) }
-            })
-            .map_err(Error::SetUserMemoryRegion)?;
         Ok(())
     }
+
+    fn set_user_memory_region(&self, memory_region: kvm_userspace_memory_region) -> Result<()> {
+        // Safe because the fd is a valid KVM file descriptor, and the memory slots bookkeeping
+        // only hands out regions backed by valid host mappings.
+        unsafe { self.fd.set_user_memory_region(memory_region) }.map_err(Error::SetUserMemoryRegion)
+    }
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -384,19 +435,83 @@ pub(crate) mod tests {
     #[test]
     fn test_set_kvm_memory_regions() {
         let kvm_context = KvmContext::new().unwrap();
-        let vm = Vm::new(kvm_context.fd()).expect("Cannot create new vm");
+        let mut vm = Vm::new(kvm_context.fd()).expect("Cannot create new vm");
 
         let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
         let res = vm.set_kvm_memory_regions(&gm, false);
         assert!(res.is_ok());
 
+        // Read-only regions are registered as read-only memory slots. KVM doesn't allow
+        // changing that flag on an existing slot, so use a fresh VM.
+        let mut vm = Vm::new(kvm_context.fd()).expect("Cannot create new vm");
+        let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        gm.find_region(GuestAddress(0))
+            .unwrap()
+            .set_protection(libc::PROT_READ)
+            .unwrap();
+        let res = vm.set_kvm_memory_regions(&gm, false);
+        assert!(res.is_ok());
+
         // Trying to set a memory region with a size that is not a multiple of PAGE_SIZE
-        // will result in error.
+        // will result in error. The region would overlap the slot above, so use a fresh VM.
+        let mut vm = Vm::new(kvm_context.fd()).expect("Cannot create new vm");
         let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10)]).unwrap();
         let res = vm.set_kvm_memory_regions(&gm, false);
         assert_eq!(
             res.unwrap_err().to_string(),
             "Cannot set the memory regions: Invalid argument (os error 22)"
         );
+        // The refused region shouldn't take up a memory slot.
+        assert!(vm.memslots().slot(GuestAddress(0)).is_err());
+    }
+
+    #[test]
+    fn test_add_remove_memory_region() {
+        let (mut vm, _gm) = setup_vm(0x1000);
+        let extra = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x1000), 0x1000)]).unwrap();
+        let region = extra.find_region(GuestAddress(0x1000)).unwrap();
+
+        vm.add_memory_region(region).unwrap();
+        assert_eq!(vm.memslots().slot(GuestAddress(0x1000)).unwrap(), 1);
+        // A region can't be mapped twice.
+        assert!(vm.add_memory_region(region).is_err());
+
+        vm.set_dirty_page_tracking(true).unwrap();
+        vm.remove_memory_region(GuestAddress(0x1000)).unwrap();
+        assert!(vm.memslots().slot(GuestAddress(0x1000)).is_err());
+        assert!(vm.remove_memory_region(GuestAddress(0x1000)).is_err());
+
+        // The freed slot gets reused.
+        vm.add_memory_region(region).unwrap();
+        assert_eq!(vm.memslots().slot(GuestAddress(0x1000)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_set_region_protection() {
+        let (mut vm, gm) = setup_vm(0x1000);
+        let region = gm.find_region(GuestAddress(0)).unwrap();
+
+        vm.set_region_protection(region, libc::PROT_READ).unwrap();
+        assert_eq!(region.protection(), libc::PROT_READ);
+        assert_eq!(vm.memslots().slot(GuestAddress(0)).unwrap(), 0);
+        vm.set_region_protection(region, libc::PROT_READ | libc::PROT_WRITE)
+            .unwrap();
+        assert_eq!(region.protection(), libc::PROT_READ | libc::PROT_WRITE);
+
+        // Invalid protection flags leave the region mapped, with its previous protection.
+        let err = vm.set_region_protection(region, -1).unwrap_err();
+        assert!(matches!(err, Error::SetMemoryProtection(_)));
+        assert_eq!(region.protection(), libc::PROT_READ | libc::PROT_WRITE);
+        assert_eq!(vm.memslots().slot(GuestAddress(0)).unwrap(), 0);
+
+        // Only mapped regions can be changed.
+        let extra = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x1000), 0x1000)]).unwrap();
+        let err = vm
+            .set_region_protection(
+                extra.find_region(GuestAddress(0x1000)).unwrap(),
+                libc::PROT_READ,
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::MemSlots(_)));
     }
 }
@@ -33,6 +33,7 @@ def test_flush_metrics(test_microvm_with_api):
         'balloon',
         'block',
         'get_api_requests',
+        'guest_memory',
         'i8042',
         'latencies_us',
         'logger',